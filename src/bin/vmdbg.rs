@@ -0,0 +1,69 @@
+use std::fs;
+use std::fs::File;
+use std::thread;
+
+use virtual_machine::lib::chip_util::BlockingLock;
+use virtual_machine::lib::debug::history::History;
+use virtual_machine::lib::debug::repl;
+use virtual_machine::lib::debug::repl::format_stop;
+use virtual_machine::lib::debug::symbols::Symbols;
+use virtual_machine::lib::debug::trace::ExecutionTracer;
+use virtual_machine::lib::machine::machine::Machine;
+use virtual_machine::lib::rtc::rtc::{RTC, RTCClock};
+use virtual_machine::lib::timer::timer::Timer;
+
+// boots a bare machine (timer and clock only) with a program image and debugs it
+//
+// vmdbg <image> [load address, default 0x1000'0000]
+// symbols are read from <image>.sym when it exists
+// vmdbg --print-trace <binary execution trace>
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: vmdbg <image> [load address]\n       vmdbg --print-trace <trace>");
+        return;
+    }
+    if args[1] == "--print-trace" {
+        let file = args.get(2).map(File::open);
+        if file.as_ref().is_none_or(|f| f.is_err()) {
+            eprintln!("can't read trace {}", args.get(2).map_or("", |a| a.as_str()));
+            return;
+        }
+        let res = ExecutionTracer::pretty_print(&mut file.unwrap().unwrap(), &mut std::io::stdout().lock());
+        if res.is_err() { eprintln!("invalid trace: {:X}", res.err().unwrap()); }
+        return;
+    }
+    let image = fs::read(&args[1]);
+    if image.is_err() {
+        eprintln!("can't read {}", args[1]);
+        return;
+    }
+    let address = args.get(2).map(|a| usize::from_str_radix(a.trim_start_matches("0x").replace('\'', "").as_str(), 16));
+    if address.as_ref().is_some_and(|a| a.is_err()) {
+        eprintln!("invalid load address {}", args[2]);
+        return;
+    }
+    let address = address.map_or(0x1000_0000, |a| a.unwrap());
+
+    let mut machine = Machine::new(536_870_912);
+    if machine.load(address, &image.unwrap()).is_err() {
+        eprintln!("image does not fit at {:#010X}", address);
+        return;
+    }
+
+    let symbols = Symbols::load(&format!("{}.sym", args[1])).ok();
+    machine.set_symbols(symbols.clone());
+
+    let bus = machine.bus();
+    bus.b_lock().attach(Box::new(Timer::new("vPIT - Programmable Interval Timer", "vpit-0000-0000-0000", 4))).unwrap();
+    bus.b_lock().attach(Box::new(RTC::new("vRTC - Real Time Clock", "vrtc-0000-0000-0000", RTCClock::Host))).unwrap();
+
+    let debugger = machine.debugger();
+    debugger.interrupt().unwrap();
+    thread::spawn(move || machine.launch());
+    // the program can be stepped backwards right away
+    debugger.record(History::CAPACITY).unwrap();
+
+    print!("{}", format_stop(&debugger.wait().unwrap(), symbols.as_ref()));
+    repl::run(&debugger, symbols, &mut std::io::stdin().lock(), &mut std::io::stdout());
+}
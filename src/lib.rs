@@ -0,0 +1,3 @@
+// the machine and its devices, shared by the VirtualMachine and vmdbg binaries
+#[path = "lib/mod.rs"]
+pub mod lib;
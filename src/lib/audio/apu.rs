@@ -0,0 +1,473 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::lib::audio::sink::AudioSink;
+use crate::lib::audio::wav::WavWriter;
+use crate::lib::bus::bus::Bus;
+use crate::lib::bus::bus_device::BusDevice;
+use crate::lib::bus::device_class::DeviceClass;
+use crate::lib::chip_util::{BlockingLock, combine_to_word};
+use crate::lib::mem::{Byte, Word};
+use crate::lib::ucode::apu_assembly::APUAssembly;
+use crate::lib::ucode::ucode::UCode;
+
+// audio processing unit
+//
+// CHANNELS tone / noise generators with an ADSR envelope each, plus a PCM fifo of
+// unsigned 8 bit samples played back at SAMPLE_RATE; everything is mixed to one
+// 16 bit mono stream. an interrupt is raised when the pcm fifo runs below PCM_LOW_WATER
+//
+// launched, the apu polls the bus and renders against the host clock for live playback;
+// attached (see Bus::attach), commands are applied as they are written and one sample is
+// rendered every CYCLES_PER_SAMPLE bus cycles, so the recorded output only depends on the program
+
+#[derive(PartialEq, Clone, Copy)]
+enum EnvelopePhase {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+struct AudioChannel {
+    waveform: Byte,
+    frequency: Word,
+    volume: Byte,
+
+    attack: Byte,
+    decay: Byte,
+    sustain: Byte,
+    release: Byte,
+
+    phase: EnvelopePhase,
+    level: f32,
+
+    oscillator: f32,
+    lfsr: u16,
+}
+
+impl AudioChannel {
+    fn new() -> Self {
+        AudioChannel {
+            waveform: APU::SQUARE,
+            frequency: 0,
+            volume: 0,
+            attack: 0,
+            decay: 0,
+            sustain: u8::MAX,
+            release: 0,
+            phase: EnvelopePhase::Idle,
+            level: 0.0,
+            oscillator: 0.0,
+            lfsr: 0x7FFF,
+        }
+    }
+
+    // envelope step per sample for a duration given in units of 10 ms
+    fn rate(units: Byte, range: f32) -> f32 {
+        if units == 0 { return range; }
+        range / (units as f32 * APU::SAMPLE_RATE as f32 / 100.0)
+    }
+
+    fn envelope(&mut self) -> f32 {
+        let sustain = self.sustain as f32 / u8::MAX as f32;
+        match self.phase {
+            EnvelopePhase::Idle => self.level = 0.0,
+            EnvelopePhase::Attack => {
+                self.level += AudioChannel::rate(self.attack, 1.0);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.phase = EnvelopePhase::Decay;
+                }
+            }
+            EnvelopePhase::Decay => {
+                self.level -= AudioChannel::rate(self.decay, 1.0 - sustain);
+                if self.level <= sustain {
+                    self.level = sustain;
+                    self.phase = EnvelopePhase::Sustain;
+                }
+            }
+            EnvelopePhase::Sustain => self.level = sustain,
+            EnvelopePhase::Release => {
+                self.level -= AudioChannel::rate(self.release, 1.0);
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.phase = EnvelopePhase::Idle;
+                }
+            }
+        }
+        self.level
+    }
+
+    fn sample(&mut self) -> f32 {
+        let envelope = self.envelope();
+
+        self.oscillator += self.frequency as f32 / APU::SAMPLE_RATE as f32;
+        while self.oscillator >= 1.0 {
+            self.oscillator -= 1.0;
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+        }
+
+        if self.frequency == 0 || envelope == 0.0 { return 0.0; }
+        let raw = match self.waveform {
+            APU::NOISE => if self.lfsr & 0x1 == 0 { 1.0 } else { -1.0 },
+            _ => if self.oscillator < 0.5 { 1.0 } else { -1.0 },
+        };
+        raw * envelope * self.volume as f32 / u8::MAX as f32
+    }
+}
+
+pub struct APU {
+    address: Byte,
+    instruction_buffer: VecDeque<Byte>,
+
+    uuid: String,
+    name: String,
+
+    channels: Vec<AudioChannel>,
+    pcm: VecDeque<Byte>,
+    pcm_low: bool,
+
+    rendered: u64,
+    // wav recording of the attached apu, samples are written in blocks of OUTPUT_BLOCK
+    output: Option<WavWriter>,
+    samples: Vec<i16>,
+    // commands dropped for invalid operands
+    rejected: u64,
+}
+
+impl APU {
+    pub fn new(name: &str, uuid: &str) -> Self {
+        APU {
+            address: 0x0,
+            instruction_buffer: VecDeque::new(),
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            channels: (0..APU::CHANNELS).map(|_| AudioChannel::new()).collect(),
+            pcm: VecDeque::with_capacity(APU::PCM_CAPACITY),
+            pcm_low: true,
+            rendered: 0,
+            output: None,
+            samples: vec![],
+            rejected: 0,
+        }
+    }
+
+    pub const CHANNELS: usize = 4;
+    pub const SAMPLE_RATE: u32 = 44_100;
+    // bus cycles per output sample when rendering against the bus clock
+    pub const CYCLES_PER_SAMPLE: u64 = 100;
+    // 10 ms of output
+    pub const OUTPUT_BLOCK: usize = APU::SAMPLE_RATE as usize / 100;
+
+    pub const PCM_CAPACITY: usize = 4096;
+    pub const PCM_LOW_WATER: usize = 1024;
+
+    pub const SQUARE: Byte = 0x00;
+    pub const NOISE: Byte = 0x01;
+}
+
+impl APU {
+    pub fn launch(&mut self, bus: &Arc<Mutex<Bus>>, sink: &mut AudioSink) {
+        let address = bus.b_lock().register(Box::new(self));
+        if address.is_err() { return self.report(address.err().unwrap()); }
+        self.address = address.unwrap();
+
+        let start_instant = Instant::now();
+
+        loop {
+            let data = {
+                let mut b = bus.b_lock();
+                // unplugged from the bus, stop the device
+                if !b.is_registered(self.address, &self.uuid) { return; }
+                b.poll(self.address)
+            };
+            self.queue_to_buffer(data);
+            self.apply();
+
+            let due = start_instant.elapsed().as_micros() as u64 * APU::SAMPLE_RATE as u64 / 1_000_000;
+            let samples = self.render((due - self.rendered) as usize);
+            let res = sink.write(&samples);
+            // without a working output the device leaves the bus, the machine keeps running
+            if res.is_err() {
+                let _ = bus.b_lock().unplug(self.address);
+                return self.report(res.err().unwrap());
+            }
+
+            if self.pcm_drained() { bus.b_lock().interrupt(self.address); }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    // mixes the next `count` output samples
+    pub fn render(&mut self, count: usize) -> Vec<i16> {
+        let mut out = Vec::with_capacity(count);
+        let scale = 1.0 / (APU::CHANNELS + 1) as f32;
+        for _ in 0..count {
+            let mut mix = 0.0;
+            for c in self.channels.iter_mut() {
+                mix += c.sample();
+            }
+            if let Some(pcm) = self.pcm.pop_front() { mix += (pcm as f32 - 128.0) / 128.0; }
+            out.push((mix * scale * i16::MAX as f32) as i16);
+        }
+        self.rendered += count as u64;
+        out
+    }
+
+    // records the output of the attached apu to a wav file
+    pub fn set_output(&mut self, output: Option<WavWriter>) {
+        self.output = output;
+    }
+
+    pub fn pcm_queued(&self) -> usize {
+        self.pcm.len()
+    }
+
+    // true once per transition of the pcm fifo below the low water mark
+    fn pcm_drained(&mut self) -> bool {
+        let low = self.pcm.len() < APU::PCM_LOW_WATER;
+        let edge = low && !self.pcm_low;
+        self.pcm_low = low;
+        edge
+    }
+
+    fn report(&self, ucode: Byte) {
+        println!("exception code: {:X} raised;\n{}", ucode, self.stack_trace());
+    }
+
+    fn stack_trace(&self) -> String {
+        let mut x = format!("-----------------------\n\
+        address:     {:#04X}\n\
+        pending:     {}\n\
+        pcm    :     {}\n\
+        samples:     {}\n\
+        dropped:     {}\n", self.address, self.instruction_buffer.len(), self.pcm.len(), self.rendered, self.rejected);
+        for (i, c) in self.channels.iter().enumerate() {
+            x += format!("channel {}:   wave {:#04X} freq {} vol {} adsr {}/{}/{}/{} level {:.2}\n",
+                         i, c.waveform, c.frequency, c.volume, c.attack, c.decay, c.sustain, c.release, c.level).as_str();
+        }
+        x
+    }
+
+    fn queue_to_buffer(&mut self, data: Vec<Byte>) {
+        self.instruction_buffer.extend(data);
+    }
+
+    // an instruction is only executed once all of its operands arrived over the bus
+    fn instruction_ready(&self) -> bool {
+        let x = self.instruction_buffer.front();
+        if x.is_none() { return false; }
+        self.instruction_buffer.len() > APUAssembly::operand_size(*x.unwrap())
+    }
+
+    // runs every complete command, a rejected command is dropped whole and counted
+    fn apply(&mut self) {
+        while self.instruction_ready() {
+            let rest = self.instruction_buffer.len() - 1 - APUAssembly::operand_size(*self.instruction_buffer.front().unwrap());
+            if self.execute().is_err() {
+                while self.instruction_buffer.len() > rest { self.instruction_buffer.pop_front(); }
+                self.rejected += 1;
+            }
+        }
+    }
+
+    fn fetch_instruction_byte(&mut self) -> Result<Byte, Byte> {
+        let x = self.instruction_buffer.pop_front();
+        if x.is_none() { return Err(UCode::INVALID_BUFFER_ACCESS); }
+        Ok(x.unwrap())
+    }
+
+    fn fetch_channel(&mut self) -> Result<usize, Byte> {
+        let x = self.fetch_instruction_byte();
+        if x.is_err() { return Err(x.err().unwrap()); }
+        let channel = x.unwrap() as usize;
+        if channel >= self.channels.len() { return Err(UCode::INVALID_AUDIO_CHANNEL); }
+        Ok(channel)
+    }
+
+    fn fetch_operands(&mut self, count: usize) -> Result<Vec<Byte>, Byte> {
+        let mut x = Vec::with_capacity(count);
+        for _ in 0..count {
+            let b = self.fetch_instruction_byte();
+            if b.is_err() { return Err(b.err().unwrap()); }
+            x.push(b.unwrap());
+        }
+        Ok(x)
+    }
+}
+
+impl APU {
+    fn execute(&mut self) -> Result<(), Byte> {
+        let opcode = self.fetch_instruction_byte();
+        if opcode.is_err() { return Err(opcode.err().unwrap()); }
+        let opcode = opcode.unwrap();
+
+        match opcode {
+            APUAssembly::HLT => Ok(()),
+            APUAssembly::SMP => {
+                let x = self.fetch_instruction_byte();
+                if x.is_err() { return Err(x.err().unwrap()); }
+                if self.pcm.len() < APU::PCM_CAPACITY { self.pcm.push_back(x.unwrap()); }
+                Ok(())
+            }
+            APUAssembly::CLR => {
+                self.pcm.clear();
+                Ok(())
+            }
+            APUAssembly::WAV | APUAssembly::FRQ | APUAssembly::VOL | APUAssembly::ENV | APUAssembly::KON | APUAssembly::KOF => {
+                let channel = self.fetch_channel();
+                if channel.is_err() { return Err(channel.err().unwrap()); }
+                let operands = self.fetch_operands(APUAssembly::operand_size(opcode) - 1);
+                if operands.is_err() { return Err(operands.err().unwrap()); }
+                let o = operands.unwrap();
+                let c = &mut self.channels[channel.unwrap()];
+
+                match opcode {
+                    APUAssembly::WAV => {
+                        if o[0] > APU::NOISE { return Err(UCode::INVALID_WAVEFORM); }
+                        c.waveform = o[0];
+                    }
+                    APUAssembly::FRQ => c.frequency = combine_to_word(o[0], o[1]),
+                    APUAssembly::VOL => c.volume = o[0],
+                    APUAssembly::ENV => {
+                        c.attack = o[0];
+                        c.decay = o[1];
+                        c.sustain = o[2];
+                        c.release = o[3];
+                    }
+                    APUAssembly::KON => c.phase = EnvelopePhase::Attack,
+                    _ => if c.phase != EnvelopePhase::Idle { c.phase = EnvelopePhase::Release },
+                }
+                Ok(())
+            }
+            _ => Ok(())
+        }
+    }
+}
+
+impl BusDevice for APU {
+    fn uuid(&self) -> String {
+        self.uuid.to_string()
+    }
+
+    fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn class(&self) -> Byte {
+        DeviceClass::AUDIO
+    }
+
+    fn capabilities(&self) -> Byte {
+        DeviceClass::CAP_INTERRUPTS
+    }
+
+    fn on_write(&mut self, byte: Byte) {
+        self.queue_to_buffer(vec![byte]);
+        self.apply();
+    }
+
+    fn tick(&mut self, cycles: u64) -> bool {
+        if !cycles.is_multiple_of(APU::CYCLES_PER_SAMPLE) { return false; }
+        let sample = self.render(1);
+        self.samples.extend(sample);
+        if self.samples.len() >= APU::OUTPUT_BLOCK {
+            let res = self.output.as_mut().map_or(Ok(()), |o| o.write(&self.samples));
+            // a failing wav file ends the recording, the machine keeps running
+            if res.is_err() { self.output = None; }
+            self.samples.clear();
+        }
+        self.pcm_drained()
+    }
+
+    fn reset(&mut self) {
+        self.instruction_buffer.clear();
+        self.channels = (0..APU::CHANNELS).map(|_| AudioChannel::new()).collect();
+        self.pcm.clear();
+        self.pcm_low = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lib::bus::bus_device::BusDevice;
+    use crate::lib::ucode::apu_assembly::APUAssembly;
+
+    use super::{APU, EnvelopePhase};
+
+    fn send(apu: &mut APU, bytes: &[u8]) {
+        for b in bytes { apu.on_write(*b); }
+    }
+
+    // output sample for a mix of the inputs, each in -1.0..=1.0
+    fn level(mix: f32) -> i16 {
+        (mix * (1.0 / (APU::CHANNELS + 1) as f32) * i16::MAX as f32) as i16
+    }
+
+    #[test]
+    fn mixer_sums_channels_and_pcm() {
+        let mut apu = APU::new("apu", "vapu-0000-0000-0000");
+        assert_eq!(apu.render(2), vec![0, 0]);
+
+        // 441 Hz square at full volume, no envelope ramps
+        send(&mut apu, &[APUAssembly::FRQ, 0, 0x01, 0xB9, APUAssembly::VOL, 0, 0xFF, APUAssembly::KON, 0]);
+        assert_eq!(apu.render(1), vec![level(1.0)]);
+        send(&mut apu, &[APUAssembly::SMP, 0x00, APUAssembly::SMP, 0xC0]);
+        assert_eq!(apu.render(2), vec![0, level(1.5)]);
+        // the second half of the period is negative
+        let samples = apu.render(100);
+        assert_eq!(samples[40], level(1.0));
+        assert_eq!(samples[50], level(-1.0));
+    }
+
+    #[test]
+    fn envelope_runs_attack_decay_sustain_release() {
+        let mut apu = APU::new("apu", "vapu-0000-0000-0000");
+        // attack and release over 10 ms (441 samples), decay to half over 10 ms
+        send(&mut apu, &[APUAssembly::ENV, 0, 1, 1, 0x80, 1, APUAssembly::FRQ, 0, 0x01, 0xB9, APUAssembly::VOL, 0, 0xFF, APUAssembly::KON, 0]);
+        apu.render(220);
+        assert!((apu.channels[0].level - 0.5).abs() < 0.01);
+        apu.render(222);
+        assert!(apu.channels[0].phase == EnvelopePhase::Decay);
+        apu.render(441);
+        assert!(apu.channels[0].phase == EnvelopePhase::Sustain);
+        assert!((apu.channels[0].level - 0x80 as f32 / 255.0).abs() < 0.001);
+
+        send(&mut apu, &[APUAssembly::KOF, 0]);
+        apu.render(200);
+        assert!(apu.channels[0].phase == EnvelopePhase::Release);
+        apu.render(241);
+        assert!(apu.channels[0].phase == EnvelopePhase::Idle);
+        assert!(apu.render(10).iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn pcm_fifo_is_bounded_and_signals_low_water_once() {
+        let mut apu = APU::new("apu", "vapu-0000-0000-0000");
+        for _ in 0..APU::PCM_CAPACITY + 10 { send(&mut apu, &[APUAssembly::SMP, 0x80]); }
+        assert_eq!(apu.pcm_queued(), APU::PCM_CAPACITY);
+        assert!(!apu.pcm_drained());
+
+        apu.render(APU::PCM_CAPACITY - APU::PCM_LOW_WATER);
+        assert!(!apu.pcm_drained());
+        apu.render(1);
+        assert!(apu.pcm_drained());
+        assert!(!apu.pcm_drained());
+        send(&mut apu, &[APUAssembly::CLR]);
+        assert_eq!(apu.pcm_queued(), 0);
+    }
+
+    #[test]
+    fn rejected_commands_are_dropped_whole() {
+        let mut apu = APU::new("apu", "vapu-0000-0000-0000");
+        send(&mut apu, &[APUAssembly::WAV, 0, 0x7, APUAssembly::ENV, 9, 1, 2, 3, 4, APUAssembly::WAV, 1, APU::NOISE]);
+        assert_eq!(apu.rejected, 2);
+        assert!(apu.channels[0].waveform == APU::SQUARE);
+        assert!(apu.channels[1].waveform == APU::NOISE);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod apu;
+pub mod sink;
+pub mod wav;
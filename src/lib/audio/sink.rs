@@ -0,0 +1,42 @@
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+
+use crate::lib::mem::Byte;
+use crate::lib::ucode::ucode::UCode;
+
+// output of a launched apu, samples are rendered against the host clock
+// (headless recordings attach the apu instead, see APU::set_output)
+pub enum AudioSink {
+    // samples are queued to the sdl audio device
+    Sdl(AudioSubsystem, AudioQueue<i16>),
+    // samples are rendered and dropped
+    Null,
+}
+
+impl AudioSink {
+    // the sdl audio queue is bound to the thread that opened it, create the sink inside the apu thread
+    pub fn sdl(sample_rate: u32) -> Result<Self, Byte> {
+        let audio = sdl2::init().and_then(|c| c.audio());
+        if audio.is_err() { return Err(UCode::AUDIO_OUTPUT_FAILURE); }
+        let audio = audio.unwrap();
+
+        let spec = AudioSpecDesired { freq: Some(sample_rate as i32), channels: Some(1), samples: None };
+        let queue = audio.open_queue::<i16, _>(None, &spec);
+        if queue.is_err() { return Err(UCode::AUDIO_OUTPUT_FAILURE); }
+        let queue = queue.unwrap();
+        queue.resume();
+        Ok(AudioSink::Sdl(audio, queue))
+    }
+}
+
+impl AudioSink {
+    pub fn write(&mut self, samples: &[i16]) -> Result<(), Byte> {
+        match self {
+            AudioSink::Sdl(_, q) => {
+                if q.queue_audio(samples).is_err() { return Err(UCode::AUDIO_OUTPUT_FAILURE); }
+                Ok(())
+            }
+            AudioSink::Null => Ok(())
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+
+use crate::lib::mem::Byte;
+use crate::lib::ucode::ucode::UCode;
+
+// 16 bit signed mono pcm RIFF/WAVE writer
+//
+// the header sizes are patched after every write, so the file stays valid
+// even when the machine is killed instead of shut down
+
+pub struct WavWriter {
+    file: File,
+    sample_rate: u32,
+    data_size: u32,
+}
+
+impl WavWriter {
+    pub fn new(path: &str, sample_rate: u32) -> Result<Self, Byte> {
+        let file = File::create(path);
+        if file.is_err() { return Err(UCode::AUDIO_OUTPUT_FAILURE); }
+        let mut w = WavWriter {
+            file: file.unwrap(),
+            sample_rate,
+            data_size: 0,
+        };
+        let res = w.write_header();
+        if res.is_err() { return Err(res.err().unwrap()); }
+        Ok(w)
+    }
+
+    const HEADER_SIZE: u32 = 44;
+    const CHANNELS: u16 = 1;
+    const BITS_PER_SAMPLE: u16 = 16;
+}
+
+impl WavWriter {
+    pub fn write(&mut self, samples: &[i16]) -> Result<(), Byte> {
+        if samples.is_empty() { return Ok(()); }
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        if self.file.write_all(&bytes).is_err() { return Err(UCode::AUDIO_OUTPUT_FAILURE); }
+        self.data_size += bytes.len() as u32;
+        self.write_header()
+    }
+
+    pub fn samples_written(&self) -> u32 {
+        self.data_size / (WavWriter::BITS_PER_SAMPLE / 8) as u32
+    }
+
+    fn write_header(&mut self) -> Result<(), Byte> {
+        let block_align = WavWriter::CHANNELS * WavWriter::BITS_PER_SAMPLE / 8;
+        let mut h: Vec<u8> = Vec::with_capacity(WavWriter::HEADER_SIZE as usize);
+        h.extend_from_slice(b"RIFF");
+        h.extend_from_slice(&(WavWriter::HEADER_SIZE - 8 + self.data_size).to_le_bytes());
+        h.extend_from_slice(b"WAVEfmt ");
+        h.extend_from_slice(&16u32.to_le_bytes());
+        h.extend_from_slice(&1u16.to_le_bytes());
+        h.extend_from_slice(&WavWriter::CHANNELS.to_le_bytes());
+        h.extend_from_slice(&self.sample_rate.to_le_bytes());
+        h.extend_from_slice(&(self.sample_rate * block_align as u32).to_le_bytes());
+        h.extend_from_slice(&block_align.to_le_bytes());
+        h.extend_from_slice(&WavWriter::BITS_PER_SAMPLE.to_le_bytes());
+        h.extend_from_slice(b"data");
+        h.extend_from_slice(&self.data_size.to_le_bytes());
+
+        let res = self.file.seek(SeekFrom::Start(0))
+            .and_then(|_| self.file.write_all(&h))
+            .and_then(|_| self.file.seek(SeekFrom::End(0)));
+        if res.is_err() { return Err(UCode::AUDIO_OUTPUT_FAILURE); }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::WavWriter;
+
+    fn le32(b: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+    }
+
+    #[test]
+    fn header_tracks_the_written_samples() {
+        let path = std::env::temp_dir().join(format!("wav-{}.wav", std::process::id()));
+        let mut w = WavWriter::new(path.to_str().unwrap(), 8_000).unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), 44);
+
+        w.write(&[0, 1, -1]).unwrap();
+        w.write(&[i16::MAX, i16::MIN]).unwrap();
+        assert_eq!(w.samples_written(), 5);
+
+        let b = fs::read(&path).unwrap();
+        assert_eq!(b.len(), 44 + 10);
+        assert_eq!(&b[0..4], b"RIFF");
+        assert_eq!(le32(&b, 4), 36 + 10);
+        assert_eq!(&b[8..16], b"WAVEfmt ");
+        // pcm, mono, 8 kHz, 16 bit
+        assert_eq!(&b[20..24], &[1, 0, 1, 0]);
+        assert_eq!(le32(&b, 24), 8_000);
+        assert_eq!(le32(&b, 28), 16_000);
+        assert_eq!(&b[32..36], &[2, 0, 16, 0]);
+        assert_eq!(&b[36..40], b"data");
+        assert_eq!(le32(&b, 40), 10);
+        let samples: Vec<i16> = b[44..].chunks(2).map(|s| i16::from_le_bytes([s[0], s[1]])).collect();
+        assert_eq!(samples, vec![0, 1, -1, i16::MAX, i16::MIN]);
+        fs::remove_file(path).unwrap();
+    }
+}
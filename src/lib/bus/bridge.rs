@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::lib::bus::bus::Bus;
+use crate::lib::bus::bus_device::BusDevice;
+use crate::lib::bus::device_class::DeviceClass;
+use crate::lib::chip_util::BlockingLock;
+use crate::lib::mem::Byte;
+use crate::lib::ucode::bridge_assembly::BridgeAssembly;
+
+// joins a child bus segment to its parent, see Bus::bridge
+//
+// the child segment is clocked together with the parent through the bridge's tick,
+// interrupts raised on the child are collected and forwarded with the bridge as source.
+// segments must form a tree, the parent is always locked before the child
+
+pub struct Bridge {
+    uuid: String,
+    name: String,
+
+    child: Arc<Mutex<Bus>>,
+    // child devices that raised an interrupt the guest has not collected yet
+    sources: VecDeque<Byte>,
+    response_buffer: VecDeque<Byte>,
+}
+
+impl Bridge {
+    pub fn new(name: &str, uuid: &str, child: Arc<Mutex<Bus>>) -> Self {
+        Bridge {
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            child,
+            sources: VecDeque::new(),
+            response_buffer: VecDeque::new(),
+        }
+    }
+
+    // oldest sources are dropped beyond this
+    pub const MAX_SOURCES: usize = 64;
+}
+
+impl Bridge {
+    pub fn child(&self) -> Arc<Mutex<Bus>> {
+        Arc::clone(&self.child)
+    }
+}
+
+impl BusDevice for Bridge {
+    fn uuid(&self) -> String {
+        self.uuid.to_string()
+    }
+
+    fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn class(&self) -> Byte {
+        DeviceClass::BRIDGE
+    }
+
+    fn capabilities(&self) -> Byte {
+        DeviceClass::CAP_RESPONDS | DeviceClass::CAP_INTERRUPTS
+    }
+
+    fn on_write(&mut self, byte: Byte) {
+        match byte {
+            BridgeAssembly::SRC => {
+                self.response_buffer.push_back(self.sources.len() as Byte);
+                self.response_buffer.extend(self.sources.drain(..));
+            }
+            BridgeAssembly::CNT => {
+                let count = self.child.b_lock().count();
+                self.response_buffer.push_back(count as Byte);
+            }
+            _ => ()
+        }
+    }
+
+    fn on_read(&mut self) -> Option<Byte> {
+        self.response_buffer.pop_front()
+    }
+
+    fn tick(&mut self, _cycles: u64) -> bool {
+        let mut child = self.child.b_lock();
+        child.clock();
+        let mut raised = false;
+        while let Some(a) = child.next_interrupt() {
+            if self.sources.len() >= Bridge::MAX_SOURCES { self.sources.pop_front(); }
+            self.sources.push_back(a);
+            raised = true;
+        }
+        raised
+    }
+
+    fn reset(&mut self) {
+        self.sources.clear();
+        self.response_buffer.clear();
+        self.child.b_lock().reset();
+    }
+}
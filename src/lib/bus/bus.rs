@@ -1,32 +1,132 @@
 use std::borrow::Borrow;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
+
+use crate::lib::bus::bridge::Bridge;
 use crate::lib::bus::bus_device::BusDevice;
-use crate::lib::mem::Byte;
+use crate::lib::bus::device_class::DeviceClass;
+use crate::lib::bus::timing::{BusMaster, BusTiming};
+use crate::lib::bus::trace::{BusTracer, TraceKind};
+use crate::lib::chip_util::BlockingLock;
+use crate::lib::mem::{Byte, DoubleWord, Word};
+use crate::lib::ucode::discovery_assembly::DiscoveryAssembly;
+use crate::lib::ucode::ucode::UCode;
 
 struct BusDeviceInfo {
     uuid: String,
-    name: String
+    name: String,
+    class: Byte,
+    capabilities: Byte,
+}
+
+struct PendingTransaction {
+    id: Word,
+    // raise an interrupt from the device once the response arrived
+    signal: bool,
 }
 
+// cpu => device traffic is queued per device in `buffer` and drained by the device with `poll`,
+// device => cpu traffic goes through `respond`: it completes the oldest pending transaction of the
+// device (see `request`), or is queued per device in `outbound` for `read` when none is pending
+
 pub struct Bus {
     buffer: BTreeMap<Byte, Vec<Byte>>,
+    outbound: BTreeMap<Byte, VecDeque<Byte>>,
+    transactions: BTreeMap<Byte, VecDeque<PendingTransaction>>,
+    // completed transactions by id, with the address of the device that answered
+    responses: BTreeMap<Word, (Byte, Vec<Byte>)>,
+    transaction_pointer: Word,
     devices: BTreeMap<Byte, BusDeviceInfo>,
-    pointer: Byte,
+    handlers: BTreeMap<Byte, Box<dyn BusDevice + Send>>,
+    // child segments by the address of their bridge on this segment
+    bridges: BTreeMap<Byte, Arc<Mutex<Bus>>>,
+
+    discovery_buffer: Vec<Byte>,
+    // (event, address) plug / unplug events not yet read by the guest
+    events: VecDeque<(Byte, Byte)>,
+    unplugged: Vec<Byte>,
+
+    // pending interrupt sources in request order, at most one entry per device
+    interrupts: VecDeque<Byte>,
+    cycles: u64,
+
+    tracer: Option<BusTracer>,
+    timing: Option<BusTiming>,
 }
 
 impl Bus {
     pub fn new() -> Self {
         Bus {
             buffer: BTreeMap::new(),
+            outbound: BTreeMap::new(),
+            transactions: BTreeMap::new(),
+            responses: BTreeMap::new(),
+            transaction_pointer: 0x0,
             devices: BTreeMap::new(),
-            pointer: 0x0,
+            handlers: BTreeMap::new(),
+            bridges: BTreeMap::new(),
+
+            discovery_buffer: vec![],
+            events: VecDeque::new(),
+            unplugged: vec![],
+
+            interrupts: VecDeque::new(),
+            cycles: 0,
+
+            tracer: None,
+            timing: None,
         }
     }
+
+    // reserved address answering DiscoveryAssembly commands, never assigned to a device
+    pub const DISCOVERY: Byte = 0xFF;
+    // reserved interrupt source of the cpu dma controller
+    pub const DMA: Byte = 0xFE;
+
+    // wide device address of the device at 0xDD on this segment: 0xFFFF'FFDD
+    // every byte above the lowest one selects a bridge, starting from the most significant
+    // byte that is not 0xFF: 0xFFFF'BBDD is device 0xDD behind the bridge at 0xBB,
+    // 0xFFB1'B2DD is device 0xDD behind bridge 0xB2 on the segment behind bridge 0xB1
+    pub const LOCAL: DoubleWord = 0xFFFF_FF00;
+
+    pub const PLUGGED: Byte = 0x01;
+    pub const UNPLUGGED: Byte = 0x02;
+    // oldest events are dropped beyond this
+    pub const MAX_EVENTS: usize = 64;
+    // how often await_response looks for the response
+    pub const AWAIT_INTERVAL: Duration = Duration::from_micros(100);
 }
 
 impl Bus {
     pub fn write(&mut self, address: Byte, byte: Byte) {
+        self.write_block(address, &[byte]);
+    }
+
+    // writes several bytes as one transfer, traced as a single record
+    pub fn write_block(&mut self, address: Byte, data: &[Byte]) {
+        self.write_from(BusMaster::CPU, address, data)
+    }
+
+    fn write_from(&mut self, master: BusMaster, address: Byte, data: &[Byte]) {
+        self.trace(TraceKind::Write, master, None, Some(address), data);
+        for b in data {
+            self.deliver(address, *b);
+        }
+    }
+
+    fn deliver(&mut self, address: Byte, byte: Byte) {
+        if address == Bus::DISCOVERY {
+            self.discovery_buffer.push(byte);
+            self.discover();
+            return;
+        }
+        if let Some(h) = self.handlers.get_mut(&address) {
+            h.on_write(byte);
+            return;
+        }
         let mut x = self.buffer.get_mut(address.borrow());
         if x.is_none() { return; }
         x.unwrap().push(byte);
@@ -37,21 +137,642 @@ impl Bus {
         if x.is_none() { return vec![]; }
         let a = x.unwrap().clone();
         self.buffer.insert(address, vec![]);
+        self.trace(TraceKind::Poll, BusMaster::CPU, None, Some(address), &a);
         return a;
     }
 
-    pub fn register(&mut self, device: Box<&dyn BusDevice>) -> Byte {
-        self.buffer.insert(self.pointer, vec![]);
-        self.devices.insert(self.pointer, BusDeviceInfo {uuid: device.uuid(), name: device.uuid()});
-        self.pointer += 1;
-        self.pointer
+    // reads one byte sent by the device, None while it has nothing to send
+    pub fn read(&mut self, address: Byte) -> Option<Byte> {
+        self.read_from(BusMaster::CPU, address)
+    }
+
+    fn read_from(&mut self, master: BusMaster, address: Byte) -> Option<Byte> {
+        let x = match self.outbound.get_mut(&address) {
+            Some(o) if !o.is_empty() => o.pop_front(),
+            _ => self.handlers.get_mut(&address).and_then(|h| h.on_read()),
+        };
+        if let Some(b) = x { self.trace(TraceKind::Read, master, Some(address), None, &[b]); }
+        x
+    }
+
+    // memory mapped access to an attached device, unmapped devices read as 0
+    pub fn read_mapped(&mut self, address: Byte, offset: DoubleWord) -> Byte {
+        let h = self.handlers.get_mut(&address);
+        if h.is_none() { return 0x0; }
+        h.unwrap().mmio_read(offset)
+    }
+
+    pub fn write_mapped(&mut self, address: Byte, offset: DoubleWord, byte: Byte) {
+        let h = self.handlers.get_mut(&address);
+        if h.is_none() { return; }
+        h.unwrap().mmio_write(offset, byte)
+    }
+
+    // sends a request to the device and returns the id of the transaction its response completes;
+    // attached devices answer synchronously, registered devices once their thread calls `respond`
+    pub fn request(&mut self, address: Byte, data: &[Byte], signal: bool) -> Option<Word> {
+        if !self.devices.contains_key(&address) && address != Bus::DISCOVERY { return None; }
+        let id = self.transaction_pointer;
+        self.transaction_pointer = self.transaction_pointer.wrapping_add(1);
+
+        self.transactions.entry(address).or_default().push_back(PendingTransaction { id, signal });
+        self.write_block(address, data);
+
+        if self.handlers.contains_key(&address) {
+            let mut response = vec![];
+            loop {
+                let x = self.handlers.get_mut(&address).unwrap().on_read();
+                if x.is_none() { break; }
+                response.push(x.unwrap());
+            }
+            self.respond(address, &response);
+        }
+        Some(id)
+    }
+
+    // called by the device at address to send data towards the cpu
+    pub fn respond(&mut self, address: Byte, data: &[Byte]) {
+        self.trace(TraceKind::Respond, BusMaster::CPU, Some(address), None, data);
+        let t = self.transactions.get_mut(&address).and_then(|t| t.pop_front());
+        if t.is_none() {
+            self.outbound.entry(address).or_default().extend(data);
+            return;
+        }
+        let t = t.unwrap();
+        self.responses.insert(t.id, (address, data.to_vec()));
+        if t.signal { self.interrupt(address); }
+    }
+
+    // takes the response of a completed transaction, None while it is still pending
+    pub fn response(&mut self, id: Word) -> Option<Vec<Byte>> {
+        self.responses.remove(&id).map(|(_, x)| x)
+    }
+
+    // true while the transaction waits for the response of its device
+    pub fn is_pending(&self, id: Word) -> bool {
+        self.transactions.values().any(|q| q.iter().any(|t| t.id == id))
+    }
+
+    // blocks until the transaction completed, was dropped (unplug, reset) or the timeout ran out;
+    // the bus is unlocked while waiting
+    pub fn await_response(bus: &Arc<Mutex<Bus>>, id: Word, timeout: Duration) -> Option<Vec<Byte>> {
+        let start = Instant::now();
+        loop {
+            {
+                let mut b = bus.b_lock();
+                let x = b.response(id);
+                if x.is_some() { return x; }
+                if !b.is_pending(id) { return None; }
+            }
+            if start.elapsed() >= timeout { return None; }
+            thread::sleep(Bus::AWAIT_INTERVAL);
+        }
+    }
+
+    // registers a device polling the bus from its own thread at the lowest free address,
+    // the guest is notified with a plug event
+    pub fn register(&mut self, device: Box<&dyn BusDevice>) -> Result<Byte, Byte> {
+        let address = (0..Bus::DMA).find(|a| !self.devices.contains_key(a));
+        if address.is_none() { return Err(UCode::BUS_FULL); }
+        let address = address.unwrap();
+
+        self.buffer.insert(address, vec![]);
+        self.devices.insert(address, BusDeviceInfo {
+            uuid: device.uuid(),
+            name: device.name(),
+            class: device.class(),
+            capabilities: device.capabilities(),
+        });
+        self.event(Bus::PLUGGED, address);
+        Ok(address)
+    }
+
+    // attaches a device driven by its BusDevice hooks, no polling thread required
+    pub fn attach(&mut self, device: Box<dyn BusDevice + Send>) -> Result<Byte, Byte> {
+        let address = self.register(Box::new(device.as_ref()))?;
+        self.buffer.remove(&address);
+        self.handlers.insert(address, device);
+        self.devices.get_mut(&address).unwrap().capabilities |= DeviceClass::CAP_ATTACHED;
+        Ok(address)
+    }
+
+    // joins the child segment to this one through a bridge device, returns the bridge address
+    pub fn bridge(&mut self, name: &str, uuid: &str, child: &Arc<Mutex<Bus>>) -> Result<Byte, Byte> {
+        let address = self.attach(Box::new(Bridge::new(name, uuid, Arc::clone(child))))?;
+        self.bridges.insert(address, Arc::clone(child));
+        Ok(address)
+    }
+
+    // first bridge on the way to a wide address and the address on the segment behind it,
+    // no bridge for devices on this segment
+    pub fn next_hop(address: DoubleWord) -> (Option<Byte>, DoubleWord) {
+        for shift in [24, 16, 8] {
+            let b = (address >> shift) as Byte;
+            if b != 0xFF { return (Some(b), address | (0xFF << shift)); }
+        }
+        (None, address)
+    }
+
+    // every segment address on the way to a wide address, the device itself last
+    pub fn route(address: DoubleWord) -> Vec<Byte> {
+        let mut x = vec![];
+        let mut address = address;
+        loop {
+            let (hop, rest) = Bus::next_hop(address);
+            if hop.is_none() {
+                x.push(rest as Byte);
+                return x;
+            }
+            x.push(hop.unwrap());
+            address = rest;
+        }
+    }
+
+    // wide address of a device on the segment behind the bridge, seen from the bridge's segment;
+    // None once there is no bridge byte left
+    pub fn behind(bridge: Byte, address: DoubleWord) -> Option<DoubleWord> {
+        for shift in [8, 16, 24] {
+            if (address >> shift) as Byte == 0xFF { return Some(address & !(0xFF << shift) | (bridge as DoubleWord) << shift); }
+        }
+        None
+    }
+
+    // address on this segment a wide address is reached through (the device itself or the first bridge)
+    pub fn segment_address(address: DoubleWord) -> Byte {
+        let (hop, rest) = Bus::next_hop(address);
+        hop.unwrap_or(rest as Byte)
     }
 
+    // writes to a device on this segment or on a segment behind one or more bridges,
+    // traced on every segment it crosses; UCode::DEVICE_NOT_FOUND when nothing is plugged in at the address
+    pub fn write_wide(&mut self, master: BusMaster, address: DoubleWord, data: &[Byte]) -> Result<(), Byte> {
+        let (hop, rest) = Bus::next_hop(address);
+        if hop.is_none() {
+            if !self.is_present(rest as Byte) { return Err(UCode::DEVICE_NOT_FOUND); }
+            self.write_from(master, rest as Byte, data);
+            return Ok(());
+        }
+        let child = self.bridges.get(&hop.unwrap()).cloned();
+        if child.is_none() { return Err(UCode::DEVICE_NOT_FOUND); }
+        let res = child.unwrap().b_lock().write_wide(master, rest, data);
+        if res.is_ok() { self.trace(TraceKind::Write, master, None, hop, data); }
+        res
+    }
+
+    // None while the device has nothing to send, UCode::DEVICE_NOT_FOUND when nothing is plugged in at the address
+    pub fn read_wide(&mut self, master: BusMaster, address: DoubleWord) -> Result<Option<Byte>, Byte> {
+        let (hop, rest) = Bus::next_hop(address);
+        if hop.is_none() {
+            if !self.is_present(rest as Byte) { return Err(UCode::DEVICE_NOT_FOUND); }
+            return Ok(self.read_from(master, rest as Byte));
+        }
+        let child = self.bridges.get(&hop.unwrap()).cloned();
+        if child.is_none() { return Err(UCode::DEVICE_NOT_FOUND); }
+        let x = child.unwrap().b_lock().read_wide(master, rest);
+        if let Ok(Some(b)) = x { self.trace(TraceKind::Read, master, hop, None, &[b]); }
+        x
+    }
+
+    // a device or the discovery protocol answers at the address
+    fn is_present(&self, address: Byte) -> bool {
+        address == Bus::DISCOVERY || self.devices.contains_key(&address)
+    }
+
+    // removes a device while the machine runs, its address becomes free for reuse;
+    // attached devices are handed back, registered devices stop once `is_registered` turns false
+    pub fn unplug(&mut self, address: Byte) -> Result<Option<Box<dyn BusDevice + Send>>, Byte> {
+        if self.devices.remove(&address).is_none() { return Err(UCode::DEVICE_NOT_FOUND); }
+        self.buffer.remove(&address);
+        self.outbound.remove(&address);
+        self.transactions.remove(&address);
+        self.responses.retain(|_, (a, _)| *a != address);
+        self.bridges.remove(&address);
+        // the address may be handed to the next plugged device, its requests must not be serviced for it
+        self.interrupts.retain(|a| *a != address);
+        self.unplugged.push(address);
+        self.event(Bus::UNPLUGGED, address);
+        Ok(self.handlers.remove(&address))
+    }
+
+    pub fn is_registered(&self, address: Byte, uuid: &str) -> bool {
+        self.devices.get(&address).is_some_and(|d| d.uuid == uuid)
+    }
+
+    // wide addresses unplugged since the last call on this segment and every segment behind it,
+    // used by the cpu to drop stale memory mappings and dma transfers
+    pub fn take_unplugged(&mut self) -> Vec<DoubleWord> {
+        let mut x: Vec<DoubleWord> = self.unplugged.drain(..).map(|a| Bus::LOCAL | a as DoubleWord).collect();
+        for (b, child) in self.bridges.iter() {
+            x.extend(child.b_lock().take_unplugged().into_iter().filter_map(|a| Bus::behind(*b, a)));
+        }
+        x
+    }
+
+    fn event(&mut self, event: Byte, address: Byte) {
+        if self.events.len() >= Bus::MAX_EVENTS { self.events.pop_front(); }
+        self.events.push_back((event, address));
+        self.interrupt(Bus::DISCOVERY);
+    }
+
+    // resets every attached device and drops all pending traffic
+    pub fn reset(&mut self) {
+        for h in self.handlers.values_mut() {
+            h.reset();
+        }
+        for b in self.buffer.values_mut() {
+            b.clear();
+        }
+        self.outbound.clear();
+        self.transactions.clear();
+        self.events.clear();
+        self.responses.clear();
+        self.interrupts.clear();
+    }
+
+    // installs a tracer recording all traffic from now on, None stops tracing; returns the previous tracer
+    pub fn set_tracer(&mut self, tracer: Option<BusTracer>) -> Option<BusTracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    pub fn tracer(&mut self) -> Option<&mut BusTracer> {
+        self.tracer.as_mut()
+    }
+
+    fn trace(&mut self, kind: TraceKind, master: BusMaster, source: Option<Byte>, destination: Option<Byte>, bytes: &[Byte]) {
+        if self.tracer.is_none() { return; }
+        let device = source.or(destination).unwrap();
+        let class = self.devices.get(&device).map_or(DeviceClass::GENERIC, |d| d.class);
+        self.tracer.as_mut().unwrap().record(self.cycles, kind, master, (source, destination), class, bytes);
+    }
+
+    // enables the timing model, None makes every transfer free again; returns the previous model
+    pub fn set_timing(&mut self, timing: Option<BusTiming>) -> Option<BusTiming> {
+        std::mem::replace(&mut self.timing, timing)
+    }
+
+    pub fn timing(&mut self) -> Option<&mut BusTiming> {
+        self.timing.as_mut()
+    }
+
+    // true when the master may transfer during the current cycle, always without a timing model
+    pub fn arbitrate(&mut self, master: BusMaster) -> bool {
+        let cycles = self.cycles;
+        self.timing.as_mut().is_none_or(|t| t.arbitrate(master, cycles))
+    }
+
+    // charges the transfer of `bytes` to or from the device at address against the timing model
+    pub fn occupy(&mut self, master: BusMaster, address: Byte, bytes: usize) {
+        let cycles = self.cycles;
+        if let Some(t) = self.timing.as_mut() { t.occupy(master, address, bytes, cycles); }
+    }
+
+    // queue an interrupt request raised by the device at address, requests of a device that is
+    // still waiting to be serviced are merged into the pending one
+    pub fn interrupt(&mut self, address: Byte) {
+        if !self.interrupts.contains(&address) { self.interrupts.push_back(address); }
+    }
+
+    pub fn next_interrupt(&mut self) -> Option<Byte> {
+        self.interrupts.pop_front()
+    }
+
+    // advance the bus clock by one cpu cycle, ticking every attached device
+    pub fn clock(&mut self) {
+        self.cycles += 1;
+        let mut raised = vec![];
+        for (a, h) in self.handlers.iter_mut() {
+            if h.tick(self.cycles) { raised.push(*a); }
+        }
+        for a in raised {
+            self.interrupt(a);
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn count(&self) -> usize {
+        self.devices.len()
+    }
+
+    // lists the devices of this segment, segments behind bridges are indented below their bridge
     pub fn devices(&self) -> String {
         let mut x = "".to_string();
         for i in self.devices.iter() {
-            x += format!("{:#04X}: {} [{}]\n", i.0, i.1.uuid, i.1.name).as_str();
+            x += format!("{:#04X}: {} [{}] {} {:#010b}\n", i.0, i.1.uuid, i.1.name, DeviceClass::name(i.1.class), i.1.capabilities).as_str();
+            let child = self.bridges.get(i.0);
+            if child.is_none() { continue; }
+            for l in child.unwrap().b_lock().devices().lines() {
+                x += format!("    {}\n", l).as_str();
+            }
         }
         x.to_string()
     }
-}
\ No newline at end of file
+
+    // executes a complete discovery command once all of its operands arrived
+    fn discover(&mut self) {
+        let filter = match self.discovery_buffer[0] {
+            DiscoveryAssembly::EVT => {
+                self.discovery_buffer.clear();
+                let mut x = vec![self.events.len() as Byte];
+                for (e, a) in self.events.drain(..) {
+                    x.extend([e, a]);
+                }
+                self.respond(Bus::DISCOVERY, &x);
+                return;
+            }
+            DiscoveryAssembly::LST => None,
+            DiscoveryAssembly::FND => {
+                if self.discovery_buffer.len() < 2 { return; }
+                Some(self.discovery_buffer[1])
+            }
+            // unknown command, drop it
+            _ => {
+                self.discovery_buffer.clear();
+                return;
+            }
+        };
+        self.discovery_buffer.clear();
+
+        let mut count = 0;
+        let mut table = vec![];
+        for (a, d) in self.devices.iter() {
+            if filter.is_some() && filter.unwrap() != d.class { continue; }
+            let uuid = &d.uuid.as_bytes()[..d.uuid.len().min(u8::MAX as usize)];
+            let name = &d.name.as_bytes()[..d.name.len().min(u8::MAX as usize)];
+            table.extend([*a, d.class, d.capabilities, uuid.len() as Byte]);
+            table.extend(uuid);
+            table.push(name.len() as Byte);
+            table.extend(name);
+            count += 1;
+        }
+        table.insert(0, count);
+        self.respond(Bus::DISCOVERY, &table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::lib::bus::bus_device::BusDevice;
+    use crate::lib::bus::device_class::DeviceClass;
+    use crate::lib::bus::timing::BusMaster;
+    use crate::lib::bus::trace::{BusTracer, TraceKind};
+    use crate::lib::chip_util::BlockingLock;
+    use crate::lib::mem::Byte;
+    use crate::lib::ucode::discovery_assembly::DiscoveryAssembly;
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::Bus;
+
+    // answers every byte written to it
+    struct Echo(VecDeque<Byte>);
+
+    impl BusDevice for Echo {
+        fn uuid(&self) -> String { "echo-0000-0000-0000".to_string() }
+        fn name(&self) -> String { "echo".to_string() }
+        fn on_write(&mut self, byte: Byte) { self.0.push_back(byte); }
+        fn on_read(&mut self) -> Option<Byte> { self.0.pop_front() }
+    }
+
+    // registered device without a thread, the test answers for it
+    struct Silent;
+
+    impl BusDevice for Silent {
+        fn uuid(&self) -> String { "silent-0000-0000-0000".to_string() }
+        fn name(&self) -> String { "silent".to_string() }
+    }
+
+    // counts its hooks, interrupts every third cycle
+    struct Counter(Arc<Mutex<Vec<Byte>>>);
+
+    impl BusDevice for Counter {
+        fn uuid(&self) -> String { "counter-0000-0000-0000".to_string() }
+        fn name(&self) -> String { "counter".to_string() }
+        fn on_write(&mut self, byte: Byte) { self.0.b_lock().push(byte); }
+        fn on_read(&mut self) -> Option<Byte> { self.0.b_lock().pop() }
+        fn tick(&mut self, cycles: u64) -> bool { cycles.is_multiple_of(3) }
+        fn reset(&mut self) { self.0.b_lock().clear(); }
+    }
+
+    #[test]
+    fn attached_devices_are_driven_by_their_hooks() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let mut bus = Bus::new();
+        let address = bus.attach(Box::new(Counter(Arc::clone(&seen)))).unwrap();
+        assert_eq!(bus.next_interrupt(), Some(Bus::DISCOVERY));
+
+        bus.write_block(address, &[0x1, 0x2, 0x3]);
+        assert_eq!(*seen.b_lock(), vec![0x1, 0x2, 0x3]);
+        assert_eq!(bus.read(address), Some(0x3));
+        // nothing is queued for attached devices
+        assert_eq!(bus.poll(address), vec![]);
+
+        for _ in 0..7 { bus.clock(); }
+        assert_eq!(bus.cycles(), 7);
+        // pending interrupts are not repeated
+        assert_eq!(bus.next_interrupt(), Some(address));
+        assert_eq!(bus.next_interrupt(), None);
+
+        bus.reset();
+        assert!(seen.b_lock().is_empty());
+        assert_eq!(bus.read(address), None);
+    }
+
+    #[test]
+    fn attached_devices_answer_requests_at_once() {
+        let mut bus = Bus::new();
+        let address = bus.attach(Box::new(Echo(VecDeque::new()))).unwrap();
+        let id = bus.request(address, &[0x1, 0x2], false).unwrap();
+        assert_eq!(bus.response(id), Some(vec![0x1, 0x2]));
+        assert_eq!(bus.response(id), None);
+        assert_eq!(bus.request(0x42, &[0x1], false), None);
+    }
+
+    #[test]
+    fn responses_complete_transactions_in_request_order() {
+        let bus = Arc::new(Mutex::new(Bus::new()));
+        let address = bus.b_lock().register(Box::new(&Silent)).unwrap();
+        let first = bus.b_lock().request(address, &[0x1], false).unwrap();
+        let second = bus.b_lock().request(address, &[0x2], true).unwrap();
+        assert!(bus.b_lock().is_pending(first));
+        assert_eq!(bus.b_lock().poll(address), vec![0x1, 0x2]);
+
+        let device = Arc::clone(&bus);
+        let t = std::thread::spawn(move || {
+            device.b_lock().respond(address, &[0xA]);
+            device.b_lock().respond(address, &[0xB]);
+            // nothing pending anymore, queued for `read`
+            device.b_lock().respond(address, &[0xC]);
+        });
+        assert_eq!(Bus::await_response(&bus, second, Duration::from_secs(5)), Some(vec![0xB]));
+        assert_eq!(Bus::await_response(&bus, first, Duration::from_secs(5)), Some(vec![0xA]));
+        t.join().unwrap();
+
+        let mut b = bus.b_lock();
+        // only the second transaction asked for an interrupt
+        assert_eq!(b.next_interrupt(), Some(Bus::DISCOVERY));
+        assert_eq!(b.next_interrupt(), Some(address));
+        assert_eq!(b.next_interrupt(), None);
+        assert_eq!(b.read(address), Some(0xC));
+    }
+
+    #[test]
+    fn await_response_gives_up() {
+        let bus = Arc::new(Mutex::new(Bus::new()));
+        let address = bus.b_lock().register(Box::new(&Silent)).unwrap();
+        let id = bus.b_lock().request(address, &[0x1], false).unwrap();
+        assert_eq!(Bus::await_response(&bus, id, Duration::from_millis(5)), None);
+        assert!(bus.b_lock().is_pending(id));
+
+        // dropped with the device, no need to wait for the timeout
+        bus.b_lock().unplug(address).unwrap();
+        assert!(!bus.b_lock().is_pending(id));
+        assert_eq!(Bus::await_response(&bus, id, Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn unplugged_addresses_are_reused_without_stale_traffic() {
+        let mut bus = Bus::new();
+        let echo = bus.attach(Box::new(Echo(VecDeque::new()))).unwrap();
+        let silent = bus.register(Box::new(&Silent)).unwrap();
+        bus.write(silent, 0x1);
+        let answered = bus.request(echo, &[0x2], false).unwrap();
+        bus.interrupt(echo);
+
+        assert!(bus.unplug(echo).unwrap().is_some());
+        assert!(bus.unplug(echo).is_err());
+        assert_eq!(bus.response(answered), None);
+        assert_eq!(bus.take_unplugged(), vec![Bus::LOCAL | echo as u32]);
+
+        // the lowest free address is handed out again
+        let reused = bus.register(Box::new(&Silent)).unwrap();
+        assert_eq!(reused, echo);
+        assert!(bus.is_registered(reused, "silent-0000-0000-0000"));
+        assert_eq!(bus.poll(reused), vec![]);
+        assert_eq!(bus.read(reused), None);
+        assert_eq!(bus.poll(silent), vec![0x1]);
+        assert_eq!(bus.next_interrupt(), Some(Bus::DISCOVERY));
+        assert_eq!(bus.next_interrupt(), None);
+    }
+
+    // the discovery table as (address, class, capabilities, uuid, name)
+    fn entries(x: &[Byte]) -> Vec<(Byte, Byte, Byte, String, String)> {
+        let mut r = vec![];
+        let mut i = 1;
+        for _ in 0..x[0] {
+            let uuid_end = i + 4 + x[i + 3] as usize;
+            let name_end = uuid_end + 1 + x[uuid_end] as usize;
+            r.push((x[i], x[i + 1], x[i + 2],
+                    String::from_utf8(x[i + 4..uuid_end].to_vec()).unwrap(),
+                    String::from_utf8(x[uuid_end + 1..name_end].to_vec()).unwrap()));
+            i = name_end;
+        }
+        assert_eq!(i, x.len());
+        r
+    }
+
+    #[test]
+    fn discovery_enumerates_devices_and_events() {
+        let mut bus = Bus::new();
+        let echo = bus.attach(Box::new(Echo(VecDeque::new()))).unwrap();
+        let silent = bus.register(Box::new(&Silent)).unwrap();
+        let bridge = bus.bridge("child", "brg-0000-0000-0000", &Arc::new(Mutex::new(Bus::new()))).unwrap();
+
+        let id = bus.request(Bus::DISCOVERY, &[DiscoveryAssembly::LST], false).unwrap();
+        let x = entries(&bus.response(id).unwrap());
+        assert_eq!(x.len(), 3);
+        assert_eq!(x[0], (echo, DeviceClass::GENERIC, DeviceClass::CAP_ATTACHED, "echo-0000-0000-0000".to_string(), "echo".to_string()));
+        assert_eq!(x[1], (silent, DeviceClass::GENERIC, 0x0, "silent-0000-0000-0000".to_string(), "silent".to_string()));
+        assert_eq!((x[2].0, x[2].1, x[2].3.as_str()), (bridge, DeviceClass::BRIDGE, "brg-0000-0000-0000"));
+
+        // operands may arrive in separate writes
+        bus.write(Bus::DISCOVERY, DiscoveryAssembly::FND);
+        assert_eq!(bus.read(Bus::DISCOVERY), None);
+        bus.write(Bus::DISCOVERY, DeviceClass::BRIDGE);
+        let mut x = vec![];
+        while let Some(b) = bus.read(Bus::DISCOVERY) { x.push(b); }
+        assert_eq!(entries(&x).iter().map(|e| e.0).collect::<Vec<Byte>>(), vec![bridge]);
+
+        bus.unplug(silent).unwrap();
+        let id = bus.request(Bus::DISCOVERY, &[DiscoveryAssembly::EVT], false).unwrap();
+        assert_eq!(bus.response(id).unwrap(), vec![4,
+                                                   Bus::PLUGGED, echo, Bus::PLUGGED, silent,
+                                                   Bus::PLUGGED, bridge, Bus::UNPLUGGED, silent]);
+        let id = bus.request(Bus::DISCOVERY, &[DiscoveryAssembly::EVT], false).unwrap();
+        assert_eq!(bus.response(id).unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn next_hop_peels_one_bridge_per_segment() {
+        assert_eq!(Bus::next_hop(0xFFFF_FF07), (None, 0xFFFF_FF07));
+        assert_eq!(Bus::next_hop(Bus::LOCAL | 0x3), (None, 0xFFFF_FF03));
+        assert_eq!(Bus::next_hop(0xFFFF_0502), (Some(0x05), 0xFFFF_FF02));
+        assert_eq!(Bus::next_hop(0xFF01_0502), (Some(0x01), 0xFFFF_0502));
+        assert_eq!(Bus::next_hop(0x0001_0502), (Some(0x00), 0xFF01_0502));
+        assert_eq!(Bus::segment_address(0xFFFF_FF07), 0x07);
+        assert_eq!(Bus::segment_address(0xFF01_0502), 0x01);
+        assert_eq!(Bus::route(0xFFFF_FF07), vec![0x07]);
+        assert_eq!(Bus::route(0xFF01_0502), vec![0x01, 0x05, 0x02]);
+        assert_eq!(Bus::behind(0x05, 0xFFFF_FF02), Some(0xFFFF_0502));
+        assert_eq!(Bus::behind(0x01, 0xFFFF_0502), Some(0xFF01_0502));
+        assert_eq!(Bus::behind(0x09, 0x0001_0502), None);
+    }
+
+    #[test]
+    fn wide_io_crosses_nested_bridges() {
+        let inner = Arc::new(Mutex::new(Bus::new()));
+        let device = inner.b_lock().attach(Box::new(Echo(VecDeque::new()))).unwrap();
+        let middle = Arc::new(Mutex::new(Bus::new()));
+        let b1 = middle.b_lock().bridge("inner", "brg-0000-0000-0001", &inner).unwrap();
+        let mut bus = Bus::new();
+        let b0 = bus.bridge("middle", "brg-0000-0000-0000", &middle).unwrap();
+
+        let address = 0xFF00_0000 | (b0 as u32) << 16 | (b1 as u32) << 8 | device as u32;
+        bus.write_wide(BusMaster::CPU, address, &[0xAB, 0xCD]).unwrap();
+        assert_eq!(bus.read_wide(BusMaster::CPU, address), Ok(Some(0xAB)));
+        assert_eq!(bus.read_wide(BusMaster::CPU, address), Ok(Some(0xCD)));
+        assert_eq!(bus.read_wide(BusMaster::CPU, address), Ok(None));
+        // no bridge at the first hop
+        let missing = 0xFF00_0000 | 0x42 << 16 | (b1 as u32) << 8 | device as u32;
+        assert_eq!(bus.write_wide(BusMaster::CPU, missing, &[0x1]), Err(UCode::DEVICE_NOT_FOUND));
+        assert_eq!(bus.read_wide(BusMaster::CPU, missing), Err(UCode::DEVICE_NOT_FOUND));
+        assert_eq!(bus.read_wide(BusMaster::CPU, address), Ok(None));
+        // no device behind the last bridge, nor on this segment
+        assert_eq!(bus.write_wide(BusMaster::CPU, address + 1, &[0x1]), Err(UCode::DEVICE_NOT_FOUND));
+        assert_eq!(bus.read_wide(BusMaster::CPU, Bus::LOCAL | 0x42), Err(UCode::DEVICE_NOT_FOUND));
+    }
+
+    #[test]
+    fn tracer_names_the_master_and_follows_bridges() {
+        let child = Arc::new(Mutex::new(Bus::new()));
+        let device = child.b_lock().attach(Box::new(Echo(VecDeque::new()))).unwrap();
+        let mut bus = Bus::new();
+        let bridge = bus.bridge("child", "brg-0000-0000-0000", &child).unwrap();
+        bus.set_tracer(Some(BusTracer::new()));
+        child.b_lock().set_tracer(Some(BusTracer::new()));
+
+        let address = 0xFFFF_0000 | (bridge as u32) << 8 | device as u32;
+        bus.write_wide(BusMaster::DMA, address, &[0x1, 0x2]).unwrap();
+        assert_eq!(bus.read_wide(BusMaster::CPU, address), Ok(Some(0x1)));
+
+        let x: Vec<_> = bus.tracer().unwrap().records()
+            .map(|r| (r.kind, r.master, r.source, r.destination, r.bytes.clone())).collect();
+        assert_eq!(x, vec![
+            (TraceKind::Write, BusMaster::DMA, None, Some(bridge), vec![0x1, 0x2]),
+            (TraceKind::Read, BusMaster::CPU, Some(bridge), None, vec![0x1]),
+        ]);
+        let f = bus.tracer().unwrap().format();
+        assert!(f.lines().next().unwrap().contains(&format!("dma  -> {:#04X}", bridge)));
+        assert!(f.lines().nth(1).unwrap().contains(&format!("{:#04X} -> cpu", bridge)));
+
+        // the child segment sees the same traffic with the device as endpoint
+        let mut c = child.b_lock();
+        let x: Vec<(BusMaster, Option<Byte>)> = c.tracer().unwrap().records().map(|r| (r.master, r.destination)).collect();
+        assert_eq!(x, vec![(BusMaster::DMA, Some(device)), (BusMaster::CPU, None)]);
+    }
+}
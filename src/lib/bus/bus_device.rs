@@ -1,4 +1,27 @@
+use crate::lib::bus::device_class::DeviceClass;
+use crate::lib::mem::{Byte, DoubleWord};
+
 pub trait BusDevice {
     fn uuid(&self) -> String;
     fn name(&self) -> String;
-}
\ No newline at end of file
+
+    // reported to the guest by the discovery protocol, see DeviceClass
+    fn class(&self) -> Byte { DeviceClass::GENERIC }
+    fn capabilities(&self) -> Byte { 0x0 }
+
+    // the hooks below are only called for devices handed to Bus::attach,
+    // devices registered with Bus::register poll the bus from their own thread instead
+
+    // a byte was written to the device's address
+    fn on_write(&mut self, _byte: Byte) {}
+    // a byte is read from the device's address, None while the device has nothing to send
+    fn on_read(&mut self) -> Option<Byte> { None }
+    // called once per bus clock cycle, returning true raises an interrupt from the device
+    fn tick(&mut self, _cycles: u64) -> bool { false }
+    // return to the power-on state
+    fn reset(&mut self) {}
+
+    // the cpu read / wrote a byte of a memory mapped region routed to the device (see MMIOTable)
+    fn mmio_read(&mut self, _offset: DoubleWord) -> Byte { 0x0 }
+    fn mmio_write(&mut self, _offset: DoubleWord, _byte: Byte) {}
+}
@@ -0,0 +1,40 @@
+use crate::lib::mem::Byte;
+
+pub struct DeviceClass {}
+
+impl DeviceClass {
+    pub const GENERIC: Byte = 0x00;
+    pub const DISPLAY: Byte = 0x01;
+    pub const FRAMEBUFFER: Byte = 0x02;
+    pub const TIMER: Byte = 0x03;
+    pub const CLOCK: Byte = 0x04;
+    pub const AUDIO: Byte = 0x05;
+    pub const STORAGE: Byte = 0x06;
+    pub const NETWORK: Byte = 0x07;
+    pub const BRIDGE: Byte = 0x08;
+
+    // capability bits reported next to the class
+
+    // the device sends data back (see Bus::respond)
+    pub const CAP_RESPONDS: Byte = 0b0000_0001;
+    // the device raises interrupts
+    pub const CAP_INTERRUPTS: Byte = 0b0000_0010;
+    // the device is meant to be memory mapped
+    pub const CAP_MMIO: Byte = 0b0000_0100;
+    // the device is attached and driven by the bus hooks (set by the bus)
+    pub const CAP_ATTACHED: Byte = 0b1000_0000;
+
+    pub fn name(class: Byte) -> &'static str {
+        match class {
+            DeviceClass::DISPLAY => "display",
+            DeviceClass::FRAMEBUFFER => "framebuffer",
+            DeviceClass::TIMER => "timer",
+            DeviceClass::CLOCK => "clock",
+            DeviceClass::AUDIO => "audio",
+            DeviceClass::STORAGE => "storage",
+            DeviceClass::NETWORK => "network",
+            DeviceClass::BRIDGE => "bridge",
+            _ => "generic"
+        }
+    }
+}
@@ -1,2 +1,6 @@
 pub mod bus;
-pub mod bus_device;
\ No newline at end of file
+pub mod bus_device;
+pub mod device_class;
+pub mod trace;
+pub mod timing;
+pub mod bridge;
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+
+use crate::lib::mem::Byte;
+
+// optional timing model of the bus, see Bus::set_timing
+//
+// a transfer to a device occupies the bus for its latency plus one cycle per `bandwidth` bytes,
+// masters asking for the bus while it is occupied (or while the arbitration favours another waiting
+// master) stall and retry on their next cycle. memory mapped accesses are not modelled
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum BusMaster {
+    CPU,
+    DMA,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Arbitration {
+    // the given master wins whenever both are waiting
+    Fixed(BusMaster),
+    // the master that did not own the bus last wins
+    RoundRobin,
+}
+
+#[derive(Clone, Copy)]
+pub struct DeviceTiming {
+    // cycles before the first byte moves
+    pub latency: u64,
+    // bytes moved per cycle
+    pub bandwidth: u64,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct MasterStatistics {
+    pub transfers: u64,
+    pub bytes: u64,
+    pub stalls: u64,
+}
+
+pub struct BusTiming {
+    default: DeviceTiming,
+    devices: BTreeMap<Byte, DeviceTiming>,
+    arbitration: Arbitration,
+
+    busy_until: u64,
+    owner: Option<BusMaster>,
+    // cycle each master last stalled at
+    waiting: BTreeMap<BusMaster, u64>,
+
+    masters: BTreeMap<BusMaster, MasterStatistics>,
+    device_bytes: BTreeMap<Byte, u64>,
+    busy_cycles: u64,
+}
+
+impl BusTiming {
+    pub fn new(latency: u64, bandwidth: u64) -> Self {
+        BusTiming {
+            default: DeviceTiming { latency, bandwidth: bandwidth.max(1) },
+            devices: BTreeMap::new(),
+            arbitration: Arbitration::Fixed(BusMaster::DMA),
+            busy_until: 0,
+            owner: None,
+            waiting: BTreeMap::new(),
+            masters: BTreeMap::new(),
+            device_bytes: BTreeMap::new(),
+            busy_cycles: 0,
+        }
+    }
+}
+
+impl BusTiming {
+    // overrides the default timing for the device at address
+    pub fn set_device(&mut self, address: Byte, latency: u64, bandwidth: u64) {
+        self.devices.insert(address, DeviceTiming { latency, bandwidth: bandwidth.max(1) });
+    }
+
+    pub fn set_arbitration(&mut self, arbitration: Arbitration) {
+        self.arbitration = arbitration;
+    }
+
+    pub fn device(&self, address: Byte) -> DeviceTiming {
+        *self.devices.get(&address).unwrap_or(&self.default)
+    }
+
+    // cycles a transfer of `bytes` to the device occupies the bus for
+    pub fn cost(&self, address: Byte, bytes: usize) -> u64 {
+        let t = self.device(address);
+        t.latency + (bytes as u64).div_ceil(t.bandwidth)
+    }
+
+    // true when the master may use the bus at the given cycle, otherwise the stall is recorded
+    pub fn arbitrate(&mut self, master: BusMaster, cycle: u64) -> bool {
+        // only masters that retried since the previous cycle are still competing
+        self.waiting.retain(|_, c| *c + 1 >= cycle);
+
+        let other = self.waiting.keys().find(|m| **m != master).copied();
+        let yields = other.is_some() && match self.arbitration {
+            Arbitration::Fixed(p) => p == other.unwrap(),
+            Arbitration::RoundRobin => self.owner == Some(master),
+        };
+        if cycle < self.busy_until || yields {
+            self.waiting.insert(master, cycle);
+            self.masters.entry(master).or_default().stalls += 1;
+            return false;
+        }
+        self.waiting.remove(&master);
+        self.owner = Some(master);
+        true
+    }
+
+    // charges a transfer the master performed after winning the arbitration
+    pub fn occupy(&mut self, master: BusMaster, address: Byte, bytes: usize, cycle: u64) {
+        let cost = self.cost(address, bytes);
+        self.busy_until = cycle + cost;
+        self.busy_cycles += cost;
+
+        let s = self.masters.entry(master).or_default();
+        s.transfers += 1;
+        s.bytes += bytes as u64;
+        *self.device_bytes.entry(address).or_default() += bytes as u64;
+    }
+
+    pub fn statistics(&self, master: BusMaster) -> MasterStatistics {
+        self.masters.get(&master).copied().unwrap_or_default()
+    }
+
+    pub fn reset_statistics(&mut self) {
+        self.masters.clear();
+        self.device_bytes.clear();
+        self.busy_cycles = 0;
+    }
+
+    pub fn report(&self, cycles: u64) -> String {
+        let mut x = format!("bus busy {} of {} cycles ({:.1}%)\n",
+                            self.busy_cycles, cycles, self.busy_cycles as f64 * 100.0 / cycles.max(1) as f64);
+        for (m, s) in self.masters.iter() {
+            x += format!("{:<4} {} transfers, {} bytes, {} stall cycles\n", format!("{:?}", m), s.transfers, s.bytes, s.stalls).as_str();
+        }
+        for (a, b) in self.device_bytes.iter() {
+            let t = self.device(*a);
+            x += format!("{:#04X} {} bytes (latency {}, {} bytes/cycle)\n", a, b, t.latency, t.bandwidth).as_str();
+        }
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arbitration, BusMaster, BusTiming};
+
+    // DMA owns the bus at cycle 1 for two cycles, both masters stall on cycle 2 and compete on cycle 3
+    fn contended(arbitration: Arbitration) -> BusTiming {
+        let mut t = BusTiming::new(0, 1);
+        t.set_arbitration(arbitration);
+        assert!(t.arbitrate(BusMaster::DMA, 1));
+        t.occupy(BusMaster::DMA, 0x0, 2, 1);
+        assert!(!t.arbitrate(BusMaster::DMA, 2));
+        assert!(!t.arbitrate(BusMaster::CPU, 2));
+        t
+    }
+
+    #[test]
+    fn fixed_arbitration_favours_its_master() {
+        let mut t = contended(Arbitration::Fixed(BusMaster::DMA));
+        assert!(!t.arbitrate(BusMaster::CPU, 3));
+        assert!(t.arbitrate(BusMaster::DMA, 3));
+        assert_eq!(t.statistics(BusMaster::CPU).stalls, 2);
+        assert_eq!(t.statistics(BusMaster::DMA).stalls, 1);
+
+        let mut t = contended(Arbitration::Fixed(BusMaster::CPU));
+        assert!(!t.arbitrate(BusMaster::DMA, 3));
+        assert!(t.arbitrate(BusMaster::CPU, 3));
+    }
+
+    #[test]
+    fn round_robin_passes_the_bus_on() {
+        let mut t = contended(Arbitration::RoundRobin);
+        assert!(!t.arbitrate(BusMaster::DMA, 3));
+        assert!(t.arbitrate(BusMaster::CPU, 3));
+        t.occupy(BusMaster::CPU, 0x0, 1, 3);
+        assert!(!t.arbitrate(BusMaster::CPU, 4));
+        assert!(t.arbitrate(BusMaster::DMA, 4));
+    }
+
+    #[test]
+    fn masters_that_stopped_retrying_do_not_compete() {
+        let mut t = contended(Arbitration::Fixed(BusMaster::CPU));
+        // the cpu gave up after cycle 2
+        assert!(t.arbitrate(BusMaster::DMA, 4));
+    }
+
+    #[test]
+    fn cost_uses_the_device_timing() {
+        let mut t = BusTiming::new(2, 4);
+        t.set_device(0x3, 1, 1);
+        assert_eq!(t.cost(0x0, 9), 5);
+        assert_eq!(t.cost(0x3, 9), 10);
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::lib::bus::device_class::DeviceClass;
+use crate::lib::bus::timing::BusMaster;
+use crate::lib::mem::Byte;
+use crate::lib::ucode::gpu_assembly::GPUAssembly;
+use crate::lib::ucode::ucode::UCode;
+
+// records the traffic crossing the bus, see Bus::set_tracer
+//
+// every record carries the bus cycle and host time it happened at, the endpoints (None being the
+// bus master, the cpu or the dma controller) and the bytes moved; traffic to devices behind a bridge
+// is recorded on this segment with the bridge as endpoint; bytes written to display devices are decoded as GPUAssembly streams,
+// instructions split over several writes are decoded once all of their operands were sent
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TraceKind {
+    // bus master => device
+    Write,
+    // device drained the bytes the cpu wrote to it
+    Poll,
+    // device => bus master
+    Respond,
+    // the bus master took a byte the device sent
+    Read,
+}
+
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub elapsed: Duration,
+    pub kind: TraceKind,
+    pub source: Option<Byte>,
+    pub destination: Option<Byte>,
+    // the endpoint that is not a device
+    pub master: BusMaster,
+    // class of the device end of the transfer
+    pub class: Byte,
+    pub bytes: Vec<Byte>,
+}
+
+pub struct BusTracer {
+    start: Instant,
+    records: VecDeque<TraceRecord>,
+    capacity: usize,
+    dropped: u64,
+
+    // records are also printed here as they happen
+    live: Option<Box<dyn Write + Send>>,
+    // partial gpu instructions of the live output, per device
+    live_streams: BTreeMap<Byte, Vec<Byte>>,
+}
+
+impl Default for BusTracer {
+    fn default() -> Self {
+        BusTracer::new()
+    }
+}
+
+impl BusTracer {
+    pub fn new() -> Self {
+        BusTracer::with_capacity(BusTracer::CAPACITY)
+    }
+
+    // keeps at most `capacity` records, the oldest are dropped first
+    pub fn with_capacity(capacity: usize) -> Self {
+        BusTracer {
+            start: Instant::now(),
+            records: VecDeque::new(),
+            capacity,
+            dropped: 0,
+            live: None,
+            live_streams: BTreeMap::new(),
+        }
+    }
+
+    pub const CAPACITY: usize = 65_536;
+}
+
+impl BusTracer {
+    // prints every record to `out` as it happens (e.g. stdout or a capture file), None stops it
+    pub fn set_live(&mut self, out: Option<Box<dyn Write + Send>>) {
+        self.live = out;
+        self.live_streams.clear();
+    }
+
+    // `endpoints` are the source and destination device, None for the bus master
+    pub fn record(&mut self, cycle: u64, kind: TraceKind, master: BusMaster, endpoints: (Option<Byte>, Option<Byte>), class: Byte, bytes: &[Byte]) {
+        let (source, destination) = endpoints;
+        if bytes.is_empty() { return; }
+        let r = TraceRecord {
+            cycle,
+            elapsed: self.start.elapsed(),
+            kind,
+            source,
+            destination,
+            master,
+            class,
+            bytes: bytes.to_vec(),
+        };
+        if let Some(live) = self.live.as_mut() {
+            let x = BusTracer::format_record(&r, &mut self.live_streams);
+            let _ = live.write_all(x.as_bytes());
+        }
+
+        if self.capacity == 0 { return; }
+        if self.records.len() >= self.capacity {
+            self.records.pop_front();
+            self.dropped += 1;
+        }
+        self.records.push_back(r);
+    }
+
+    pub fn records(&self) -> impl Iterator<Item=&TraceRecord> {
+        self.records.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.live_streams.clear();
+        self.dropped = 0;
+    }
+
+    // every byte the cpu wrote to the device at address that is still recorded
+    pub fn stream(&self, address: Byte) -> Vec<Byte> {
+        self.records.iter()
+            .filter(|r| r.kind == TraceKind::Write && r.destination == Some(address))
+            .flat_map(|r| r.bytes.iter().copied())
+            .collect()
+    }
+
+    pub fn format(&self) -> String {
+        let mut streams = BTreeMap::new();
+        let mut x = if self.dropped > 0 { format!("... {} older records dropped\n", self.dropped) } else { "".to_string() };
+        for r in self.records.iter() {
+            x += BusTracer::format_record(r, &mut streams).as_str();
+        }
+        x
+    }
+
+    pub fn dump(&self, path: &str) -> Result<(), Byte> {
+        let file = File::create(path);
+        if file.is_err() { return Err(UCode::BUS_TRACE_FAILURE); }
+        if file.unwrap().write_all(self.format().as_bytes()).is_err() { return Err(UCode::BUS_TRACE_FAILURE); }
+        Ok(())
+    }
+
+    // decodes a complete GPUAssembly stream, one instruction per line
+    pub fn decode_gpu(bytes: &[Byte]) -> String {
+        let mut pending = bytes.to_vec();
+        let mut x = BusTracer::decode_gpu_partial(&mut pending);
+        if !pending.is_empty() { x += format!("    {} (incomplete)\n", BusTracer::hex(&pending)).as_str(); }
+        x
+    }
+
+    // decodes the complete instructions at the front of `stream` and removes them
+    fn decode_gpu_partial(stream: &mut Vec<Byte>) -> String {
+        let mut x = "".to_string();
+        let mut i = 0;
+        while i < stream.len() {
+            let size = 1 + GPUAssembly::operand_size(stream[i]);
+            if i + size > stream.len() { break; }
+            let line = format!("    {} {}", GPUAssembly::mnemonic(stream[i]), BusTracer::hex(&stream[i + 1..i + size]));
+            x += line.trim_end();
+            x += "\n";
+            i += size;
+        }
+        stream.drain(..i);
+        x
+    }
+
+    fn format_record(r: &TraceRecord, streams: &mut BTreeMap<Byte, Vec<Byte>>) -> String {
+        let master = if r.master == BusMaster::DMA { "dma " } else { "cpu " };
+        let endpoint = |e: Option<Byte>| e.map_or(master.to_string(), |e| format!("{:#04X}", e));
+        let mut x = format!("{:>10} {:>10}us {:<7} {} -> {} [{}] {}\n",
+                            r.cycle, r.elapsed.as_micros(), format!("{:?}", r.kind),
+                            endpoint(r.source), endpoint(r.destination), r.bytes.len(), BusTracer::hex(&r.bytes));
+
+        if let (TraceKind::Write, DeviceClass::DISPLAY, Some(d)) = (r.kind, r.class, r.destination) {
+            let s = streams.entry(d).or_default();
+            s.extend(&r.bytes);
+            x += BusTracer::decode_gpu_partial(s).as_str();
+        }
+        x
+    }
+
+    fn hex(bytes: &[Byte]) -> String {
+        bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" ")
+    }
+}
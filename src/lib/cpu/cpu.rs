@@ -4,11 +4,34 @@ use std::ptr::addr_of;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
-use crate::{Bus, RAM};
+use crate::lib::bus::bus::Bus;
+use crate::lib::bus::timing::BusMaster;
+use crate::lib::debug::backtrace;
+use crate::lib::debug::coverage::Coverage;
+use crate::lib::debug::format;
+use crate::lib::debug::debugger::{DebugPort, DebugReply, DebugRequest, Debugger, StopReason, WatchKind};
+use crate::lib::debug::history::{History, UndoRecord};
+use crate::lib::debug::profiler::Profiler;
+use crate::lib::debug::symbols::Symbols;
+use crate::lib::debug::trace::ExecutionTracer;
+use crate::lib::dma::dma::DMA;
+use crate::lib::mem::ram::RAM;
 use crate::lib::chip_util::{BlockingLock, combine_to_double_word, combine_to_word};
 use crate::lib::mem::{B, Byte, D, DoubleWord, W, Word};
+use crate::lib::mem::mmio::MMIOTable;
 use crate::lib::ucode::cpu_assembly::CPUAssembly;
-use crate::lib::ucode::gpu_assembly::GPUAssembly;
+use crate::lib::ucode::ucode::UCode;
+
+// register file as seen between two instructions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Registers {
+    pub a: Word,
+    pub x: Word,
+    pub y: Word,
+    pub flags: Byte,
+    pub sp: DoubleWord,
+    pub pc: DoubleWord,
+}
 
 pub struct CPU {
     a_register: Word,
@@ -20,9 +43,25 @@ pub struct CPU {
     instruction_step: u8,
     instruction_step_a_registry: Word,
     instruction_step_a_registry_long: DoubleWord,
+    // wide device address (see Bus::LOCAL)
+    instruction_step_device: DoubleWord,
 
     stack_pointer: DoubleWord,
     program_counter: DoubleWord,
+
+    instruction: Byte,
+    // address the running instruction was fetched from
+    instruction_address: DoubleWord,
+    instruction_finished: bool,
+
+    mmio: MMIOTable,
+    dma: DMA,
+    debug: DebugPort,
+    tracer: Option<ExecutionTracer>,
+    history: History,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    symbols: Option<Symbols>,
 }
 
 /// memory for primitives (ints, chars, floats, ...)
@@ -41,26 +80,58 @@ impl CPU {
             x_register: 0x0,
             y_register: 0x0,
             flag_register: 0x0,
-            stack_pointer: 0x04FF_FFFF,
+            stack_pointer: CPU::STACK_TOP,
             program_counter: 0x1000_0000,
             instruction_step: 0,
             instruction_step_a_registry: 0x0,
             instruction_step_a_registry_long: 0x0,
+            instruction_step_device: 0x0,
+            instruction: CPUAssembly::HLT,
+            instruction_address: 0x1000_0000,
+            instruction_finished: true,
+            mmio: MMIOTable::new(),
+            dma: DMA::new(),
+            debug: DebugPort::new(),
+            tracer: None,
+            history: History::new(),
+            profiler: None,
+            coverage: None,
+            symbols: None,
         }
     }
 
-    const CARRY: usize = 0;
-    const ZERO: usize = 1;
-    const INTERRUPT: usize = 2;
-    const DECIMAL: usize = 3;
-    const BREAK: usize = 4;
-    const OVERFLOW: usize = 6;
-    const NEGATIVE: usize = 7;
+    pub const CARRY: usize = 0;
+    pub const ZERO: usize = 1;
+    pub const INTERRUPT: usize = 2;
+    pub const DECIMAL: usize = 3;
+    pub const BREAK: usize = 4;
+    pub const OVERFLOW: usize = 6;
+    pub const NEGATIVE: usize = 7;
+
+    /// highest stack address, the stack grows down from here
+    pub const STACK_TOP: DoubleWord = 0x04FF_FFFF;
+    /// program region, see the memory map above
+    pub const PROGRAM_START: DoubleWord = 0x1000_0000;
+    pub const PROGRAM_END: DoubleWord = 0x1FFF_FFFF;
+
+    /// interrupt handler address (0 => no handler installed)
+    const INTERRUPT_VECTOR: usize = 0x0FFF_FFFC;
+    /// bus address of the device that raised the interrupt being serviced
+    const INTERRUPT_SOURCE: usize = 0x0FFF_FFFB;
+
+    // route the given address range to an attached bus device instead of RAM
+    pub fn map_region(&mut self, start: DoubleWord, size: DoubleWord, device: Byte) -> Result<(), Byte> {
+        self.mmio.map(start, size, device)
+    }
+
+    pub fn unmap_region(&mut self, device: Byte) {
+        self.mmio.unmap(device)
+    }
 
     fn fetch_byte(&mut self, ram: &mut RAM) -> Result<Byte, Byte> {
         while ram.is_locked() {};
         ram.lock().unwrap();
-        let res = ram.fetch_byte(self.stack_pointer as usize);
+        let res = ram.fetch_byte(self.program_counter as usize);
         ram.unlock().unwrap();
         res
     }
@@ -80,29 +151,57 @@ impl CPU {
     }
 
 
-    fn read_byte(&mut self, ram: &mut RAM, address: usize) -> Result<Byte, Byte> {
+    fn read_byte(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>, address: usize) -> Result<Byte, Byte> {
+        let x = self.peek_byte(ram, bus, address);
+        if let (Ok(b), true) = (x, self.debug.is_watching()) {
+            self.debug.access(address as DoubleWord, WatchKind::Read, self.instruction_address, b, b);
+        }
+        x
+    }
+    // read_byte without triggering watchpoints
+    fn peek_byte(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>, address: usize) -> Result<Byte, Byte> {
+        if let Some((device, offset)) = self.mmio.lookup(address) {
+            return Ok(bus.b_lock().read_mapped(device, offset));
+        }
         while ram.is_locked() {};
         ram.lock().unwrap();
         let res = ram.fetch_byte(address);
         ram.unlock().unwrap();
         res
     }
-    fn read_word(&mut self, ram: &mut RAM, address: usize) -> Result<Word, Byte> {
-        let sig = self.read_byte(ram, address);
+    fn read_word(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>, address: usize) -> Result<Word, Byte> {
+        let sig = self.read_byte(ram, bus, address);
         if sig.is_err() { return Err(sig.err().unwrap()); };
-        let insig = self.read_byte(ram, address + 1);
+        let insig = self.read_byte(ram, bus, address + 1);
         if insig.is_err() { return Err(insig.err().unwrap()); };
         Ok(combine_to_word(sig.unwrap(), insig.unwrap()))
     }
-    fn read_double_word(&mut self, ram: &mut RAM, address: usize) -> Result<DoubleWord, Byte> {
-        let sig = self.read_word(ram, address);
+    fn read_double_word(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>, address: usize) -> Result<DoubleWord, Byte> {
+        let sig = self.read_word(ram, bus, address);
         if sig.is_err() { return Err(sig.err().unwrap()); }
-        let insig = self.read_word(ram, address + 2);
+        let insig = self.read_word(ram, bus, address + 2);
         if insig.is_err() { return Err(insig.err().unwrap()); }
         Ok(combine_to_double_word(sig.unwrap(), insig.unwrap()))
     }
 
-    fn write_byte(&mut self, ram: &mut RAM, address: usize, byte: Byte) -> Result<(), Byte> {
+    fn write_byte(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>, address: usize, byte: Byte) -> Result<(), Byte> {
+        let recording = self.history.is_recording();
+        if !self.debug.is_watching() && !recording { return self.poke_byte(ram, bus, address, byte); }
+        // reading a device window may have side effects, its old value is neither reported nor recorded
+        let mapped = self.mmio.lookup(address).is_some();
+        let old = if mapped { Ok(byte) } else { self.peek_byte(ram, bus, address) };
+        let res = self.poke_byte(ram, bus, address, byte);
+        if res.is_err() { return Err(res.err().unwrap()); }
+        if recording && !mapped { self.history.store(address as DoubleWord, old.unwrap_or(0)); }
+        if self.debug.is_watching() { self.debug.access(address as DoubleWord, WatchKind::Write, self.instruction_address, old.unwrap_or(0), byte); }
+        Ok(())
+    }
+    // write_byte without triggering watchpoints
+    fn poke_byte(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>, address: usize, byte: Byte) -> Result<(), Byte> {
+        if let Some((device, offset)) = self.mmio.lookup(address) {
+            bus.b_lock().write_mapped(device, offset, byte);
+            return Ok(());
+        }
         while ram.is_locked() {};
         ram.lock().unwrap();
         let res = ram.write_byte(address, byte);
@@ -110,101 +209,421 @@ impl CPU {
         if res.is_err() { return Err(res.err().unwrap()); }
         Ok(())
     }
-    fn write_word(&mut self, ram: &mut RAM, address: usize, word: Word) -> Result<(), Byte> {
-        let res = self.write_byte(ram, address, word.significant_byte());
+    fn write_word(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>, address: usize, word: Word) -> Result<(), Byte> {
+        let res = self.write_byte(ram, bus, address, word.significant_byte());
         if res.is_err() { return Err(res.err().unwrap()); }
-        let res2 = self.write_byte(ram, address + 1, word.significant_byte());
+        let res2 = self.write_byte(ram, bus, address + 1, word.insignificant_byte());
         if res2.is_err() { return Err(res2.err().unwrap()); }
         Ok(())
     }
-    fn write_double_word(&mut self, ram: &mut RAM, address: usize, dword: DoubleWord) -> Result<(), Byte> {
-        let res = self.write_word(ram, address, dword.significant_word());
-        let res2 = self.write_word(ram, address + 1, dword.insignificant_word());
+    fn write_double_word(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>, address: usize, dword: DoubleWord) -> Result<(), Byte> {
+        let res = self.write_word(ram, bus, address, dword.significant_word());
+        let res2 = self.write_word(ram, bus, address + 2, dword.insignificant_word());
         if res.is_err() { return Err(res.err().unwrap()); }
         if res2.is_err() { return Err(res2.err().unwrap()); }
         Ok(())
     }
 
+    fn push_word(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>, word: Word) -> Result<(), Byte> {
+        if self.stack_pointer < 2 { return Err(UCode::POINTER_UNDERFLOW_FAILURE); }
+        self.stack_pointer -= 2;
+        self.write_word(ram, bus, self.stack_pointer as usize, word)
+    }
+    fn push_double_word(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>, dword: DoubleWord) -> Result<(), Byte> {
+        if self.stack_pointer < 4 { return Err(UCode::POINTER_UNDERFLOW_FAILURE); }
+        self.stack_pointer -= 4;
+        self.write_double_word(ram, bus, self.stack_pointer as usize, dword)
+    }
+    fn pull_word(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>) -> Result<Word, Byte> {
+        let res = self.read_word(ram, bus, self.stack_pointer as usize);
+        if res.is_ok() { self.stack_pointer += 2; }
+        res
+    }
+    fn pull_double_word(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>) -> Result<DoubleWord, Byte> {
+        let res = self.read_double_word(ram, bus, self.stack_pointer as usize);
+        if res.is_ok() { self.stack_pointer += 4; }
+        res
+    }
+
+    // device operand of an io instruction, a wide address for the instructions reaching other bus segments
+    fn fetch_device(&mut self, ram: &mut RAM, wide: bool) -> Result<DoubleWord, Byte> {
+        if wide { return self.fetch_double_word(ram); }
+        let x = self.fetch_byte(ram);
+        if x.is_err() { return Err(x.err().unwrap()); }
+        self.on_success_byte_fetch();
+        Ok(Bus::LOCAL | x.unwrap() as DoubleWord)
+    }
+
     fn on_success_byte_fetch(&mut self) {
         self.program_counter += 1;
     }
 
-    pub fn launch(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>) {
-        let mut instruction: Byte = CPUAssembly::HLT;
-        let mut finished_instruction: bool = true;
+    // pushes the program counter, records the source device and jumps to the installed handler;
+    // interrupts are dropped while no handler is installed
+    fn service_interrupt(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>, source: Byte) -> Result<(), Byte> {
+        // accesses made while servicing are reported against the interrupted address
+        self.instruction_address = self.program_counter;
+        let vector = self.read_double_word(ram, bus, CPU::INTERRUPT_VECTOR);
+        if vector.is_err() { return Err(vector.err().unwrap()); }
+        let vector = vector.unwrap();
+        if vector == 0x0 { return Ok(()); }
 
-        println!("{}", bus.b_lock().devices());
+        let res = self.write_byte(ram, bus, CPU::INTERRUPT_SOURCE, source);
+        if res.is_err() { return Err(res.err().unwrap()); }
+        let res = self.push_double_word(ram, bus, self.program_counter);
+        if res.is_err() { return Err(res.err().unwrap()); }
 
-        bus.b_lock().write(0x0, GPUAssembly::BVB);
-        bus.b_lock().write(0x0, 0x0);
-        bus.b_lock().write(0x0, 0x0);
-        bus.b_lock().write(0x0, GPUAssembly::VRX);
-        // x
-        bus.b_lock().write(0x0, 0b0000_0000);
-        bus.b_lock().write(0x0, 0b0000_0000);
-        // y
-        bus.b_lock().write(0x0, 0b0000_0000);
-        bus.b_lock().write(0x0, 0b0000_0000);
-        // c
-        bus.b_lock().write(0x0, 0b0000_0000);
-        bus.b_lock().write(0x0, 0b0000_0000);
-        // ax
-        bus.b_lock().write(0x0, 0b0000_0000);
-        bus.b_lock().write(0x0, 0b0000_0000);
-        // ay
-        bus.b_lock().write(0x0, 0b0000_00000);
-        bus.b_lock().write(0x0, 0b0000_0000);
-        // z index
-        bus.b_lock().write(0x0, 0b0000_0000);
-
-        bus.b_lock().write(0x0, GPUAssembly::UVB);
-        bus.b_lock().write(0x0, GPUAssembly::DRW);
+        self.flag_register = self.flag_register.set_bit(CPU::INTERRUPT);
+        self.program_counter = vector;
+        Ok(())
+    }
+
+    pub fn launch(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>) {
+        println!("{}", bus.b_lock().devices());
 
         loop {
-            if finished_instruction {
-                let x = self.fetch_byte(ram);
-                if x.is_ok() {
-                    instruction = x.unwrap();
-                    self.on_success_byte_fetch()
-                } else { self.raise_exception(x.err().unwrap()) }
-            }
-            let res = self.execute(instruction, ram);
-            self.instruction_step += 1;
-            if res.is_ok() { finished_instruction = res.unwrap() } else { self.raise_exception(res.err().unwrap()) }
-            if finished_instruction { self.instruction_step = 0 }
+            // the debugger is only served between two instructions (or while halted on an exception),
+            // halt requests are taken between the steps of an instruction as well
+            if !self.instruction_finished && !self.debug.is_halted() { self.debug_halt_request(); }
+            if self.instruction_finished || self.debug.is_halted() { self.debug_boundary(ram, bus); }
+            let res = self.cycle(ram, bus);
+            if res.is_err() { self.on_exception(ram, res.err().unwrap()) }
+        }
+    }
+
+    // one bus cycle: services a pending interrupt or runs the next step of the current instruction,
+    // fetching a new one once the previous instruction finished
+    pub fn cycle(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>) -> Result<(), Byte> {
+        let irq = {
+            let mut b = bus.b_lock();
+            b.clock();
+            // memory mapped windows of unplugged devices read as open bus from now on
+            for a in b.take_unplugged() {
+                // only devices on this segment can be mapped
+                if Bus::next_hop(a).0.is_none() { self.mmio.unmap(a as Byte); }
+                self.dma.abort(a);
+            }
+            let res = self.dma.step(ram, &mut b);
+            if res.is_err() { return Err(res.err().unwrap()); }
+            if self.instruction_finished && !self.flag_register.is_set_bit(CPU::INTERRUPT) { b.next_interrupt() } else { None }
+        };
+        // servicing takes the whole cycle, the handler starts on the next one
+        if let Some(irq) = irq {
+            self.history.begin(self.registers());
+            let interrupted = self.program_counter;
+            let res = self.service_interrupt(ram, bus, irq);
+            self.history.commit();
+            if let Some(p) = self.profiler.as_mut() {
+                p.cycle(interrupted);
+                if self.program_counter != interrupted { p.interrupt(self.program_counter); }
+            }
+            return res;
+        }
+
+        if self.instruction_finished {
+            self.history.begin(self.registers());
+            self.instruction_address = self.program_counter;
+            let x = self.fetch_byte(ram);
+            if x.is_err() { return Err(x.err().unwrap()); }
+            self.instruction = x.unwrap();
+            self.on_success_byte_fetch()
+        }
+        let res = self.execute(self.instruction, ram, bus);
+        self.instruction_step = self.instruction_step.saturating_add(1);
+        if let Some(p) = self.profiler.as_mut() { p.cycle(self.instruction_address); }
+        if res.is_err() { return Err(res.err().unwrap()); }
+        self.instruction_finished = res.unwrap();
+        if self.instruction_finished {
+            self.instruction_step = 0;
+            self.debug.retire();
+            self.history.commit();
+            if self.tracer.is_some() { self.trace(ram); }
+            if let Some(p) = self.profiler.as_mut() { p.retire(self.instruction_address, self.instruction, self.program_counter); }
+            if let Some(c) = self.coverage.as_mut() { c.retire(self.instruction_address, self.instruction, self.flag_register); }
+        }
+        Ok(())
+    }
+
+    // logs every executed instruction, returns the previous tracer
+    pub fn set_tracer(&mut self, tracer: Option<ExecutionTracer>) -> Option<ExecutionTracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    // profiles the guest program from now on, returns the previous profile
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) -> Option<Profiler> {
+        std::mem::replace(&mut self.profiler, profiler)
+    }
+
+    // collects code coverage from now on, returns the previous counts
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) -> Option<Coverage> {
+        std::mem::replace(&mut self.coverage, coverage)
+    }
+
+    fn trace(&mut self, ram: &mut RAM) {
+        if !self.tracer.as_ref().unwrap().traces(self.instruction_address) {
+            self.tracer.as_mut().unwrap().skip();
+            return;
+        }
+        let size = 1 + CPUAssembly::operand_size(self.instruction);
+        let mut bytes = Vec::with_capacity(size);
+        while ram.is_locked() {};
+        ram.lock().unwrap();
+        for i in 0..size {
+            bytes.push(ram.fetch_byte(self.instruction_address as usize + i).unwrap_or(0));
         }
+        ram.unlock().unwrap();
+        let registers = self.registers();
+        self.tracer.as_mut().unwrap().record(self.instruction_address, &bytes, registers);
+    }
+
+    // with a debugger attached exceptions halt the cpu instead of exiting
+    fn on_exception(&mut self, ram: &mut RAM, ucode: Byte) {
+        if !self.debug.is_attached() { self.raise_exception(ram, ucode) }
+        let registers = self.registers();
+        self.debug.halt(StopReason::Exception(ucode), registers);
     }
 
-    fn raise_exception(&self, ucode: Byte) {
-        println!("exception code: {} raised;\n{}", format!("{:X}", ucode), self.stack_trace());
+    fn raise_exception(&self, ram: &mut RAM, ucode: Byte) {
+        println!("exception code: {} raised;\n{}", format!("{:X}", ucode), self.stack_trace(ram));
         exit(ucode as i32)
     }
 
-    fn stack_trace(&self) -> String {
+    // symbols of the loaded program, used by stack traces
+    pub fn set_symbols(&mut self, symbols: Option<Symbols>) {
+        self.symbols = symbols;
+    }
+
+    fn stack_trace(&self, ram: &mut RAM) -> String {
+        // the instruction that was running, by label and source line
+        let location = self.symbols.as_ref().map_or("".to_string(), |s| {
+            format!("\ninstr  :     {:0>8} {}", format!("{:X}", self.instruction_address), s.describe(self.instruction_address))
+        });
         format!("-----------------------\n\
         a      :     {}\n\
         x      :     {}\n\
         y      :     {}\n\
         flag   :     {}\n\
         program:     {}\n\
-        stack  :     {}",
+        stack  :     {}\n\
+        {}",
                 self.a_register,
                 self.x_register,
                 self.y_register,
                 format!("{:0>8}", format!("{:X}", self.flag_register)),
-                format!("{:0>8}", format!("{:X}", self.program_counter)),
-                format!("{:0>8}", format!("{:X}", self.stack_pointer)))
+                format!("{:0>8}{}", format!("{:X}", self.program_counter), location),
+                format!("{:0>8}", format!("{:X}", self.stack_pointer)),
+                self.dma.stack_trace()) + self.backtrace(ram).as_str()
+    }
+
+    // saved return addresses by label and source line, the top of the stack and the running instruction
+    fn backtrace(&self, ram: &mut RAM) -> String {
+        let stack = CPU::dump(ram, self.stack_pointer, backtrace::extent(self.stack_pointer));
+        let frames = backtrace::unwind(self.stack_pointer, &stack);
+        format!("backtrace:\n{}\
+        stack at {:#010X}:\n{}\
+        code at {:#010X}:\n{}",
+                backtrace::format(self.instruction_address, &frames, self.symbols.as_ref()),
+                self.stack_pointer,
+                format::hexdump(self.stack_pointer, &stack[..stack.len().min(64)]),
+                self.instruction_address,
+                format::hexdump(self.instruction_address, &CPU::dump(ram, self.instruction_address, 16)))
+    }
+
+    // length bytes of ram from address, fewer where ram ends
+    fn dump(ram: &mut RAM, address: DoubleWord, length: usize) -> Vec<Byte> {
+        let mut x = Vec::with_capacity(length);
+        while ram.is_locked() {};
+        ram.lock().unwrap();
+        for i in 0..length {
+            let b = ram.fetch_byte(address as usize + i);
+            if b.is_err() { break; }
+            x.push(b.unwrap());
+        }
+        ram.unlock().unwrap();
+        x
     }
 }
 
 impl CPU {
-    fn execute(&mut self, opcode: Byte, ram: &mut RAM) -> Result<bool, Byte> {
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.a_register,
+            x: self.x_register,
+            y: self.y_register,
+            flags: self.flag_register,
+            sp: self.stack_pointer,
+            pc: self.program_counter,
+        }
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.a_register = registers.a;
+        self.x_register = registers.x;
+        self.y_register = registers.y;
+        self.flag_register = registers.flags;
+        self.stack_pointer = registers.sp;
+        self.program_counter = registers.pc;
+    }
+
+    pub fn debugger(&self) -> Debugger {
+        self.debug.attach()
+    }
+
+    // rewinds the registers and RAM to before the latest recorded instruction
+    fn undo(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>) -> Option<UndoRecord> {
+        let r = self.history.pop()?;
+        for (address, old) in r.memory.iter().rev() {
+            let _ = self.poke_byte(ram, bus, *address as usize, *old);
+        }
+        self.set_registers(r.registers);
+        // an instruction interrupted by an exception is restarted from its first step
+        self.instruction_finished = true;
+        self.instruction_step = 0;
+        Some(r)
+    }
+
+    // halts inside the running instruction when asked to, a cpu waiting for a device would
+    // otherwise never reach the next boundary
+    fn debug_halt_request(&mut self) {
+        let reply = self.debug.take_halt();
+        if reply.is_none() { return; }
+        let registers = self.registers();
+        self.debug.halt(StopReason::Halted, registers);
+        let _ = reply.unwrap().send(DebugReply::Done);
+    }
+
+    // stops on breakpoints and finished steps, then answers every pending debugger request;
+    // blocks for as long as the cpu stays halted
+    fn debug_boundary(&mut self, ram: &mut RAM, bus: &Arc<Mutex<Bus>>) {
+        if self.instruction_finished && !self.debug.is_halted() {
+            if let Some(stop) = self.debug.should_stop(self.program_counter) {
+                let registers = self.registers();
+                self.debug.halt(stop, registers);
+            }
+        }
+
+        loop {
+            let x = self.debug.next_request();
+            if x.is_none() { return; }
+            let (request, reply) = x.unwrap();
+            let x = match request {
+                DebugRequest::Subscribe(s) => {
+                    self.debug.subscribe(s);
+                    DebugReply::Done
+                }
+                DebugRequest::Halt => {
+                    let registers = self.registers();
+                    self.debug.halt(StopReason::Halted, registers);
+                    DebugReply::Done
+                }
+                DebugRequest::Continue => {
+                    self.debug.resume(false);
+                    DebugReply::Done
+                }
+                DebugRequest::Step => {
+                    self.debug.resume(true);
+                    DebugReply::Done
+                }
+                DebugRequest::Registers => DebugReply::Registers(self.registers()),
+                DebugRequest::SetRegisters(r) => {
+                    self.set_registers(r);
+                    DebugReply::Done
+                }
+                DebugRequest::Read(address, length) => {
+                    // the length is not trusted, reading stops at the first address that fails
+                    let mut data = vec![];
+                    let mut failed = None;
+                    for i in 0..length {
+                        let b = self.peek_byte(ram, bus, address as usize + i);
+                        if b.is_err() {
+                            failed = b.err();
+                            break;
+                        }
+                        data.push(b.unwrap());
+                    }
+                    failed.map_or(DebugReply::Memory(data), DebugReply::Failed)
+                }
+                DebugRequest::Write(address, data) => {
+                    let mut failed = None;
+                    for (i, b) in data.iter().enumerate() {
+                        let res = self.poke_byte(ram, bus, address as usize + i, *b);
+                        if res.is_err() {
+                            failed = res.err();
+                            break;
+                        }
+                    }
+                    failed.map_or(DebugReply::Done, DebugReply::Failed)
+                }
+                DebugRequest::Break(address) => {
+                    self.debug.set_breakpoint(address);
+                    DebugReply::Done
+                }
+                DebugRequest::Clear(address) => {
+                    if self.debug.clear_breakpoint(address) { DebugReply::Done } else { DebugReply::Failed(UCode::BREAKPOINT_NOT_FOUND) }
+                }
+                DebugRequest::Breakpoints => DebugReply::Breakpoints(self.debug.breakpoints()),
+                DebugRequest::Watch(w) => {
+                    self.debug.set_watchpoint(w);
+                    DebugReply::Done
+                }
+                DebugRequest::Unwatch(start) => {
+                    if self.debug.clear_watchpoint(start) { DebugReply::Done } else { DebugReply::Failed(UCode::WATCHPOINT_NOT_FOUND) }
+                }
+                DebugRequest::Watchpoints => DebugReply::Watchpoints(self.debug.watchpoints()),
+                DebugRequest::Profile(enabled) => {
+                    self.set_profiler(if enabled { Some(Profiler::new()) } else { None });
+                    DebugReply::Done
+                }
+                DebugRequest::ProfileData => {
+                    self.profiler.as_ref().map_or(DebugReply::Failed(UCode::PROFILER_NOT_RUNNING), |p| DebugReply::Profile(Box::new(p.clone())))
+                }
+                DebugRequest::Cover(enabled) => {
+                    self.set_coverage(if enabled { Some(Coverage::new()) } else { None });
+                    DebugReply::Done
+                }
+                DebugRequest::CoverageData => {
+                    self.coverage.as_ref().map_or(DebugReply::Failed(UCode::COVERAGE_NOT_RUNNING), |c| DebugReply::Coverage(Box::new(c.clone())))
+                }
+                DebugRequest::Record(capacity) => {
+                    self.history.set_capacity(capacity);
+                    DebugReply::Done
+                }
+                DebugRequest::ReverseStep => {
+                    if self.undo(ram, bus).is_none() { DebugReply::Failed(UCode::HISTORY_EMPTY) } else {
+                        let registers = self.registers();
+                        self.debug.halt(StopReason::Step, registers);
+                        DebugReply::Done
+                    }
+                }
+                DebugRequest::ReverseContinue(address) => {
+                    let mut x = self.undo(ram, bus);
+                    if x.is_none() { DebugReply::Failed(UCode::HISTORY_EMPTY) } else {
+                        let reason = loop {
+                            let r = x.unwrap();
+                            if let Some(a) = address.filter(|a| r.writes(*a)) { break StopReason::LastWrite(a); }
+                            if self.debug.is_breakpoint(self.program_counter) { break StopReason::Breakpoint(self.program_counter); }
+                            x = self.undo(ram, bus);
+                            if x.is_none() { break StopReason::HistoryStart; }
+                        };
+                        let registers = self.registers();
+                        self.debug.halt(reason, registers);
+                        DebugReply::Done
+                    }
+                }
+            };
+            let _ = reply.send(x);
+        }
+    }
+}
+
+impl CPU {
+    fn execute(&mut self, opcode: Byte, ram: &mut RAM, bus: &Arc<Mutex<Bus>>) -> Result<bool, Byte> {
         match opcode {
             CPUAssembly::HLT => { Ok(true) }
 
             // TODO remove
             CPUAssembly::STK => {
-                println!("{}", self.stack_trace());
+                println!("{}", self.stack_trace(ram));
                 Ok(true)
             }
 
@@ -274,7 +693,7 @@ impl CPU {
                         if x.is_ok() { self.instruction_step_a_registry_long = x.unwrap() } else { return Err(x.err().unwrap()); }
                     }
                     1 => {
-                        let x = self.write_word(ram, self.instruction_step_a_registry_long as usize, self.a_register);
+                        let x = self.write_word(ram, bus, self.instruction_step_a_registry_long as usize, self.a_register);
                         if x.is_err() { return Err(x.err().unwrap()); }
                     }
                     _ => ()
@@ -288,7 +707,7 @@ impl CPU {
                         if x.is_ok() { self.instruction_step_a_registry_long = x.unwrap() } else { return Err(x.err().unwrap()); }
                     }
                     1 => {
-                        let x = self.write_word(ram, self.instruction_step_a_registry_long as usize, self.x_register);
+                        let x = self.write_word(ram, bus, self.instruction_step_a_registry_long as usize, self.x_register);
                         if x.is_err() { return Err(x.err().unwrap()); }
                     }
                     _ => ()
@@ -302,7 +721,7 @@ impl CPU {
                         if x.is_ok() { self.instruction_step_a_registry_long = x.unwrap() } else { return Err(x.err().unwrap()); }
                     }
                     1 => {
-                        let x = self.write_word(ram, self.instruction_step_a_registry_long as usize, self.y_register);
+                        let x = self.write_word(ram, bus, self.instruction_step_a_registry_long as usize, self.y_register);
                         if x.is_err() { return Err(x.err().unwrap()); }
                     }
                     _ => ()
@@ -313,52 +732,176 @@ impl CPU {
             // TODO add load from memory ? storing is pointless otherwise
 
             CPUAssembly::PSA => {
-                let res = self.write_word(ram, self.stack_pointer as usize, self.a_register);
+                let res = self.push_word(ram, bus, self.a_register);
                 if res.is_err() { return Err(res.err().unwrap()); }
-                self.stack_pointer -= 2;
                 Ok(true)
             }
             CPUAssembly::PSX => {
-                let res = self.write_word(ram, self.stack_pointer as usize, self.x_register);
+                let res = self.push_word(ram, bus, self.x_register);
                 if res.is_err() { return Err(res.err().unwrap()); }
-                self.stack_pointer -= 2;
                 Ok(true)
             }
             CPUAssembly::PSY => {
-                let res = self.write_word(ram, self.stack_pointer as usize, self.y_register);
+                let res = self.push_word(ram, bus, self.y_register);
                 if res.is_err() { return Err(res.err().unwrap()); }
-                self.stack_pointer -= 2;
                 Ok(true)
             }
             CPUAssembly::PSP => {
-                let res = self.write_double_word(ram, self.stack_pointer as usize, self.program_counter);
+                let res = self.push_double_word(ram, bus, self.program_counter);
                 if res.is_err() { return Err(res.err().unwrap()); }
-                self.stack_pointer -= 4;
                 Ok(true)
             }
 
             CPUAssembly::PLA => {
-                let res = self.read_word(ram, self.stack_pointer as usize);
+                let res = self.pull_word(ram, bus);
                 if res.is_err() { return Err(res.err().unwrap()); } else { self.a_register = res.unwrap() }
-                self.stack_pointer += 2;
                 Ok(true)
             }
             CPUAssembly::PLX => {
-                let res = self.read_word(ram, self.stack_pointer as usize);
+                let res = self.pull_word(ram, bus);
                 if res.is_err() { return Err(res.err().unwrap()); } else { self.x_register = res.unwrap() }
-                self.stack_pointer += 2;
                 Ok(true)
             }
             CPUAssembly::PLY => {
-                let res = self.read_word(ram, self.stack_pointer as usize);
+                let res = self.pull_word(ram, bus);
                 if res.is_err() { return Err(res.err().unwrap()); } else { self.y_register = res.unwrap() }
-                self.stack_pointer += 2;
                 Ok(true)
             }
             CPUAssembly::PLP => {
-                let res = self.read_double_word(ram, self.stack_pointer as usize);
+                let res = self.pull_double_word(ram, bus);
                 if res.is_err() { return Err(res.err().unwrap()); } else { self.program_counter = res.unwrap() }
-                self.stack_pointer += 4;
+                Ok(true)
+            }
+
+            CPUAssembly::RTI => {
+                self.program_counter = self.pull_double_word(ram, bus)?;
+                self.flag_register = self.flag_register.unset_bit(CPU::INTERRUPT);
+                Ok(true)
+            }
+            CPUAssembly::SEI => {
+                self.flag_register = self.flag_register.set_bit(CPU::INTERRUPT);
+                Ok(true)
+            }
+            CPUAssembly::CLI => {
+                self.flag_register = self.flag_register.unset_bit(CPU::INTERRUPT);
+                Ok(true)
+            }
+
+            CPUAssembly::OUT | CPUAssembly::OTW | CPUAssembly::OTL => {
+                match self.instruction_step {
+                    0 => {
+                        self.instruction_step_device = self.fetch_device(ram, opcode == CPUAssembly::OTL)?;
+                        Ok(false)
+                    }
+                    // stay on this step until the bus is granted
+                    _ => {
+                        let a = [self.a_register.significant_byte(), self.a_register.insignificant_byte()];
+                        let data = if opcode == CPUAssembly::OTW { &a[..] } else { &a[1..] };
+                        let mut b = bus.b_lock();
+                        if !b.arbitrate(BusMaster::CPU) { return Ok(false); }
+                        let res = b.write_wide(BusMaster::CPU, self.instruction_step_device, data);
+                        if res.is_err() { return Err(res.err().unwrap()); }
+                        b.occupy(BusMaster::CPU, Bus::segment_address(self.instruction_step_device), data.len());
+                        Ok(true)
+                    }
+                }
+            }
+            CPUAssembly::INP | CPUAssembly::INW | CPUAssembly::INL => {
+                match self.instruction_step {
+                    0 => {
+                        self.instruction_step_device = self.fetch_device(ram, opcode == CPUAssembly::INL)?;
+                        self.instruction_step_a_registry = if opcode == CPUAssembly::INW { 2 } else { 1 };
+                        self.a_register = 0x0;
+                        Ok(false)
+                    }
+                    // stay on this step until every byte arrived
+                    _ => {
+                        let mut b = bus.b_lock();
+                        if !b.arbitrate(BusMaster::CPU) { return Ok(false); }
+                        let x = b.read_wide(BusMaster::CPU, self.instruction_step_device);
+                        if x.is_err() { return Err(x.err().unwrap()); }
+                        let x = x.unwrap();
+                        if x.is_none() { return Ok(false); }
+                        b.occupy(BusMaster::CPU, Bus::segment_address(self.instruction_step_device), 1);
+                        self.a_register = (self.a_register << 8) | x.unwrap() as Word;
+                        self.instruction_step_a_registry -= 1;
+                        Ok(self.instruction_step_a_registry == 0)
+                    }
+                }
+            }
+            CPUAssembly::OTB | CPUAssembly::INB | CPUAssembly::OBL | CPUAssembly::IBL => {
+                match self.instruction_step {
+                    0 => {
+                        self.instruction_step_device = self.fetch_device(ram, opcode == CPUAssembly::OBL || opcode == CPUAssembly::IBL)?;
+                        let x = self.fetch_double_word(ram);
+                        if x.is_ok() { self.instruction_step_a_registry_long = x.unwrap() } else { return Err(x.err().unwrap()); }
+                        let x = self.fetch_word(ram);
+                        if x.is_ok() { self.instruction_step_a_registry = x.unwrap() } else { return Err(x.err().unwrap()); }
+                        Ok(self.instruction_step_a_registry == 0)
+                    }
+                    // stay on this step until the bus is granted
+                    _ if opcode == CPUAssembly::OTB || opcode == CPUAssembly::OBL => {
+                        if !bus.b_lock().arbitrate(BusMaster::CPU) { return Ok(false); }
+                        let mut data = Vec::with_capacity(self.instruction_step_a_registry as usize);
+                        for i in 0..self.instruction_step_a_registry as usize {
+                            let x = self.read_byte(ram, bus, self.instruction_step_a_registry_long as usize + i);
+                            if x.is_err() { return Err(x.err().unwrap()); }
+                            data.push(x.unwrap());
+                        }
+                        let mut b = bus.b_lock();
+                        let res = b.write_wide(BusMaster::CPU, self.instruction_step_device, &data);
+                        if res.is_err() { return Err(res.err().unwrap()); }
+                        b.occupy(BusMaster::CPU, Bus::segment_address(self.instruction_step_device), data.len());
+                        Ok(true)
+                    }
+                    // stay on this step until every byte arrived
+                    _ => {
+                        let mut data = vec![];
+                        {
+                            let mut b = bus.b_lock();
+                            if !b.arbitrate(BusMaster::CPU) { return Ok(false); }
+                            while data.len() < self.instruction_step_a_registry as usize {
+                                let x = b.read_wide(BusMaster::CPU, self.instruction_step_device);
+                                if x.is_err() { return Err(x.err().unwrap()); }
+                                let x = x.unwrap();
+                                if x.is_none() { break; }
+                                data.push(x.unwrap());
+                            }
+                            if !data.is_empty() { b.occupy(BusMaster::CPU, Bus::segment_address(self.instruction_step_device), data.len()); }
+                        }
+                        for i in data {
+                            let x = self.write_byte(ram, bus, self.instruction_step_a_registry_long as usize, i);
+                            if x.is_err() { return Err(x.err().unwrap()); }
+                            self.instruction_step_a_registry_long += 1;
+                            self.instruction_step_a_registry -= 1;
+                        }
+                        Ok(self.instruction_step_a_registry == 0)
+                    }
+                }
+            }
+
+            CPUAssembly::DMO | CPUAssembly::DMI | CPUAssembly::DOL | CPUAssembly::DIL => {
+                let channel = self.fetch_byte(ram);
+                if channel.is_ok() { self.on_success_byte_fetch() } else { return Err(channel.err().unwrap()); }
+                let device = self.fetch_device(ram, opcode == CPUAssembly::DOL || opcode == CPUAssembly::DIL);
+                if device.is_err() { return Err(device.err().unwrap()); }
+                let address = self.fetch_double_word(ram);
+                if address.is_err() { return Err(address.err().unwrap()); }
+                let length = self.fetch_word(ram);
+                if length.is_err() { return Err(length.err().unwrap()); }
+
+                let direction = if opcode == CPUAssembly::DMO || opcode == CPUAssembly::DOL { DMA::OUT } else { DMA::IN };
+                let res = self.dma.start(channel.unwrap(), direction, device.unwrap(), address.unwrap(), length.unwrap());
+                if res.is_err() { return Err(res.err().unwrap()); }
+                Ok(true)
+            }
+            CPUAssembly::DMS => {
+                let channel = self.fetch_byte(ram);
+                if channel.is_ok() { self.on_success_byte_fetch() } else { return Err(channel.err().unwrap()); }
+                let x = self.dma.remaining(channel.unwrap());
+                if x.is_err() { return Err(x.err().unwrap()); }
+                self.a_register = x.unwrap();
+                self.flag_register = if self.a_register == 0 { self.flag_register.set_bit(CPU::ZERO) } else { self.flag_register.unset_bit(CPU::ZERO) };
                 Ok(true)
             }
 
@@ -422,7 +965,11 @@ impl CPU {
                         _ => ()
                     }
                     Ok(self.instruction_step >= 1)
-                } else { Ok(true) }
+                } else {
+                    // not taken, continue after the target address
+                    self.program_counter += 4;
+                    Ok(true)
+                }
             }
             CPUAssembly::BNE => {
                 if !self.flag_register.is_set_bit(CPU::ZERO) {
@@ -435,7 +982,11 @@ impl CPU {
                         _ => ()
                     }
                     Ok(self.instruction_step >= 1)
-                } else { Ok(true) }
+                } else {
+                    // not taken, continue after the target address
+                    self.program_counter += 4;
+                    Ok(true)
+                }
             }
 
             CPUAssembly::JMP => {
@@ -491,4 +1042,91 @@ impl CPU {
             _ => Ok(true)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::lib::bus::bus::Bus;
+    use crate::lib::bus::bus_device::BusDevice;
+    use crate::lib::chip_util::BlockingLock;
+    use crate::lib::debug::debugger::StopReason;
+    use crate::lib::mem::Byte;
+    use crate::lib::mem::ram::RAM;
+    use crate::lib::ucode::cpu_assembly::CPUAssembly;
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::CPU;
+
+    struct Echo(VecDeque<Byte>);
+
+    impl BusDevice for Echo {
+        fn uuid(&self) -> String { "echo-0000-0000-0000".to_string() }
+        fn name(&self) -> String { "echo".to_string() }
+        fn on_write(&mut self, byte: Byte) { self.0.push_back(byte); }
+        fn on_read(&mut self) -> Option<Byte> { self.0.pop_front() }
+    }
+
+    // runs the program for at most `cycles` cycles, stops at the first error
+    fn run(program: &[Byte], bus: &Arc<Mutex<Bus>>, cycles: usize) -> (CPU, Result<(), Byte>) {
+        let mut cpu = CPU::new();
+        let mut ram = RAM::new(CPU::PROGRAM_START as usize + 0x100);
+        for (i, b) in program.iter().enumerate() { ram.write_byte(CPU::PROGRAM_START as usize + i, *b).unwrap(); }
+        for _ in 0..cycles {
+            let res = cpu.cycle(&mut ram, bus);
+            if res.is_err() { return (cpu, res); }
+        }
+        (cpu, Ok(()))
+    }
+
+    #[test]
+    fn io_with_missing_devices_raises() {
+        let bus = Arc::new(Mutex::new(Bus::new()));
+        for program in [
+            vec![CPUAssembly::INP, 0x05],
+            vec![CPUAssembly::OUT, 0x05],
+            vec![CPUAssembly::INB, 0x05, 0x10, 0x00, 0x00, 0x00, 0x00, 0x02],
+            vec![CPUAssembly::OTB, 0x05, 0x10, 0x00, 0x00, 0x00, 0x00, 0x02],
+            // no bridge at 0x07
+            vec![CPUAssembly::INL, 0xFF, 0xFF, 0x07, 0x00],
+            vec![CPUAssembly::OTL, 0xFF, 0xFF, 0x07, 0x00],
+        ] {
+            let (_, res) = run(&program, &bus, 16);
+            assert_eq!(res, Err(UCode::DEVICE_NOT_FOUND), "{:02X?}", program);
+        }
+    }
+
+    #[test]
+    fn halt_stops_an_instruction_waiting_for_a_device() {
+        let bus = Arc::new(Mutex::new(Bus::new()));
+        let address = bus.b_lock().attach(Box::new(Echo(VecDeque::new()))).unwrap();
+        let (mut cpu, res) = run(&[CPUAssembly::INP, address], &bus, 16);
+        assert_eq!(res, Ok(()));
+
+        let debugger = cpu.debugger();
+        debugger.interrupt().unwrap();
+        cpu.debug_halt_request();
+        assert!(cpu.debug.is_halted());
+        assert!(!cpu.instruction_finished);
+        let stop = debugger.wait_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!((stop.reason, stop.registers.pc), (StopReason::Halted, CPU::PROGRAM_START + 2));
+    }
+
+    #[test]
+    fn io_waits_for_present_devices() {
+        let bus = Arc::new(Mutex::new(Bus::new()));
+        let address = bus.b_lock().attach(Box::new(Echo(VecDeque::new()))).unwrap();
+        // nothing to read yet, the cpu keeps waiting
+        let (cpu, res) = run(&[CPUAssembly::INP, address], &bus, 16);
+        assert_eq!(res, Ok(()));
+        assert!(!cpu.instruction_finished);
+
+        bus.b_lock().write(address, 0x2A);
+        let (cpu, res) = run(&[CPUAssembly::INP, address], &bus, 16);
+        assert_eq!(res, Ok(()));
+        assert_eq!(cpu.a_register, 0x2A);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::lib::debug::symbols::Symbols;
+use crate::lib::mem::{Byte, DoubleWord, W, D, Word};
+use crate::lib::ucode::ucode::UCode;
+
+// assembles a program image together with its symbols
+//
+//     let mut p = ProgramBuilder::new(0x1000_0000);
+//     p.source("main.s", 1).label("main").op(CPUAssembly::LDA).word(0x1234);
+//     p.source("main.s", 2).label("idle").op(CPUAssembly::JMP).address("idle");
+//     let (image, symbols) = p.build().unwrap();
+//
+// labels may be used before they are defined, they are resolved by `build`
+
+pub struct ProgramBuilder {
+    origin: DoubleWord,
+    bytes: Vec<Byte>,
+    symbols: Symbols,
+    // (offset, label) of addresses to fill in
+    fixups: Vec<(usize, String)>,
+    // source position of the following instructions
+    position: Option<(String, u32)>,
+}
+
+impl ProgramBuilder {
+    pub fn new(origin: DoubleWord) -> Self {
+        ProgramBuilder {
+            origin,
+            bytes: vec![],
+            symbols: Symbols::new(),
+            fixups: vec![],
+            position: None,
+        }
+    }
+}
+
+impl ProgramBuilder {
+    // address the next byte is placed at
+    pub fn here(&self) -> DoubleWord {
+        self.origin + self.bytes.len() as DoubleWord
+    }
+
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        let here = self.here();
+        self.symbols.add_label(here, name);
+        self
+    }
+
+    // the following instructions were written at file:line
+    pub fn source(&mut self, file: &str, line: u32) -> &mut Self {
+        self.position = Some((file.to_string(), line));
+        self
+    }
+
+    pub fn op(&mut self, opcode: Byte) -> &mut Self {
+        let here = self.here();
+        if let Some((file, line)) = self.position.as_ref() {
+            self.symbols.add_line(here, file, *line);
+        }
+        self.byte(opcode)
+    }
+
+    pub fn byte(&mut self, byte: Byte) -> &mut Self {
+        self.bytes.push(byte);
+        self
+    }
+
+    pub fn word(&mut self, word: Word) -> &mut Self {
+        self.bytes.extend([word.significant_byte(), word.insignificant_byte()]);
+        self
+    }
+
+    pub fn double_word(&mut self, double_word: DoubleWord) -> &mut Self {
+        self.word(double_word.significant_word()).word(double_word.insignificant_word())
+    }
+
+    // the address of a label, defined before or after this point
+    pub fn address(&mut self, label: &str) -> &mut Self {
+        self.fixups.push((self.bytes.len(), label.to_string()));
+        self.double_word(0)
+    }
+
+    // named bytes that are not instructions
+    pub fn data(&mut self, name: &str, bytes: &[Byte]) -> &mut Self {
+        let here = self.here();
+        self.symbols.add_data(here, bytes.len() as DoubleWord, name);
+        self.bytes.extend(bytes);
+        self
+    }
+
+    // the image and its symbols, fails with SYMBOL_NOT_FOUND on an undefined label
+    pub fn build(&self) -> Result<(Vec<Byte>, Symbols), Byte> {
+        let mut image = self.bytes.clone();
+        for (offset, label) in self.fixups.iter() {
+            let address = self.symbols.lookup(label);
+            if address.is_none() { return Err(UCode::SYMBOL_NOT_FOUND); }
+            image[*offset..*offset + 4].copy_from_slice(&address.unwrap().to_be_bytes());
+        }
+        Ok((image, self.symbols.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lib::ucode::cpu_assembly::CPUAssembly;
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::ProgramBuilder;
+
+    #[test]
+    fn build_fills_in_labels_defined_before_and_after_use() {
+        let mut p = ProgramBuilder::new(0x1000_0000);
+        p.source("main.s", 1).label("main").op(CPUAssembly::JMP).address("end");
+        p.source("main.s", 2).op(CPUAssembly::JMP).address("main");
+        p.data("message", &[0x68, 0x69]);
+        p.source("main.s", 4).label("end").op(CPUAssembly::HLT);
+        let (image, symbols) = p.build().unwrap();
+
+        assert_eq!(image, vec![
+            CPUAssembly::JMP, 0x10, 0x00, 0x00, 0x0C,
+            CPUAssembly::JMP, 0x10, 0x00, 0x00, 0x00,
+            0x68, 0x69,
+            CPUAssembly::HLT,
+        ]);
+        assert_eq!(symbols.lookup("end"), Some(0x1000_000C));
+        assert_eq!(symbols.lookup("message"), Some(0x1000_000A));
+        assert_eq!(symbols.describe(0x1000_0005), "<main+0x5> main.s:2");
+        // data is not an instruction of the previous line
+        assert!(symbols.source().lookup(0x1000_000A).is_none());
+        assert_eq!(symbols.source().lookup(0x1000_000C).map(|l| l.line), Some(4));
+    }
+
+    #[test]
+    fn build_fails_on_undefined_labels() {
+        let mut p = ProgramBuilder::new(0x1000_0000);
+        p.op(CPUAssembly::JMP).address("nowhere");
+        assert_eq!(p.build().err(), Some(UCode::SYMBOL_NOT_FOUND));
+    }
+}
@@ -0,0 +1,89 @@
+use crate::lib::cpu::cpu::CPU;
+use crate::lib::debug::symbols::Symbols;
+use crate::lib::mem::{Byte, DoubleWord};
+
+// call stack of the guest program, see CPU::stack_trace
+//
+// the cpu keeps no frame pointers, so the stack is scanned from the stack pointer towards its top:
+// every double word pointing into the program region is taken for a program counter saved by PSP or
+// by an interrupt. words pushed by PHA / PHX / PHY may line up to look like one as well
+
+// bytes of the stack scanned at most
+pub const DEPTH: usize = 4096;
+
+// bytes from sp to the top of the stack, at most DEPTH
+pub fn extent(sp: DoubleWord) -> usize {
+    ((CPU::STACK_TOP - sp.min(CPU::STACK_TOP)) as usize + 1).min(DEPTH)
+}
+
+pub struct Frame {
+    // where on the stack the address was found
+    pub slot: DoubleWord,
+    pub address: DoubleWord,
+}
+
+// saved program counters in `stack`, the bytes from sp upwards, innermost first
+pub fn unwind(sp: DoubleWord, stack: &[Byte]) -> Vec<Frame> {
+    let mut x = vec![];
+    let mut at = 0;
+    while at + 4 <= stack.len() {
+        let address = DoubleWord::from_be_bytes([stack[at], stack[at + 1], stack[at + 2], stack[at + 3]]);
+        if !(CPU::PROGRAM_START..=CPU::PROGRAM_END).contains(&address) {
+            at += 1;
+            continue;
+        }
+        x.push(Frame { slot: sp + at as DoubleWord, address });
+        at += 4;
+    }
+    x
+}
+
+// one line per frame, #0 being the instruction at pc
+pub fn format(pc: DoubleWord, frames: &[Frame], symbols: Option<&Symbols>) -> String {
+    let describe = |a: DoubleWord| symbols.map_or("".to_string(), |s| s.describe(a));
+    let mut x = format!("#0  {:#010X}                  {}", pc, describe(pc)).trim_end().to_string() + "\n";
+    for (i, f) in frames.iter().enumerate() {
+        let line = format!("#{:<2} {:#010X} (sp {:#010X})  {}", i + 1, f.address, f.slot, describe(f.address));
+        x += line.trim_end();
+        x += "\n";
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lib::cpu::cpu::CPU;
+    use crate::lib::debug::symbols::Symbols;
+
+    use super::{extent, format, unwind, DEPTH};
+
+    #[test]
+    fn unwind_finds_saved_program_counters() {
+        let sp = CPU::STACK_TOP - 11;
+        // a pushed word, a saved pc one byte off the slot grid, another saved pc
+        let stack = [0x02, 0x34, 0x10, 0x00, 0x00, 0x20, 0x1F, 0xFF, 0xFF, 0xFF, 0x00, 0x01];
+        let frames = unwind(sp, &stack);
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].slot, frames[0].address), (sp + 2, 0x1000_0020));
+        assert_eq!((frames[1].slot, frames[1].address), (sp + 6, 0x1FFF_FFFF));
+        // the trailing bytes are too short for an address
+        assert!(unwind(sp, &stack[..5]).is_empty());
+    }
+
+    #[test]
+    fn extent_stops_at_the_top_of_the_stack() {
+        assert_eq!(extent(CPU::STACK_TOP), 1);
+        assert_eq!(extent(CPU::STACK_TOP - 15), 16);
+        assert_eq!(extent(0x0), DEPTH);
+        assert_eq!(extent(CPU::STACK_TOP + 1), 1);
+    }
+
+    #[test]
+    fn format_names_the_frames() {
+        let mut symbols = Symbols::new();
+        symbols.add_label(0x1000_0000, "main");
+        let frames = unwind(CPU::STACK_TOP - 3, &[0x10, 0x00, 0x00, 0x08]);
+        assert_eq!(format(0x0FFF_FFFF, &frames, Some(&symbols)),
+                   "#0  0x0FFFFFFF\n#1  0x10000008 (sp 0x04FFFFFC)  <main+0x8>\n");
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+
+use crate::lib::cpu::cpu::CPU;
+use crate::lib::debug::disassembler;
+use crate::lib::debug::source::SourceMap;
+use crate::lib::mem::{B, Byte, DoubleWord};
+use crate::lib::ucode::cpu_assembly::CPUAssembly;
+
+// code coverage of the program region, see CPU::set_coverage
+//
+// counts how often every opcode address was executed and how often each BEQ / BNE was taken and not
+// taken. reports decode the program image linearly from its start, so data placed between
+// instructions shows up as (uncovered) instructions too
+
+#[derive(Default, Clone, Copy)]
+pub struct BranchCoverage {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+// executions of a source line and the branches on it
+type LineCoverage = (u64, Vec<Option<BranchCoverage>>);
+
+#[derive(Clone)]
+pub struct Coverage {
+    executed: BTreeMap<DoubleWord, u64>,
+    branches: BTreeMap<DoubleWord, BranchCoverage>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage {
+            executed: BTreeMap::new(),
+            branches: BTreeMap::new(),
+        }
+    }
+}
+
+impl Coverage {
+    // the instruction at address finished with the given flags
+    pub fn retire(&mut self, address: DoubleWord, opcode: Byte, flags: Byte) {
+        if !(CPU::PROGRAM_START..=CPU::PROGRAM_END).contains(&address) { return; }
+        *self.executed.entry(address).or_default() += 1;
+
+        if opcode != CPUAssembly::BEQ && opcode != CPUAssembly::BNE { return; }
+        // branches leave the flags alone, so they still tell which way it went
+        let b = self.branches.entry(address).or_default();
+        if (opcode == CPUAssembly::BEQ) == flags.is_set_bit(CPU::ZERO) { b.taken += 1; } else { b.not_taken += 1; }
+    }
+
+    pub fn executions(&self, address: DoubleWord) -> u64 {
+        *self.executed.get(&address).unwrap_or(&0)
+    }
+
+    pub fn branch(&self, address: DoubleWord) -> Option<BranchCoverage> {
+        self.branches.get(&address).copied()
+    }
+
+    // gcov like listing of the program image at start, one instruction per line
+    pub fn listing(&self, start: DoubleWord, program: &[Byte]) -> String {
+        let mut x = "".to_string();
+        for (address, bytes, text) in Coverage::decode(start, program) {
+            let count = self.executions(address);
+            let count = if count == 0 { "#####".to_string() } else { count.to_string() };
+            let line = format!("{:>10}: {:#010X}  {:<32}  {:<32} {}", count, address, disassembler::hex(bytes), text, self.branch_note(address, bytes[0]));
+            x += line.trim_end();
+            x += "\n";
+        }
+        x
+    }
+
+    // lcov tracefile; without source positions the lines are those of `listing`, written to `listing_name`
+    pub fn lcov(&self, start: DoubleWord, program: &[Byte], source: Option<&SourceMap>, listing_name: &str) -> String {
+        let mut files: BTreeMap<String, BTreeMap<u32, LineCoverage>> = BTreeMap::new();
+        for (i, (address, bytes, _)) in Coverage::decode(start, program).into_iter().enumerate() {
+            let position = source.and_then(|s| s.lookup(address));
+            if source.is_some() && position.is_none() { continue; }
+            let (file, line) = position.map_or((listing_name.to_string(), i as u32 + 1), |p| (p.file.clone(), p.line));
+
+            let l = files.entry(file).or_default().entry(line).or_default();
+            // a source line is as covered as its best covered instruction
+            l.0 = l.0.max(self.executions(address));
+            if bytes[0] == CPUAssembly::BEQ || bytes[0] == CPUAssembly::BNE { l.1.push(self.branch(address)); }
+        }
+
+        let mut x = "TN:\n".to_string();
+        for (file, lines) in files.iter() {
+            x += format!("SF:{}\n", file).as_str();
+            let (mut found, mut hit) = (0, 0);
+            for (line, (_, branches)) in lines.iter() {
+                for (block, b) in branches.iter().enumerate() {
+                    let taken = |c: u64| if b.is_some() { c.to_string() } else { "-".to_string() };
+                    let b0 = b.unwrap_or_default();
+                    x += format!("BRDA:{},{},0,{}\n", line, block, taken(b0.taken)).as_str();
+                    x += format!("BRDA:{},{},1,{}\n", line, block, taken(b0.not_taken)).as_str();
+                    found += 2;
+                    hit += (b0.taken > 0) as u32 + (b0.not_taken > 0) as u32;
+                }
+            }
+            x += format!("BRF:{}\nBRH:{}\n", found, hit).as_str();
+            for (line, (count, _)) in lines.iter() {
+                x += format!("DA:{},{}\n", line, count).as_str();
+            }
+            x += format!("LF:{}\nLH:{}\nend_of_record\n", lines.len(), lines.values().filter(|l| l.0 > 0).count()).as_str();
+        }
+        x
+    }
+
+    pub fn summary(&self, start: DoubleWord, program: &[Byte]) -> String {
+        let decoded = Coverage::decode(start, program);
+        let covered = decoded.iter().filter(|d| self.executions(d.0) > 0).count();
+        let branches: Vec<BranchCoverage> = decoded.iter()
+            .filter(|d| d.1[0] == CPUAssembly::BEQ || d.1[0] == CPUAssembly::BNE)
+            .map(|d| self.branch(d.0).unwrap_or_default())
+            .collect();
+        let directions = branches.iter().map(|b| (b.taken > 0) as usize + (b.not_taken > 0) as usize).sum::<usize>();
+        format!("instructions {}/{} executed, branch directions {}/{} taken\n", covered, decoded.len(), directions, branches.len() * 2)
+    }
+
+    fn branch_note(&self, address: DoubleWord, opcode: Byte) -> String {
+        if opcode != CPUAssembly::BEQ && opcode != CPUAssembly::BNE { return "".to_string(); }
+        let b = self.branch(address).unwrap_or_default();
+        format!("[taken {}, not taken {}]", b.taken, b.not_taken)
+    }
+
+    // (address, bytes, text) of every instruction in the image
+    fn decode(start: DoubleWord, program: &[Byte]) -> Vec<(DoubleWord, &[Byte], String)> {
+        let mut x = vec![];
+        let mut at = 0;
+        while at < program.len() {
+            let decoded = disassembler::instruction(&program[at..]);
+            let (text, size) = decoded.unwrap_or(("(incomplete)".to_string(), program.len() - at));
+            x.push((start + at as DoubleWord, &program[at..at + size], text));
+            at += size;
+        }
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lib::cpu::cpu::CPU;
+    use crate::lib::debug::source::SourceMap;
+    use crate::lib::ucode::cpu_assembly::CPUAssembly;
+
+    use super::Coverage;
+
+    // LDA $0x0001, BEQ $0x1000'0000, HLT, BNE $0x1000'0000
+    const PROGRAM: [u8; 14] = [
+        CPUAssembly::LDA, 0x00, 0x01,
+        CPUAssembly::BEQ, 0x10, 0x00, 0x00, 0x00,
+        CPUAssembly::HLT,
+        CPUAssembly::BNE, 0x10, 0x00, 0x00, 0x00,
+    ];
+    const START: u32 = CPU::PROGRAM_START;
+
+    fn covered() -> Coverage {
+        let mut c = Coverage::new();
+        c.retire(START, CPUAssembly::LDA, 0x0);
+        c.retire(START + 3, CPUAssembly::BEQ, 1 << CPU::ZERO);
+        c.retire(START + 3, CPUAssembly::BEQ, 1 << CPU::ZERO);
+        c
+    }
+
+    #[test]
+    fn lcov_without_source_uses_the_listing() {
+        let c = covered();
+        assert_eq!(c.branch(START + 3).map(|b| (b.taken, b.not_taken)), Some((2, 0)));
+        assert_eq!(c.lcov(START, &PROGRAM, None, "program.lst"), "TN:\nSF:program.lst\n\
+            BRDA:2,0,0,2\nBRDA:2,0,1,0\nBRDA:4,0,0,-\nBRDA:4,0,1,-\nBRF:4\nBRH:1\n\
+            DA:1,1\nDA:2,2\nDA:3,0\nDA:4,0\nLF:4\nLH:2\nend_of_record\n");
+    }
+
+    #[test]
+    fn lcov_merges_instructions_of_a_source_line() {
+        let c = covered();
+        let mut source = SourceMap::new();
+        source.insert(START, "main.s", 5);
+        source.insert(START + 3, "main.s", 5);
+        source.insert(START + 8, "main.s", 7);
+        // the second branch has no position and is left out
+        assert_eq!(c.lcov(START, &PROGRAM, Some(&source), "program.lst"), "TN:\nSF:main.s\n\
+            BRDA:5,0,0,2\nBRDA:5,0,1,0\nBRF:2\nBRH:1\n\
+            DA:5,2\nDA:7,0\nLF:2\nLH:1\nend_of_record\n");
+    }
+
+    #[test]
+    fn addresses_outside_the_program_are_ignored() {
+        let mut c = Coverage::new();
+        c.retire(START - 1, CPUAssembly::BEQ, 0x0);
+        assert_eq!(c.executions(START - 1), 0);
+        assert!(c.branch(START - 1).is_none());
+    }
+}
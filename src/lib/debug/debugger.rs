@@ -0,0 +1,528 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use crate::lib::cpu::cpu::Registers;
+use crate::lib::debug::coverage::Coverage;
+use crate::lib::debug::profiler::Profiler;
+use crate::lib::mem::{Byte, DoubleWord};
+use crate::lib::ucode::ucode::UCode;
+
+// interactive debugging of the cpu
+//
+// a Debugger sends requests to the DebugPort owned by the cpu, the cpu answers them between two
+// instructions only (never in the middle of a multi-step instruction), so registers and memory are
+// always seen in a consistent state. the exception is a halt request, it is also taken between the
+// steps of an instruction so a cpu waiting for a device can be stopped; like an exception it leaves
+// the cpu halted inside the instruction. while halted the cpu blocks on its port; every attached
+// debugger is told when and why the cpu stopped
+
+pub enum DebugRequest {
+    Subscribe(Sender<DebugStop>),
+    Halt,
+    Continue,
+    Step,
+    Registers,
+    SetRegisters(Registers),
+    Read(DoubleWord, usize),
+    Write(DoubleWord, Vec<Byte>),
+    Break(DoubleWord),
+    Clear(DoubleWord),
+    Breakpoints,
+    Watch(Watchpoint),
+    Unwatch(DoubleWord),
+    Watchpoints,
+    // keep the undo log of the last n instructions, 0 stops recording
+    Record(usize),
+    ReverseStep,
+    // runs backwards to a breakpoint or to the last write of the address
+    ReverseContinue(Option<DoubleWord>),
+    // starts a fresh profile (true) or stops profiling
+    Profile(bool),
+    ProfileData,
+    // starts fresh coverage counts (true) or stops collecting them
+    Cover(bool),
+    CoverageData,
+}
+
+pub enum DebugReply {
+    Done,
+    Registers(Registers),
+    Memory(Vec<Byte>),
+    Breakpoints(Vec<DoubleWord>),
+    Watchpoints(Vec<Watchpoint>),
+    Profile(Box<Profiler>),
+    Coverage(Box<Coverage>),
+    Failed(Byte),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    // stopped on request
+    Halted,
+    // one instruction executed
+    Step,
+    Breakpoint(DoubleWord),
+    // the uCode the cpu would have exited with
+    Exception(Byte),
+    // the instruction that touched the watched memory has finished
+    Watchpoint(WatchHit),
+    // ran backwards to just before the instruction that last wrote the address
+    LastWrite(DoubleWord),
+    // ran backwards to the oldest recorded instruction
+    HistoryStart,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchAction {
+    // halt the cpu once the instruction finished, without a debugger attached the hit is logged
+    Break,
+    // print the hit and keep running
+    Log,
+}
+
+// watches `size` bytes from `start`, see CPU::read_byte / CPU::write_byte
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub start: DoubleWord,
+    pub size: DoubleWord,
+    pub kind: WatchKind,
+    pub action: WatchAction,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatchHit {
+    pub address: DoubleWord,
+    // start of the instruction that accessed the memory
+    pub pc: DoubleWord,
+    // Read or Write, never Access
+    pub kind: WatchKind,
+    pub old: Byte,
+    pub new: Byte,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, address: DoubleWord, kind: WatchKind) -> bool {
+        address >= self.start && (address - self.start) < self.size && (self.kind == WatchKind::Access || self.kind == kind)
+    }
+}
+
+impl WatchHit {
+    pub fn format(&self) -> String {
+        let kind = if self.kind == WatchKind::Read { "read" } else { "write" };
+        format!("watch {} {:#010X} by {:#010X}: {:#04X} -> {:#04X}", kind, self.address, self.pc, self.old, self.new)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DebugStop {
+    pub reason: StopReason,
+    pub registers: Registers,
+}
+
+type Request = (DebugRequest, Sender<DebugReply>);
+
+// cpu side of the debugger connection
+pub struct DebugPort {
+    requests: Receiver<Request>,
+    sender: Sender<Request>,
+    // requests that arrived in the middle of an instruction, served on the next boundary
+    deferred: VecDeque<Request>,
+    subscribers: Vec<Sender<DebugStop>>,
+
+    breakpoints: BTreeSet<DoubleWord>,
+    watchpoints: Vec<Watchpoint>,
+    // first breaking hit of the running instruction
+    watch_hit: Option<WatchHit>,
+    halted: bool,
+    stepping: bool,
+    // an instruction finished since the cpu resumed
+    retired: bool,
+}
+
+impl Default for DebugPort {
+    fn default() -> Self {
+        DebugPort::new()
+    }
+}
+
+impl DebugPort {
+    pub fn new() -> Self {
+        let (sender, requests) = channel();
+        DebugPort {
+            requests,
+            sender,
+            deferred: VecDeque::new(),
+            subscribers: vec![],
+            breakpoints: BTreeSet::new(),
+            watchpoints: vec![],
+            watch_hit: None,
+            halted: false,
+            stepping: false,
+            retired: false,
+        }
+    }
+}
+
+impl DebugPort {
+    pub fn attach(&self) -> Debugger {
+        Debugger::new(self.sender.clone())
+    }
+
+    pub fn is_attached(&self) -> bool {
+        !self.subscribers.is_empty()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // next pending request, blocks while halted
+    pub fn next_request(&mut self) -> Option<Request> {
+        let x = self.deferred.pop_front();
+        if x.is_some() { return x; }
+        // the port keeps a sender itself, so recv never fails
+        if self.halted { self.requests.recv().ok() } else { self.requests.try_recv().ok() }
+    }
+
+    // called between the steps of an instruction, returns the reply channel of a pending halt request;
+    // subscriptions are taken as well, every other request is deferred to the next boundary
+    pub fn take_halt(&mut self) -> Option<Sender<DebugReply>> {
+        while let Ok((request, reply)) = self.requests.try_recv() {
+            match request {
+                DebugRequest::Halt => return Some(reply),
+                DebugRequest::Subscribe(s) => self.subscribe(s),
+                r => self.deferred.push_back((r, reply)),
+            }
+        }
+        None
+    }
+
+    pub fn subscribe(&mut self, subscriber: Sender<DebugStop>) {
+        self.subscribers.push(subscriber);
+    }
+
+    // reason to stop before executing the instruction at pc, if any
+    pub fn should_stop(&mut self, pc: DoubleWord) -> Option<StopReason> {
+        if let Some(hit) = self.watch_hit.take() { return Some(StopReason::Watchpoint(hit)); }
+        if self.stepping && self.retired { return Some(StopReason::Step); }
+        if self.breakpoints.contains(&pc) { return Some(StopReason::Breakpoint(pc)); }
+        None
+    }
+
+    pub fn halt(&mut self, reason: StopReason, registers: Registers) {
+        self.halted = true;
+        self.stepping = false;
+        // debuggers that went away are dropped
+        self.subscribers.retain(|s| s.send(DebugStop { reason, registers }).is_ok());
+    }
+
+    pub fn resume(&mut self, step: bool) {
+        self.halted = false;
+        self.stepping = step;
+        self.retired = false;
+    }
+
+    // called by the cpu whenever an instruction finished
+    pub fn retire(&mut self) {
+        self.retired = true;
+    }
+
+    pub fn set_breakpoint(&mut self, address: DoubleWord) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn clear_breakpoint(&mut self, address: DoubleWord) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> Vec<DoubleWord> {
+        self.breakpoints.iter().copied().collect()
+    }
+
+    pub fn is_breakpoint(&self, address: DoubleWord) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    pub fn is_watching(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    // a watchpoint starting at the same address is replaced
+    pub fn set_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.retain(|w| w.start != watchpoint.start);
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn clear_watchpoint(&mut self, start: DoubleWord) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.start != start);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> Vec<Watchpoint> {
+        self.watchpoints.clone()
+    }
+
+    // called by the cpu for every byte it reads or writes while watching
+    pub fn access(&mut self, address: DoubleWord, kind: WatchKind, pc: DoubleWord, old: Byte, new: Byte) {
+        let w = self.watchpoints.iter().find(|w| w.matches(address, kind));
+        if w.is_none() { return; }
+        let hit = WatchHit { address, pc, kind, old, new };
+        if w.unwrap().action == WatchAction::Log || !self.is_attached() {
+            println!("{}", hit.format());
+            return;
+        }
+        if self.watch_hit.is_none() { self.watch_hit = Some(hit); }
+    }
+}
+
+// front-end side, any number of them can be attached to one cpu (see Machine::debugger);
+// a cpu left halted stays halted after its debuggers went away
+pub struct Debugger {
+    requests: Sender<Request>,
+    stops: Receiver<DebugStop>,
+}
+
+impl Debugger {
+    fn new(requests: Sender<Request>) -> Self {
+        let (subscriber, stops) = channel();
+        let (reply, _) = channel();
+        let _ = requests.send((DebugRequest::Subscribe(subscriber), reply));
+        Debugger { requests, stops }
+    }
+}
+
+impl Debugger {
+    // sends a request and waits for the cpu to answer it on its next instruction boundary
+    pub fn request(&self, request: DebugRequest) -> Result<DebugReply, Byte> {
+        let (reply, replies) = channel();
+        if self.requests.send((request, reply)).is_err() { return Err(UCode::DEBUG_TARGET_LOST); }
+        let x = replies.recv();
+        if x.is_err() { return Err(UCode::DEBUG_TARGET_LOST); }
+        match x.unwrap() {
+            DebugReply::Failed(ucode) => Err(ucode),
+            r => Ok(r)
+        }
+    }
+
+    // asks the cpu to stop without waiting for it, see `wait`
+    pub fn interrupt(&self) -> Result<(), Byte> {
+        let (reply, _) = channel();
+        if self.requests.send((DebugRequest::Halt, reply)).is_err() { return Err(UCode::DEBUG_TARGET_LOST); }
+        Ok(())
+    }
+
+    pub fn halt(&self) -> Result<DebugStop, Byte> {
+        self.discard_stops();
+        let x = self.request(DebugRequest::Halt);
+        if x.is_err() { return Err(x.err().unwrap()); }
+        self.wait()
+    }
+
+    // resumes execution, the next stop is reported through `wait` / `poll`
+    pub fn resume(&self) -> Result<(), Byte> {
+        self.discard_stops();
+        let x = self.request(DebugRequest::Continue);
+        if x.is_err() { return Err(x.err().unwrap()); }
+        Ok(())
+    }
+
+    // executes exactly one instruction (all of its steps), interrupts may divert it into their handler
+    pub fn step(&self) -> Result<DebugStop, Byte> {
+        self.discard_stops();
+        let x = self.request(DebugRequest::Step);
+        if x.is_err() { return Err(x.err().unwrap()); }
+        self.wait()
+    }
+
+    // blocks until the cpu stops
+    pub fn wait(&self) -> Result<DebugStop, Byte> {
+        let x = self.stops.recv();
+        if x.is_err() { return Err(UCode::DEBUG_TARGET_LOST); }
+        Ok(x.unwrap())
+    }
+
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<DebugStop> {
+        self.stops.recv_timeout(timeout).ok()
+    }
+
+    // the last stop that was not waited for yet
+    pub fn poll(&self) -> Option<DebugStop> {
+        self.stops.try_iter().last()
+    }
+
+    pub fn registers(&self) -> Result<Registers, Byte> {
+        match self.request(DebugRequest::Registers) {
+            Ok(DebugReply::Registers(r)) => Ok(r),
+            Ok(_) => Err(UCode::DEBUG_TARGET_LOST),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub fn set_registers(&self, registers: Registers) -> Result<(), Byte> {
+        self.request(DebugRequest::SetRegisters(registers)).map(|_| ())
+    }
+
+    // reads memory as the cpu sees it, memory mapped devices included
+    pub fn read_memory(&self, address: DoubleWord, length: usize) -> Result<Vec<Byte>, Byte> {
+        match self.request(DebugRequest::Read(address, length)) {
+            Ok(DebugReply::Memory(m)) => Ok(m),
+            Ok(_) => Err(UCode::DEBUG_TARGET_LOST),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub fn write_memory(&self, address: DoubleWord, data: &[Byte]) -> Result<(), Byte> {
+        self.request(DebugRequest::Write(address, data.to_vec())).map(|_| ())
+    }
+
+    pub fn set_breakpoint(&self, address: DoubleWord) -> Result<(), Byte> {
+        self.request(DebugRequest::Break(address)).map(|_| ())
+    }
+
+    pub fn clear_breakpoint(&self, address: DoubleWord) -> Result<(), Byte> {
+        self.request(DebugRequest::Clear(address)).map(|_| ())
+    }
+
+    pub fn breakpoints(&self) -> Result<Vec<DoubleWord>, Byte> {
+        match self.request(DebugRequest::Breakpoints) {
+            Ok(DebugReply::Breakpoints(b)) => Ok(b),
+            Ok(_) => Err(UCode::DEBUG_TARGET_LOST),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub fn set_watchpoint(&self, watchpoint: Watchpoint) -> Result<(), Byte> {
+        self.request(DebugRequest::Watch(watchpoint)).map(|_| ())
+    }
+
+    pub fn clear_watchpoint(&self, start: DoubleWord) -> Result<(), Byte> {
+        self.request(DebugRequest::Unwatch(start)).map(|_| ())
+    }
+
+    pub fn watchpoints(&self) -> Result<Vec<Watchpoint>, Byte> {
+        match self.request(DebugRequest::Watchpoints) {
+            Ok(DebugReply::Watchpoints(w)) => Ok(w),
+            Ok(_) => Err(UCode::DEBUG_TARGET_LOST),
+            Err(e) => Err(e)
+        }
+    }
+
+    // records the undo log of the last `capacity` instructions (History::CAPACITY is a sane default), 0 stops
+    pub fn record(&self, capacity: usize) -> Result<(), Byte> {
+        self.request(DebugRequest::Record(capacity)).map(|_| ())
+    }
+
+    // undoes the last instruction, fails with HISTORY_EMPTY when nothing is recorded
+    pub fn reverse_step(&self) -> Result<DebugStop, Byte> {
+        self.discard_stops();
+        let x = self.request(DebugRequest::ReverseStep);
+        if x.is_err() { return Err(x.err().unwrap()); }
+        self.wait()
+    }
+
+    // runs backwards until a breakpoint, the last write of `address` or the start of the history
+    pub fn reverse_continue(&self, address: Option<DoubleWord>) -> Result<DebugStop, Byte> {
+        self.discard_stops();
+        let x = self.request(DebugRequest::ReverseContinue(address));
+        if x.is_err() { return Err(x.err().unwrap()); }
+        self.wait()
+    }
+
+    // profiling starts over every time it is enabled
+    pub fn set_profiling(&self, enabled: bool) -> Result<(), Byte> {
+        self.request(DebugRequest::Profile(enabled)).map(|_| ())
+    }
+
+    // snapshot of the running profile
+    pub fn profile(&self) -> Result<Profiler, Byte> {
+        match self.request(DebugRequest::ProfileData) {
+            Ok(DebugReply::Profile(p)) => Ok(*p),
+            Ok(_) => Err(UCode::DEBUG_TARGET_LOST),
+            Err(e) => Err(e)
+        }
+    }
+
+    // coverage counts start over every time collecting them is enabled
+    pub fn set_coverage(&self, enabled: bool) -> Result<(), Byte> {
+        self.request(DebugRequest::Cover(enabled)).map(|_| ())
+    }
+
+    pub fn coverage(&self) -> Result<Coverage, Byte> {
+        match self.request(DebugRequest::CoverageData) {
+            Ok(DebugReply::Coverage(c)) => Ok(*c),
+            Ok(_) => Err(UCode::DEBUG_TARGET_LOST),
+            Err(e) => Err(e)
+        }
+    }
+
+    fn discard_stops(&self) {
+        while self.stops.try_recv().is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::lib::cpu::cpu::Registers;
+
+    use super::{DebugPort, DebugReply, DebugRequest, StopReason, WatchAction, WatchKind, Watchpoint};
+
+    fn watch(start: u32, size: u32, kind: WatchKind) -> Watchpoint {
+        Watchpoint { start, size, kind, action: WatchAction::Break }
+    }
+
+    #[test]
+    fn watchpoint_matches_its_range() {
+        let w = watch(0x0500_0000, 4, WatchKind::Write);
+        assert!(w.matches(0x0500_0000, WatchKind::Write));
+        assert!(w.matches(0x0500_0003, WatchKind::Write));
+        assert!(!w.matches(0x0500_0004, WatchKind::Write));
+        assert!(!w.matches(0x04FF_FFFF, WatchKind::Write));
+        // ranges ending at the top of the address space
+        let w = watch(0xFFFF_FFFE, 2, WatchKind::Write);
+        assert!(w.matches(0xFFFF_FFFF, WatchKind::Write));
+    }
+
+    #[test]
+    fn watchpoint_matches_its_kind() {
+        let w = watch(0x10, 1, WatchKind::Read);
+        assert!(w.matches(0x10, WatchKind::Read));
+        assert!(!w.matches(0x10, WatchKind::Write));
+        let w = watch(0x10, 1, WatchKind::Access);
+        assert!(w.matches(0x10, WatchKind::Read));
+        assert!(w.matches(0x10, WatchKind::Write));
+    }
+
+    #[test]
+    fn halt_is_taken_ahead_of_deferred_requests() {
+        let mut port = DebugPort::new();
+        let debugger = port.attach();
+        let sender = port.sender.clone();
+        let (reply, replies) = std::sync::mpsc::channel();
+        sender.send((DebugRequest::Registers, reply)).unwrap();
+        assert!(port.take_halt().is_none());
+        debugger.interrupt().unwrap();
+
+        let halt = port.take_halt();
+        assert!(halt.is_some());
+        port.halt(StopReason::Halted, Registers { a: 0, x: 0, y: 0, flags: 0, sp: 0, pc: 0x1000_0002 });
+        let stop = debugger.wait_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!((stop.reason, stop.registers.pc), (StopReason::Halted, 0x1000_0002));
+
+        // the deferred request is served first once the cpu reads its port again
+        let (request, reply) = port.next_request().unwrap();
+        assert!(matches!(request, DebugRequest::Registers));
+        reply.send(DebugReply::Done).unwrap();
+        assert!(matches!(replies.recv().unwrap(), DebugReply::Done));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::lib::debug::symbols::Symbols;
+use crate::lib::mem::{Byte, DoubleWord};
+use crate::lib::ucode::cpu_assembly::CPUAssembly;
+
+// CPUAssembly to text, operands are printed the way the program comments write them
+// ($0x00, $0xFFFF, $0x1000'0000); with symbols, addresses get their label (<loop+0x3>)
+
+// decodes the instruction at the front of `bytes` into its text and length, None if it is incomplete
+pub fn instruction(bytes: &[Byte]) -> Option<(String, usize)> {
+    decode(bytes, None)
+}
+
+pub fn symbolic(bytes: &[Byte], symbols: &Symbols) -> Option<(String, usize)> {
+    decode(bytes, Some(symbols))
+}
+
+fn decode(bytes: &[Byte], symbols: Option<&Symbols>) -> Option<(String, usize)> {
+    if bytes.is_empty() { return None; }
+    let opcode = bytes[0];
+    let size = 1 + CPUAssembly::operand_size(opcode);
+    if bytes.len() < size { return None; }
+
+    let mut x = CPUAssembly::mnemonic(opcode).to_string();
+    let mut at = 1;
+    for width in CPUAssembly::operands(opcode) {
+        let value = bytes[at..at + width].iter().fold(0 as DoubleWord, |v, b| v << 8 | *b as DoubleWord);
+        x += " ";
+        x += operand(value, *width).as_str();
+        let label = if *width == 4 { symbols.and_then(|s| s.symbolize(value)) } else { None };
+        if let Some(label) = label { x += format!(" <{}>", label).as_str(); }
+        at += width;
+    }
+    Some((x, size))
+}
+
+// one instruction per line with its address and bytes, a trailing incomplete instruction is shown as data;
+// with symbols labels head their instructions, data ranges are dumped and source lines noted
+pub fn listing(address: DoubleWord, bytes: &[Byte], symbols: Option<&Symbols>) -> String {
+    let mut x = "".to_string();
+    let mut at = 0;
+    while at < bytes.len() {
+        let here = address.wrapping_add(at as DoubleWord);
+        let label = symbols.and_then(|s| s.symbolize(here)).filter(|l| !l.contains('+'));
+        if let Some(label) = label { x += format!("{}:\n", label).as_str(); }
+
+        if let Some(d) = symbols.and_then(|s| s.data(here)) {
+            // rest of the range, at most one row
+            let end = (d.length - (here - d.start)).min(16) as usize;
+            let size = end.min(bytes.len() - at);
+            x += format!("{:#010X}  {:<32}  .data\n", here, hex(&bytes[at..at + size])).as_str();
+            at += size;
+            continue;
+        }
+
+        let decoded = decode(&bytes[at..], symbols);
+        let (text, size) = decoded.unwrap_or(("(incomplete)".to_string(), bytes.len() - at));
+        let line = symbols.and_then(|s| s.source().lookup(here)).map_or("".to_string(), |l| format!("; {}:{}", l.file, l.line));
+        let row = format!("{:#010X}  {:<32}  {:<40} {}", here, hex(&bytes[at..at + size]), text, line);
+        x += row.trim_end();
+        x += "\n";
+        at += size;
+    }
+    x
+}
+
+pub fn operand(value: DoubleWord, width: usize) -> String {
+    match width {
+        1 => format!("${:#04X}", value),
+        2 => format!("${:#06X}", value),
+        _ => format!("$0x{:04X}'{:04X}", value >> 16, value & 0xFFFF),
+    }
+}
+
+pub fn hex(bytes: &[Byte]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lib::debug::symbols::Symbols;
+
+    use super::listing;
+
+    #[test]
+    fn listing_dumps_data_ranges_at_the_top_of_memory() {
+        let mut s = Symbols::new();
+        s.add_data(0xFFFF_FFF0, 0x10, "tail");
+        let x = listing(0xFFFF_FFEC, &[0x0; 0x14], Some(&s));
+        let rows: Vec<&str> = x.lines().collect();
+        assert!(rows.last().unwrap().starts_with("0xFFFFFFF0  00 00"), "{}", x);
+        assert!(rows.last().unwrap().ends_with(".data"), "{}", x);
+    }
+}
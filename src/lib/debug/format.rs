@@ -0,0 +1,50 @@
+use crate::lib::mem::{Byte, DoubleWord};
+
+// number parsing and memory dumps shared by the cpu stack traces, the debugger front-ends and
+// the symbol file readers
+
+// decimal or hex with a 0x prefix, ' and _ may be used as digit separators (0x1000'0000)
+pub fn parse(s: &str) -> Result<DoubleWord, String> {
+    let clean: String = s.chars().filter(|c| *c != '\'' && *c != '_').collect();
+    let x = if clean.starts_with("0x") || clean.starts_with("0X") {
+        DoubleWord::from_str_radix(&clean[2..], 16)
+    } else {
+        clean.parse::<DoubleWord>()
+    };
+    x.map_err(|_| format!("invalid number {}", s))
+}
+
+// 16 bytes per row with their ascii text
+pub fn hexdump(address: DoubleWord, data: &[Byte]) -> String {
+    let mut x = "".to_string();
+    for (i, row) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+        let text: String = row.iter().map(|b| if b.is_ascii_graphic() { *b as char } else { '.' }).collect();
+        x += format!("{:#010X}  {:<47}  {}\n", address as usize + i * 16, hex.join(" "), text).as_str();
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hexdump, parse};
+
+    #[test]
+    fn parse_numbers() {
+        assert_eq!(parse("0x1000'0000"), Ok(0x1000_0000));
+        assert_eq!(parse("0XFF_FF"), Ok(0xFFFF));
+        assert_eq!(parse("42"), Ok(42));
+        assert!(parse("0x1'0000'0000").is_err());
+        assert!(parse("0x").is_err());
+        assert!(parse("abc").is_err());
+    }
+
+    #[test]
+    fn hexdump_rows() {
+        let data: Vec<u8> = (0x41..0x53).collect();
+        assert_eq!(hexdump(0x10, &data),
+                   "0x00000010  41 42 43 44 45 46 47 48 49 4A 4B 4C 4D 4E 4F 50  ABCDEFGHIJKLMNOP\n\
+                    0x00000020  51 52                                            QR\n");
+        assert_eq!(hexdump(0x0, &[0x00, 0x20]), format!("0x00000000  00 20{}  ..\n", " ".repeat(42)));
+    }
+}
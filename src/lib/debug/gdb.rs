@@ -0,0 +1,504 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::lib::cpu::cpu::Registers;
+use crate::lib::debug::debugger::{DebugStop, Debugger, StopReason, WatchAction, WatchKind, Watchpoint};
+use crate::lib::debug::history::History;
+use crate::lib::mem::{Byte, DoubleWord, Word};
+use crate::lib::ucode::ucode::UCode;
+
+// gdb remote serial protocol stub on top of a Debugger
+//
+// one client at a time over tcp; registers are numbered as in TARGET_XML and sent in target
+// (big endian) byte order. supported: ? g G p P m M c s bs bc Z0-Z4/z0-z4 D k qSupported,
+// qXfer:features:read and the ctrl-c interrupt while running
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.virtualmachine.cpu">
+    <flags id="cpu_flags" size="1">
+      <field name="C" start="0" end="0"/>
+      <field name="Z" start="1" end="1"/>
+      <field name="I" start="2" end="2"/>
+      <field name="D" start="3" end="3"/>
+      <field name="B" start="4" end="4"/>
+      <field name="V" start="6" end="6"/>
+      <field name="N" start="7" end="7"/>
+    </flags>
+    <reg name="a" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="x" bitsize="16" type="uint16"/>
+    <reg name="y" bitsize="16" type="uint16"/>
+    <reg name="flags" bitsize="8" type="cpu_flags"/>
+    <reg name="sp" bitsize="32" type="data_ptr"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+pub struct GdbStub {
+    debugger: Debugger,
+}
+
+impl GdbStub {
+    pub fn new(debugger: Debugger) -> Self {
+        GdbStub { debugger }
+    }
+
+    pub const PACKET_SIZE: usize = 0x4000;
+    // bytes of each register in the g / G packets
+    const REGISTER_SIZES: [usize; 6] = [2, 2, 2, 1, 4, 4];
+
+    const SIGINT: Byte = 2;
+    const SIGTRAP: Byte = 5;
+    const SIGSEGV: Byte = 11;
+}
+
+impl GdbStub {
+    // serves clients on the given address (e.g. 127.0.0.1:1234) one after another, the cpu is
+    // halted while a client connects and resumed when it detaches
+    pub fn listen(&mut self, address: &str) -> Result<(), Byte> {
+        let listener = TcpListener::bind(address);
+        if listener.is_err() { return Err(UCode::GDB_CONNECTION_FAILURE); }
+        for stream in listener.unwrap().incoming() {
+            if stream.is_err() { continue; }
+            let res = self.serve(stream.unwrap());
+            if res.is_err() && res.err().unwrap() == UCode::DEBUG_TARGET_LOST { return res; }
+        }
+        Ok(())
+    }
+
+    pub fn serve(&mut self, mut stream: TcpStream) -> Result<(), Byte> {
+        let _ = stream.set_nodelay(true);
+        let stop = self.debugger.halt();
+        if stop.is_err() { return Err(stop.err().unwrap()); }
+        let mut last = stop.unwrap();
+        // reverse execution covers what ran since the client connected
+        self.debugger.record(History::CAPACITY)?;
+
+        loop {
+            let packet = GdbStub::receive(&mut stream);
+            if packet.is_err() {
+                let _ = self.debugger.resume();
+                return Err(packet.err().unwrap());
+            }
+            let packet = packet.unwrap();
+            // ctrl-c while already halted
+            if packet.is_none() {
+                GdbStub::send(&mut stream, &GdbStub::stop_reply(&last))?;
+                continue;
+            }
+            // every supported packet is plain ascii, anything else gets the empty (unsupported) reply
+            let packet = packet.unwrap();
+            if !packet.is_ascii() {
+                GdbStub::send(&mut stream, "")?;
+                continue;
+            }
+            let packet = String::from_utf8(packet).unwrap();
+
+            let reply = match packet.as_bytes().first() {
+                Some(b'c') | Some(b's') => {
+                    let stop = self.run(&mut stream, &packet);
+                    if stop.is_err() { return Err(stop.err().unwrap()); }
+                    last = stop.unwrap();
+                    GdbStub::stop_reply(&last)
+                }
+                Some(b'b') => {
+                    let stop = match packet.as_str() {
+                        "bs" => Some(self.debugger.reverse_step()),
+                        "bc" => Some(self.debugger.reverse_continue(None)),
+                        _ => None,
+                    };
+                    match stop {
+                        None => "".to_string(),
+                        Some(Ok(s)) => {
+                            last = s;
+                            GdbStub::stop_reply(&last)
+                        }
+                        // nothing recorded (yet)
+                        Some(Err(UCode::HISTORY_EMPTY)) => format!("T{:02x}replaylog:begin;", GdbStub::SIGTRAP),
+                        Some(Err(e)) => format!("E{:02x}", e),
+                    }
+                }
+                Some(b'D') => {
+                    let _ = GdbStub::send(&mut stream, "OK");
+                    return self.debugger.resume();
+                }
+                Some(b'k') => return self.debugger.resume(),
+                _ => self.handle(&packet, &last),
+            };
+            let res = GdbStub::send(&mut stream, &reply);
+            if res.is_err() {
+                let _ = self.debugger.resume();
+                return res;
+            }
+        }
+    }
+
+    // every packet that does not resume the cpu
+    fn handle(&mut self, packet: &str, last: &DebugStop) -> String {
+        let error = |e: Byte| format!("E{:02x}", e);
+        if packet.is_empty() { return "".to_string(); }
+        let (command, args) = packet.split_at(1);
+        match command {
+            "?" => GdbStub::stop_reply(last),
+            "g" => self.debugger.registers().map(|r| GdbStub::encode_registers(&r)).unwrap_or_else(error),
+            "G" => {
+                let r = GdbStub::decode_registers(args);
+                if r.is_none() { return error(UCode::INVALID_BUFFER_ACCESS); }
+                self.debugger.set_registers(r.unwrap()).map(|_| "OK".to_string()).unwrap_or_else(error)
+            }
+            "p" => {
+                let n = usize::from_str_radix(args, 16);
+                if n.is_err() || n.as_ref().unwrap() >= &GdbStub::REGISTER_SIZES.len() { return "E00".to_string(); }
+                let n = n.unwrap();
+                let r = self.debugger.registers();
+                if r.is_err() { return error(r.err().unwrap()); }
+                let all = GdbStub::encode_registers(&r.unwrap());
+                let start: usize = GdbStub::REGISTER_SIZES[..n].iter().sum::<usize>() * 2;
+                all[start..start + GdbStub::REGISTER_SIZES[n] * 2].to_string()
+            }
+            "P" => {
+                let x = args.split_once('=');
+                if x.is_none() { return "E00".to_string(); }
+                let (n, value) = x.unwrap();
+                let n = usize::from_str_radix(n, 16);
+                if n.is_err() || n.as_ref().unwrap() >= &GdbStub::REGISTER_SIZES.len() { return "E00".to_string(); }
+                let n = n.unwrap();
+                let r = self.debugger.registers();
+                if r.is_err() { return error(r.err().unwrap()); }
+                let mut all = GdbStub::encode_registers(&r.unwrap());
+                let start: usize = GdbStub::REGISTER_SIZES[..n].iter().sum::<usize>() * 2;
+                if value.len() != GdbStub::REGISTER_SIZES[n] * 2 { return "E00".to_string(); }
+                all.replace_range(start..start + value.len(), value);
+                let r = GdbStub::decode_registers(&all);
+                if r.is_none() { return "E00".to_string(); }
+                self.debugger.set_registers(r.unwrap()).map(|_| "OK".to_string()).unwrap_or_else(error)
+            }
+            "m" => {
+                let x = GdbStub::address_length(args);
+                if x.is_none() { return "E00".to_string(); }
+                let (address, length) = x.unwrap();
+                let length = length.min((GdbStub::PACKET_SIZE - 4) / 2);
+                self.debugger.read_memory(address, length).map(|m| GdbStub::hex(&m)).unwrap_or_else(error)
+            }
+            "M" => {
+                let x = args.split_once(':');
+                if x.is_none() { return "E00".to_string(); }
+                let (range, data) = x.unwrap();
+                let range = GdbStub::address_length(range);
+                let data = GdbStub::unhex(data);
+                if range.is_none() || data.is_none() { return "E00".to_string(); }
+                if range.unwrap().1 != data.as_ref().unwrap().len() { return "E01".to_string(); }
+                self.debugger.write_memory(range.unwrap().0, &data.unwrap()).map(|_| "OK".to_string()).unwrap_or_else(error)
+            }
+            "Z" | "z" => {
+                let parts: Vec<&str> = args.split(',').collect();
+                if parts.len() < 2 { return "E00".to_string(); }
+                let address = DoubleWord::from_str_radix(parts[1], 16);
+                if address.is_err() { return "E00".to_string(); }
+                let address = address.unwrap();
+                let kind = match parts[0] {
+                    // software and hardware breakpoints are the same thing here
+                    "0" | "1" => None,
+                    "2" => Some(WatchKind::Write),
+                    "3" => Some(WatchKind::Read),
+                    "4" => Some(WatchKind::Access),
+                    _ => return "".to_string()
+                };
+                let res = match kind {
+                    None if command == "Z" => self.debugger.set_breakpoint(address),
+                    None => self.debugger.clear_breakpoint(address),
+                    Some(kind) if command == "Z" => {
+                        let size = if parts.len() > 2 { DoubleWord::from_str_radix(parts[2], 16).unwrap_or(1) } else { 1 };
+                        self.debugger.set_watchpoint(Watchpoint { start: address, size, kind, action: WatchAction::Break })
+                    }
+                    Some(_) => self.debugger.clear_watchpoint(address),
+                };
+                match res {
+                    Ok(_) | Err(UCode::BREAKPOINT_NOT_FOUND) | Err(UCode::WATCHPOINT_NOT_FOUND) => "OK".to_string(),
+                    Err(e) => error(e)
+                }
+            }
+            "H" | "T" => "OK".to_string(),
+            "q" => {
+                if args.starts_with("Supported") {
+                    format!("PacketSize={:x};qXfer:features:read+;ReverseStep+;ReverseContinue+", GdbStub::PACKET_SIZE)
+                } else if let Some(x) = args.strip_prefix("Xfer:features:read:target.xml:") {
+                    let x = GdbStub::address_length(x);
+                    if x.is_none() { return "E00".to_string(); }
+                    let (offset, length) = x.unwrap();
+                    let offset = (offset as usize).min(TARGET_XML.len());
+                    let end = offset.saturating_add(length).min(TARGET_XML.len());
+                    format!("{}{}", if end == TARGET_XML.len() { "l" } else { "m" }, &TARGET_XML[offset..end])
+                } else if args == "Attached" {
+                    "1".to_string()
+                } else if args == "C" {
+                    "QC1".to_string()
+                } else if args == "fThreadInfo" {
+                    "m1".to_string()
+                } else if args == "sThreadInfo" {
+                    "l".to_string()
+                } else {
+                    "".to_string()
+                }
+            }
+            // unsupported packets get an empty reply
+            _ => "".to_string()
+        }
+    }
+
+    // resumes (c) or steps (s) the cpu, optionally from a new pc, until it stops again;
+    // a ctrl-c from the client halts it
+    fn run(&mut self, stream: &mut TcpStream, packet: &str) -> Result<DebugStop, Byte> {
+        if packet.len() > 1 {
+            let pc = DoubleWord::from_str_radix(&packet[1..], 16);
+            let r = self.debugger.registers();
+            if let (Ok(pc), Ok(mut r)) = (pc, r) {
+                r.pc = pc;
+                let res = self.debugger.set_registers(r);
+                if res.is_err() { return Err(res.err().unwrap()); }
+            }
+        }
+        if packet.starts_with('s') { return self.debugger.step(); }
+
+        let res = self.debugger.resume();
+        if res.is_err() { return Err(res.err().unwrap()); }
+        let _ = stream.set_read_timeout(Some(Duration::from_millis(10)));
+        let stop = loop {
+            if let Some(stop) = self.debugger.wait_timeout(Duration::from_millis(10)) { break stop; }
+
+            let mut b = [0x0; 1];
+            match stream.read(&mut b) {
+                Ok(0) => {
+                    let _ = stream.set_read_timeout(None);
+                    return Err(UCode::GDB_CONNECTION_FAILURE);
+                }
+                Ok(_) if b[0] == 0x03 => {
+                    let res = self.debugger.interrupt();
+                    if res.is_err() { return Err(res.err().unwrap()); }
+                }
+                Err(e) if e.kind() != ErrorKind::WouldBlock && e.kind() != ErrorKind::TimedOut => {
+                    return Err(UCode::GDB_CONNECTION_FAILURE);
+                }
+                _ => ()
+            }
+        };
+        let _ = stream.set_read_timeout(None);
+        Ok(stop)
+    }
+
+    // next packet (acknowledged), None for a ctrl-c outside a packet; corrupted packets are nacked
+    // until the client sent them intact, packets beyond PACKET_SIZE drop the connection
+    fn receive(stream: &mut TcpStream) -> Result<Option<Vec<Byte>>, Byte> {
+        loop {
+            loop {
+                let x = GdbStub::read_byte(stream);
+                if x.is_err() { return Err(x.err().unwrap()); }
+                match x.unwrap() {
+                    0x03 => return Ok(None),
+                    b'$' => break,
+                    // acks and noise between packets
+                    _ => ()
+                }
+            }
+
+            let mut data = vec![];
+            // the checksum covers the packet as sent, escapes included
+            let mut sum = 0u8;
+            loop {
+                if data.len() >= GdbStub::PACKET_SIZE { return Err(UCode::GDB_PACKET_TOO_LARGE); }
+                let x = GdbStub::read_byte(stream);
+                if x.is_err() { return Err(x.err().unwrap()); }
+                match x.unwrap() {
+                    b'#' => break,
+                    b'}' => {
+                        let x = GdbStub::read_byte(stream);
+                        if x.is_err() { return Err(x.err().unwrap()); }
+                        sum = sum.wrapping_add(b'}').wrapping_add(x.unwrap());
+                        data.push(x.unwrap() ^ 0x20);
+                    }
+                    c => {
+                        sum = sum.wrapping_add(c);
+                        data.push(c);
+                    }
+                }
+            }
+            let mut checksum = [0x0; 2];
+            for c in checksum.iter_mut() {
+                let x = GdbStub::read_byte(stream);
+                if x.is_err() { return Err(x.err().unwrap()); }
+                *c = x.unwrap();
+            }
+            let expected = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap_or(""), 16);
+            let ok = expected.is_ok() && expected.unwrap() == sum;
+            if stream.write_all(if ok { b"+" } else { b"-" }).is_err() { return Err(UCode::GDB_CONNECTION_FAILURE); }
+            if ok { return Ok(Some(data)); }
+        }
+    }
+
+    fn read_byte(stream: &mut TcpStream) -> Result<Byte, Byte> {
+        let mut b = [0x0; 1];
+        match stream.read(&mut b) {
+            Ok(1) => Ok(b[0]),
+            _ => Err(UCode::GDB_CONNECTION_FAILURE)
+        }
+    }
+
+    fn send(stream: &mut TcpStream, data: &str) -> Result<(), Byte> {
+        let mut escaped = vec![];
+        for b in data.bytes() {
+            if b == b'$' || b == b'#' || b == b'}' || b == b'*' { escaped.extend([b'}', b ^ 0x20]); } else { escaped.push(b); }
+        }
+        let sum = escaped.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+        let packet = format!("${}#{:02x}", String::from_utf8_lossy(&escaped), sum);
+        if stream.write_all(packet.as_bytes()).is_err() { return Err(UCode::GDB_CONNECTION_FAILURE); }
+        Ok(())
+    }
+
+    fn stop_reply(stop: &DebugStop) -> String {
+        if stop.reason == StopReason::HistoryStart { return format!("T{:02x}replaylog:begin;", GdbStub::SIGTRAP); }
+        if let StopReason::Watchpoint(hit) = stop.reason {
+            let kind = if hit.kind == WatchKind::Read { "rwatch" } else { "watch" };
+            return format!("T{:02x}{}:{:x};", GdbStub::SIGTRAP, kind, hit.address);
+        }
+        let signal = match stop.reason {
+            StopReason::Halted => GdbStub::SIGINT,
+            StopReason::Exception(_) => GdbStub::SIGSEGV,
+            _ => GdbStub::SIGTRAP,
+        };
+        format!("S{:02x}", signal)
+    }
+
+    fn encode_registers(r: &Registers) -> String {
+        format!("{:04x}{:04x}{:04x}{:02x}{:08x}{:08x}", r.a, r.x, r.y, r.flags, r.sp, r.pc)
+    }
+
+    fn decode_registers(hex: &str) -> Option<Registers> {
+        let mut values = vec![];
+        let mut at = 0;
+        for size in GdbStub::REGISTER_SIZES {
+            let v = hex.get(at..at + size * 2).and_then(|v| DoubleWord::from_str_radix(v, 16).ok())?;
+            values.push(v);
+            at += size * 2;
+        }
+        Some(Registers {
+            a: values[0] as Word,
+            x: values[1] as Word,
+            y: values[2] as Word,
+            flags: values[3] as Byte,
+            sp: values[4],
+            pc: values[5],
+        })
+    }
+
+    // "addr,length" in hex
+    fn address_length(args: &str) -> Option<(DoubleWord, usize)> {
+        let (address, length) = args.split_once(',')?;
+        let address = DoubleWord::from_str_radix(address, 16);
+        let length = usize::from_str_radix(length, 16);
+        if address.is_err() || length.is_err() { return None; }
+        Some((address.unwrap(), length.unwrap()))
+    }
+
+    fn hex(bytes: &[Byte]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn unhex(hex: &str) -> Option<Vec<Byte>> {
+        if !hex.len().is_multiple_of(2) { return None; }
+        (0..hex.len()).step_by(2).map(|i| hex.get(i..i + 2).and_then(|x| Byte::from_str_radix(x, 16).ok())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    use crate::lib::cpu::cpu::Registers;
+    use crate::lib::debug::debugger::{DebugPort, DebugStop, StopReason};
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::GdbStub;
+
+    fn connected() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (listener.accept().unwrap().0, client)
+    }
+
+    #[test]
+    fn registers_round_trip() {
+        let r = Registers { a: 0x1234, x: 0xABCD, y: 0x0001, flags: 0x82, sp: 0x0FFF_FFF0, pc: 0x1000_0004 };
+        let hex = GdbStub::encode_registers(&r);
+        assert_eq!(hex, "1234abcd0001820ffffff010000004");
+        assert_eq!(GdbStub::decode_registers(&hex), Some(r));
+        assert_eq!(GdbStub::decode_registers(&hex[..hex.len() - 1]), None);
+        assert_eq!(GdbStub::decode_registers("zz34abcd0001820ffffff010000004"), None);
+        // multi byte characters must not split the slices
+        assert_eq!(GdbStub::decode_registers("éé34abcd0001820ffffff010000004"), None);
+    }
+
+    #[test]
+    fn unhex_rejects_malformed_input() {
+        assert_eq!(GdbStub::unhex("00ff7A"), Some(vec![0x00, 0xFF, 0x7A]));
+        assert_eq!(GdbStub::unhex(""), Some(vec![]));
+        assert_eq!(GdbStub::unhex("abc"), None);
+        assert_eq!(GdbStub::unhex("0g"), None);
+        assert_eq!(GdbStub::unhex("éa"), None);
+        assert_eq!(GdbStub::hex(&[0x00, 0xFF, 0x7A]), "00ff7a");
+    }
+
+    #[test]
+    fn address_length_parses_hex_pairs() {
+        assert_eq!(GdbStub::address_length("10000000,4"), Some((0x1000_0000, 4)));
+        assert_eq!(GdbStub::address_length("10000000"), None);
+        assert_eq!(GdbStub::address_length("100000000,4"), None);
+    }
+
+    #[test]
+    fn receive_checks_and_acknowledges_packets() {
+        let (mut stub, mut client) = connected();
+        // a corrupted packet is nacked and the retransmission taken, escapes are undone
+        client.write_all(b"+$m0,4#00$m0,4#fd$a}]b#9d").unwrap();
+        assert_eq!(GdbStub::receive(&mut stub), Ok(Some(b"m0,4".to_vec())));
+        assert_eq!(GdbStub::receive(&mut stub), Ok(Some(b"a}b".to_vec())));
+        client.write_all(&[0x03]).unwrap();
+        assert_eq!(GdbStub::receive(&mut stub), Ok(None));
+        let mut acks = [0x0; 3];
+        client.read_exact(&mut acks).unwrap();
+        assert_eq!(&acks, b"-++");
+    }
+
+    #[test]
+    fn receive_rejects_oversized_packets() {
+        let (mut stub, mut client) = connected();
+        let mut packet = b"$".to_vec();
+        packet.extend(vec![b'0'; GdbStub::PACKET_SIZE + 1]);
+        packet.extend(b"#00");
+        client.write_all(&packet).unwrap();
+        assert_eq!(GdbStub::receive(&mut stub), Err(UCode::GDB_PACKET_TOO_LARGE));
+    }
+
+    #[test]
+    fn write_memory_needs_the_announced_length() {
+        let mut stub = GdbStub::new(DebugPort::new().attach());
+        let last = DebugStop { reason: StopReason::Halted, registers: Registers { a: 0, x: 0, y: 0, flags: 0, sp: 0, pc: 0 } };
+        assert_eq!(stub.handle("M10000000,4:0102", &last), "E01");
+        assert_eq!(stub.handle("M10000000,1:0102", &last), "E01");
+        assert_eq!(stub.handle("M10000000,2:010", &last), "E00");
+        // well formed, the detached port answers with DEBUG_TARGET_LOST
+        assert_eq!(stub.handle("M10000000,2:0102", &last), format!("E{:02x}", UCode::DEBUG_TARGET_LOST));
+    }
+
+    #[test]
+    fn send_escapes_and_sums() {
+        let (mut stub, mut client) = connected();
+        GdbStub::send(&mut stub, "a#b").unwrap();
+        let mut packet = [0x0; 8];
+        client.read_exact(&mut packet).unwrap();
+        assert_eq!(&packet, b"$a}\x03b#43");
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+
+use crate::lib::cpu::cpu::Registers;
+use crate::lib::mem::{Byte, DoubleWord};
+
+// undo log of the instructions the cpu executed, lets the debugger run backwards
+//
+// every instruction (and every interrupt entry) gets one record holding the registers before it
+// and the old value of each byte of RAM it wrote, the oldest records are dropped once the ring is
+// full. only the cpu is rewound: writes to memory mapped devices, dma transfers into RAM and the
+// state of the devices themselves are not undone
+
+pub struct UndoRecord {
+    pub registers: Registers,
+    // (address, old value) in the order the writes happened
+    pub memory: Vec<(DoubleWord, Byte)>,
+}
+
+impl UndoRecord {
+    pub fn writes(&self, address: DoubleWord) -> bool {
+        self.memory.iter().any(|m| m.0 == address)
+    }
+}
+
+pub struct History {
+    records: VecDeque<UndoRecord>,
+    // 0 => not recording
+    capacity: usize,
+    // record of the instruction that is running
+    current: Option<UndoRecord>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            records: VecDeque::new(),
+            capacity: 0,
+            current: None,
+        }
+    }
+
+    // instructions kept by default
+    pub const CAPACITY: usize = 65_536;
+}
+
+impl History {
+    pub fn is_recording(&self) -> bool {
+        self.capacity > 0
+    }
+
+    // starts recording the last `capacity` instructions, 0 stops and forgets the history
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.records.len() > capacity { self.records.pop_front(); }
+        if capacity == 0 { self.current = None; }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len() + if self.current.is_some() { 1 } else { 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn begin(&mut self, registers: Registers) {
+        if !self.is_recording() { return; }
+        self.commit();
+        self.current = Some(UndoRecord { registers, memory: vec![] });
+    }
+
+    // a byte of RAM is about to be overwritten
+    pub fn store(&mut self, address: DoubleWord, old: Byte) {
+        if let Some(x) = self.current.as_mut() { x.memory.push((address, old)); }
+    }
+
+    pub fn commit(&mut self) {
+        let x = self.current.take();
+        if x.is_none() { return; }
+        if self.records.len() >= self.capacity { self.records.pop_front(); }
+        self.records.push_back(x.unwrap());
+    }
+
+    // the latest record, an instruction that stopped half way (on an exception) comes first
+    pub fn pop(&mut self) -> Option<UndoRecord> {
+        let x = self.current.take();
+        if x.is_some() { return x; }
+        self.records.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lib::cpu::cpu::Registers;
+
+    use super::History;
+
+    fn registers(pc: u32) -> Registers {
+        Registers { a: 0x0, x: 0x0, y: 0x0, flags: 0x0, sp: 0x0FFF_FFFF, pc }
+    }
+
+    #[test]
+    fn ring_drops_the_oldest_records() {
+        let mut h = History::new();
+        h.set_capacity(3);
+        for pc in 0..5 {
+            h.begin(registers(pc));
+            h.store(0x100 + pc, pc as u8);
+        }
+        // three committed and the running one
+        assert_eq!(h.len(), 4);
+        h.commit();
+        assert_eq!(h.len(), 3);
+        let pcs: Vec<u32> = std::iter::from_fn(|| h.pop()).map(|r| r.registers.pc).collect();
+        assert_eq!(pcs, vec![4, 3, 2]);
+    }
+
+    #[test]
+    fn running_instruction_is_undone_first() {
+        let mut h = History::new();
+        h.set_capacity(2);
+        h.begin(registers(0));
+        h.begin(registers(1));
+        h.store(0x10, 0xAA);
+        h.store(0x10, 0xBB);
+        let r = h.pop().unwrap();
+        assert_eq!(r.registers.pc, 1);
+        assert_eq!(r.memory, vec![(0x10, 0xAA), (0x10, 0xBB)]);
+        assert!(r.writes(0x10));
+        assert_eq!(h.pop().unwrap().registers.pc, 0);
+        assert!(h.pop().is_none());
+    }
+
+    #[test]
+    fn shrinking_keeps_the_newest_records() {
+        let mut h = History::new();
+        assert!(!h.is_recording());
+        h.begin(registers(0));
+        assert_eq!(h.len(), 0);
+        h.set_capacity(4);
+        for pc in 0..4 { h.begin(registers(pc)); }
+        h.commit();
+        h.set_capacity(2);
+        assert_eq!(h.pop().unwrap().registers.pc, 3);
+        assert_eq!(h.pop().unwrap().registers.pc, 2);
+        assert!(h.pop().is_none());
+        h.set_capacity(0);
+        h.begin(registers(5));
+        assert_eq!(h.len(), 0);
+    }
+}
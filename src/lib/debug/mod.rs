@@ -0,0 +1,12 @@
+pub mod backtrace;
+pub mod coverage;
+pub mod debugger;
+pub mod disassembler;
+pub mod format;
+pub mod gdb;
+pub mod history;
+pub mod profiler;
+pub mod repl;
+pub mod source;
+pub mod symbols;
+pub mod trace;
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+
+use crate::lib::mem::{Byte, DoubleWord};
+use crate::lib::ucode::cpu_assembly::CPUAssembly;
+
+// exact profiler of the guest program, see CPU::set_profiler
+//
+// every cpu cycle is charged to the instruction it was spent on and to the current call stack.
+// the cpu has no call instruction, so the stack is tracked the way programs call subroutines:
+// PSP directly followed by JMP enters the jump target, PLP returns; interrupts enter their handler
+// and RTI leaves it. the outermost frame is wherever profiling started
+
+#[derive(Default, Clone, Copy)]
+pub struct AddressProfile {
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    // cycles spent in the function itself
+    pub own: u64,
+    // cycles spent in the function and everything it called
+    pub total: u64,
+}
+
+#[derive(Clone)]
+pub struct Profiler {
+    addresses: BTreeMap<DoubleWord, AddressProfile>,
+    calls: BTreeMap<DoubleWord, u64>,
+    // cycles per call stack, outermost frame first
+    stacks: BTreeMap<Vec<DoubleWord>, u64>,
+    stack: Vec<DoubleWord>,
+    previous: Byte,
+    cycles: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            addresses: BTreeMap::new(),
+            calls: BTreeMap::new(),
+            stacks: BTreeMap::new(),
+            stack: vec![],
+            previous: CPUAssembly::HLT,
+            cycles: 0,
+        }
+    }
+}
+
+impl Profiler {
+    // one cpu cycle spent on the instruction at address
+    pub fn cycle(&mut self, address: DoubleWord) {
+        if self.stack.is_empty() { self.stack.push(address); }
+        self.cycles += 1;
+        self.addresses.entry(address).or_default().cycles += 1;
+        if let Some(x) = self.stacks.get_mut(&self.stack) { *x += 1; } else { self.stacks.insert(self.stack.clone(), 1); }
+    }
+
+    // the instruction at address finished, the cpu continues at next
+    pub fn retire(&mut self, address: DoubleWord, opcode: Byte, next: DoubleWord) {
+        self.addresses.entry(address).or_default().executions += 1;
+        match opcode {
+            CPUAssembly::JMP if self.previous == CPUAssembly::PSP => self.enter(next),
+            // the outermost frame is never left
+            CPUAssembly::PLP | CPUAssembly::RTI if self.stack.len() > 1 => { self.stack.pop(); }
+            _ => ()
+        }
+        self.previous = opcode;
+    }
+
+    // the cpu entered the interrupt handler at vector
+    pub fn interrupt(&mut self, vector: DoubleWord) {
+        self.enter(vector);
+        self.previous = CPUAssembly::HLT;
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn addresses(&self) -> &BTreeMap<DoubleWord, AddressProfile> {
+        &self.addresses
+    }
+
+    // functions by entry address
+    pub fn functions(&self) -> BTreeMap<DoubleWord, FunctionProfile> {
+        let mut x: BTreeMap<DoubleWord, FunctionProfile> = BTreeMap::new();
+        for (stack, cycles) in self.stacks.iter() {
+            x.entry(*stack.last().unwrap()).or_default().own += cycles;
+            // recursive calls count once towards the total
+            let mut seen = stack.clone();
+            seen.sort();
+            seen.dedup();
+            for f in seen {
+                x.entry(f).or_default().total += cycles;
+            }
+        }
+        for (f, calls) in self.calls.iter() {
+            x.entry(*f).or_default().calls = *calls;
+        }
+        x
+    }
+
+    // hottest `limit` addresses and all functions, most cycles first
+    pub fn report(&self, limit: usize) -> String {
+        let percent = |c: u64| c as f64 * 100.0 / self.cycles.max(1) as f64;
+        let mut x = format!("{} cycles\n\n  address     executions      cycles       %\n", self.cycles);
+        let mut addresses: Vec<(&DoubleWord, &AddressProfile)> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+        for (a, p) in addresses.iter().take(limit) {
+            x += format!("{:#010X}  {:>12} {:>11} {:>6.2}\n", a, p.executions, p.cycles, percent(p.cycles)).as_str();
+        }
+
+        x += "\n function         calls    own cycles  total cycles   total %\n";
+        let mut functions: Vec<(DoubleWord, FunctionProfile)> = self.functions().into_iter().collect();
+        functions.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(&b.0)));
+        for (f, p) in functions.iter() {
+            x += format!("{:#010X}  {:>10} {:>13} {:>13} {:>9.2}\n", f, p.calls, p.own, p.total, percent(p.total)).as_str();
+        }
+        x
+    }
+
+    // one `frame;frame;... cycles` line per call stack, as read by flamegraph.pl and friends
+    pub fn folded(&self) -> String {
+        let mut x = "".to_string();
+        for (stack, cycles) in self.stacks.iter() {
+            let frames: Vec<String> = stack.iter().map(|f| format!("{:#010X}", f)).collect();
+            x += format!("{} {}\n", frames.join(";"), cycles).as_str();
+        }
+        x
+    }
+
+    fn enter(&mut self, function: DoubleWord) {
+        self.stack.push(function);
+        *self.calls.entry(function).or_default() += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lib::ucode::cpu_assembly::CPUAssembly;
+
+    use super::Profiler;
+
+    fn run(p: &mut Profiler, address: u32, opcode: u8, cycles: u64, next: u32) {
+        for _ in 0..cycles { p.cycle(address); }
+        p.retire(address, opcode, next);
+    }
+
+    #[test]
+    fn recursion_counts_once_towards_the_total() {
+        let mut p = Profiler::new();
+        run(&mut p, 0x100, CPUAssembly::PSP, 1, 0x101);
+        run(&mut p, 0x101, CPUAssembly::JMP, 1, 0x200);
+        // the subroutine calls itself once
+        run(&mut p, 0x200, CPUAssembly::LDA, 2, 0x202);
+        run(&mut p, 0x202, CPUAssembly::PSP, 1, 0x203);
+        run(&mut p, 0x203, CPUAssembly::JMP, 1, 0x200);
+        run(&mut p, 0x200, CPUAssembly::LDA, 3, 0x204);
+        run(&mut p, 0x204, CPUAssembly::PLP, 1, 0x204);
+        run(&mut p, 0x204, CPUAssembly::PLP, 1, 0x102);
+        run(&mut p, 0x102, CPUAssembly::HLT, 1, 0x103);
+
+        assert_eq!(p.cycles(), 12);
+        let f = p.functions();
+        assert_eq!(f.len(), 2);
+        assert_eq!((f[&0x100].calls, f[&0x100].own, f[&0x100].total), (0, 3, 12));
+        assert_eq!((f[&0x200].calls, f[&0x200].own, f[&0x200].total), (2, 9, 9));
+        assert_eq!(p.addresses()[&0x200].executions, 2);
+        assert_eq!(p.addresses()[&0x200].cycles, 5);
+        assert_eq!(p.folded(), "0x00000100 3\n0x00000100;0x00000200 5\n0x00000100;0x00000200;0x00000200 4\n");
+    }
+
+    #[test]
+    fn plain_jumps_and_the_outermost_return_keep_the_stack() {
+        let mut p = Profiler::new();
+        run(&mut p, 0x100, CPUAssembly::JMP, 1, 0x200);
+        run(&mut p, 0x200, CPUAssembly::PLP, 1, 0x201);
+        run(&mut p, 0x201, CPUAssembly::HLT, 1, 0x202);
+        let f = p.functions();
+        assert_eq!(f.len(), 1);
+        assert_eq!(f[&0x100].own, 3);
+    }
+}
@@ -0,0 +1,307 @@
+use std::fs;
+use std::io::{BufRead, Write};
+
+use crate::lib::cpu::cpu::{CPU, Registers};
+use crate::lib::debug::backtrace;
+use crate::lib::debug::debugger::{DebugStop, Debugger, StopReason, WatchAction, WatchKind, Watchpoint};
+use crate::lib::debug::history::History;
+use crate::lib::debug::disassembler;
+use crate::lib::debug::format::{hexdump, parse};
+use crate::lib::debug::source::SourceMap;
+use crate::lib::debug::symbols::Symbols;
+use crate::lib::mem::{B, Byte, DoubleWord, Word};
+
+// line based front-end for a Debugger
+//
+// numbers are decimal or hex with a 0x prefix, ' and _ may be used as digit separators (0x1000'0000);
+// addresses may also be labels of the loaded symbols
+
+const HELP: &str = "\
+halt | h                     stop the cpu
+continue | c                 resume, stops are reported before the next prompt
+step | s [count]             execute count instructions (default 1)
+wait | w                     block until the cpu stops
+regs | r                     print registers and flags
+set <register> <value>       a, x, y, flags, sp, pc or a flag name (carry, zero, interrupt,
+                             decimal, break, overflow, negative)
+examine | x <address> [len]  hexdump memory (default 16 bytes, at most 65536)
+deposit | d <address> <byte>...
+break | b <address>          set breakpoint
+delete <address>             clear breakpoint
+breakpoints | bl             list breakpoints
+disassemble | u <address> [len]
+                             list len bytes of instructions (default 32)
+backtrace | bt               saved return addresses on the stack, innermost first
+symbols <file>               load labels, source lines and data ranges (see Symbols)
+watch <address> [len] [r|w|rw] [log]
+                             stop (or only log with `log`) when the cpu reads and / or writes
+                             len bytes from address (default 1 byte, writes)
+unwatch <address>            clear the watchpoint starting at address
+watchpoints | wl             list watchpoints
+record [count | off]         keep an undo log of the last count instructions (default 65536)
+rstep | rs [count]           undo count instructions (default 1)
+rcontinue | rc [address]     run backwards to a breakpoint or to the last write of address
+profile start | stop         count cycles per address and per subroutine (PSP + JMP ... PLP)
+profile report [count]       hottest count addresses (default 20) and all subroutines
+profile folded <file>        write the call stacks in the folded format of flamegraph.pl
+coverage start | stop        count executed instructions and taken / not taken branches
+coverage summary | listing <address> <len>
+                             covered share / annotated listing of the program at address
+coverage lcov <address> <len> <file> [source map]
+                             lcov tracefile, lines refer to the source map (or the lines of
+                             the loaded symbols), otherwise to the listing (written next to
+                             it as <file>.lst)
+quit | q                     detach, the machine keeps running
+";
+
+// longest memory range a single command reads
+const MAX_LENGTH: DoubleWord = 0x1_0000;
+
+const FLAGS: [(&str, usize); 7] = [
+    ("carry", CPU::CARRY),
+    ("zero", CPU::ZERO),
+    ("interrupt", CPU::INTERRUPT),
+    ("decimal", CPU::DECIMAL),
+    ("break", CPU::BREAK),
+    ("overflow", CPU::OVERFLOW),
+    ("negative", CPU::NEGATIVE),
+];
+
+// reads commands until `quit` or the end of input, the cpu is resumed when leaving
+pub fn run(debugger: &Debugger, symbols: Option<Symbols>, input: &mut dyn BufRead, output: &mut dyn Write) {
+    read_commands(debugger, symbols, input, output);
+    let _ = debugger.resume();
+}
+
+fn read_commands(debugger: &Debugger, mut symbols: Option<Symbols>, input: &mut dyn BufRead, output: &mut dyn Write) {
+    loop {
+        if let Some(stop) = debugger.poll() { let _ = write!(output, "{}", format_stop(&stop, symbols.as_ref())); }
+        let _ = write!(output, "(vmdbg) ");
+        let _ = output.flush();
+
+        let mut line = String::new();
+        let read = input.read_line(&mut line);
+        if read.is_err() || read.unwrap() == 0 { return; }
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() { continue; }
+        if args[0] == "quit" || args[0] == "q" { return; }
+        if args[0] == "symbols" && args.len() > 1 {
+            let x = Symbols::load(args[1]);
+            if x.is_ok() { symbols = x.ok(); } else { let _ = writeln!(output, "error: can't load symbols from {}", args[1]); }
+            continue;
+        }
+
+        let x = execute(debugger, symbols.as_ref(), &args);
+        let _ = match x {
+            Ok(s) => write!(output, "{}", s),
+            Err(e) => writeln!(output, "error: {}", e),
+        };
+    }
+}
+
+fn execute(debugger: &Debugger, symbols: Option<&Symbols>, args: &[&str]) -> Result<String, String> {
+    let ucode = |e: Byte| format!("uCode {:#04X}", e);
+    match args[0] {
+        "help" | "?" => Ok(HELP.to_string()),
+        "halt" | "h" => debugger.halt().map(|s| format_stop(&s, symbols)).map_err(ucode),
+        "continue" | "c" => debugger.resume().map(|_| "".to_string()).map_err(ucode),
+        "wait" | "w" => debugger.wait().map(|s| format_stop(&s, symbols)).map_err(ucode),
+        "step" | "s" => {
+            let count = if args.len() > 1 { parse(args[1])? } else { 1 };
+            let mut x = None;
+            for _ in 0..count {
+                let stop = debugger.step().map_err(ucode)?;
+                x = Some(stop);
+                if stop.reason != StopReason::Step { break; }
+            }
+            Ok(x.map_or("".to_string(), |s| format_stop(&s, symbols)))
+        }
+        "regs" | "r" => debugger.registers().map(|r| format_registers(&r)).map_err(ucode),
+        "set" => {
+            if args.len() < 3 { return Err("usage: set <register> <value>".to_string()); }
+            let value = parse(args[2])?;
+            let mut r = debugger.registers().map_err(ucode)?;
+            match args[1] {
+                "a" => r.a = value as Word,
+                "x" => r.x = value as Word,
+                "y" => r.y = value as Word,
+                "flags" => r.flags = value as Byte,
+                "sp" => r.sp = value,
+                "pc" => r.pc = value,
+                name => {
+                    let flag = FLAGS.iter().find(|f| f.0 == name);
+                    if flag.is_none() { return Err(format!("unknown register {}", name)); }
+                    let bit = flag.unwrap().1;
+                    r.flags = if value != 0 { r.flags.set_bit(bit) } else { r.flags.unset_bit(bit) };
+                }
+            }
+            debugger.set_registers(r).map_err(ucode)?;
+            Ok(format_registers(&r))
+        }
+        "examine" | "x" => {
+            if args.len() < 2 { return Err("usage: examine <address> [length]".to_string()); }
+            let address = resolve(args[1], symbols)?;
+            let length = if args.len() > 2 { length(args[2])? } else { 16 };
+            let data = debugger.read_memory(address, length as usize).map_err(ucode)?;
+            Ok(hexdump(address, &data))
+        }
+        "deposit" | "d" => {
+            if args.len() < 3 { return Err("usage: deposit <address> <byte>...".to_string()); }
+            let address = resolve(args[1], symbols)?;
+            let mut data = vec![];
+            for a in &args[2..] {
+                data.push(parse(a)? as Byte);
+            }
+            debugger.write_memory(address, &data).map(|_| "".to_string()).map_err(ucode)
+        }
+        "break" | "b" => {
+            if args.len() < 2 { return Err("usage: break <address>".to_string()); }
+            debugger.set_breakpoint(resolve(args[1], symbols)?).map(|_| "".to_string()).map_err(ucode)
+        }
+        "delete" => {
+            if args.len() < 2 { return Err("usage: delete <address>".to_string()); }
+            debugger.clear_breakpoint(resolve(args[1], symbols)?).map(|_| "".to_string()).map_err(ucode)
+        }
+        "breakpoints" | "bl" => {
+            let b = debugger.breakpoints().map_err(ucode)?;
+            Ok(b.iter().map(|a| format!("{:#010X} {}", a, describe(*a, symbols)).trim_end().to_string() + "\n").collect())
+        }
+        "disassemble" | "u" => {
+            if args.len() < 2 { return Err("usage: disassemble <address> [length]".to_string()); }
+            let address = resolve(args[1], symbols)?;
+            let length = if args.len() > 2 { length(args[2])? } else { 32 };
+            let data = debugger.read_memory(address, length as usize).map_err(ucode)?;
+            Ok(disassembler::listing(address, &data, symbols))
+        }
+        "backtrace" | "bt" => {
+            let r = debugger.registers().map_err(ucode)?;
+            let stack = debugger.read_memory(r.sp, backtrace::extent(r.sp)).map_err(ucode)?;
+            Ok(backtrace::format(r.pc, &backtrace::unwind(r.sp, &stack), symbols))
+        }
+        "watch" => {
+            if args.len() < 2 { return Err("usage: watch <address> [length] [r|w|rw] [log]".to_string()); }
+            let mut w = Watchpoint { start: resolve(args[1], symbols)?, size: 1, kind: WatchKind::Write, action: WatchAction::Break };
+            for a in &args[2..] {
+                match *a {
+                    "r" => w.kind = WatchKind::Read,
+                    "w" => w.kind = WatchKind::Write,
+                    "rw" => w.kind = WatchKind::Access,
+                    "log" => w.action = WatchAction::Log,
+                    n => w.size = parse(n)?,
+                }
+            }
+            debugger.set_watchpoint(w).map(|_| "".to_string()).map_err(ucode)
+        }
+        "unwatch" => {
+            if args.len() < 2 { return Err("usage: unwatch <address>".to_string()); }
+            debugger.clear_watchpoint(resolve(args[1], symbols)?).map(|_| "".to_string()).map_err(ucode)
+        }
+        "watchpoints" | "wl" => {
+            let w = debugger.watchpoints().map_err(ucode)?;
+            Ok(w.iter().map(|w| format!("{:#010X} +{} {:?} {:?}\n", w.start, w.size, w.kind, w.action)).collect())
+        }
+        "record" => {
+            let capacity = if args.len() < 2 { History::CAPACITY } else if args[1] == "off" { 0 } else { parse(args[1])? as usize };
+            debugger.record(capacity).map(|_| "".to_string()).map_err(ucode)
+        }
+        "rstep" | "rs" => {
+            let count = if args.len() > 1 { parse(args[1])? } else { 1 };
+            let mut x = None;
+            for _ in 0..count {
+                let stop = debugger.reverse_step();
+                // running out of history part way still reports where it got to
+                if stop.is_err() && x.is_some() { break; }
+                x = Some(stop.map_err(ucode)?);
+            }
+            Ok(x.map_or("".to_string(), |s| format_stop(&s, symbols)))
+        }
+        "rcontinue" | "rc" => {
+            let address = if args.len() > 1 { Some(resolve(args[1], symbols)?) } else { None };
+            debugger.reverse_continue(address).map(|s| format_stop(&s, symbols)).map_err(ucode)
+        }
+        "profile" => {
+            let usage = "usage: profile start | stop | report [count] | folded <file>".to_string();
+            if args.len() < 2 { return Err(usage); }
+            match args[1] {
+                "start" => debugger.set_profiling(true).map(|_| "".to_string()).map_err(ucode),
+                "stop" => debugger.set_profiling(false).map(|_| "".to_string()).map_err(ucode),
+                "report" => {
+                    let count = if args.len() > 2 { parse(args[2])? as usize } else { 20 };
+                    debugger.profile().map(|p| p.report(count)).map_err(ucode)
+                }
+                "folded" => {
+                    if args.len() < 3 { return Err(usage); }
+                    let p = debugger.profile().map_err(ucode)?;
+                    fs::write(args[2], p.folded()).map(|_| "".to_string()).map_err(|e| e.to_string())
+                }
+                _ => Err(usage)
+            }
+        }
+        "coverage" => {
+            let usage = "usage: coverage start | stop | summary <address> <len> | listing <address> <len> | lcov <address> <len> <file> [source map]".to_string();
+            if args.len() < 2 { return Err(usage); }
+            match args[1] {
+                "start" => return debugger.set_coverage(true).map(|_| "".to_string()).map_err(ucode),
+                "stop" => return debugger.set_coverage(false).map(|_| "".to_string()).map_err(ucode),
+                _ => ()
+            }
+            if args.len() < 4 { return Err(usage); }
+            let start = resolve(args[2], symbols)?;
+            let program = debugger.read_memory(start, length(args[3])? as usize).map_err(ucode)?;
+            let coverage = debugger.coverage().map_err(ucode)?;
+            match args[1] {
+                "summary" => Ok(coverage.summary(start, &program)),
+                "listing" => Ok(coverage.listing(start, &program)),
+                "lcov" => {
+                    if args.len() < 5 { return Err(usage); }
+                    let source = if args.len() > 5 { Some(SourceMap::load(args[5]).map_err(ucode)?) } else {
+                        symbols.map(|s| s.source().clone()).filter(|s| !s.is_empty())
+                    };
+                    let listing = format!("{}.lst", args[4]);
+                    if source.is_none() { fs::write(&listing, coverage.listing(start, &program)).map_err(|e| e.to_string())?; }
+                    fs::write(args[4], coverage.lcov(start, &program, source.as_ref(), &listing)).map_err(|e| e.to_string())?;
+                    Ok(coverage.summary(start, &program))
+                }
+                _ => Err(usage)
+            }
+        }
+        c => Err(format!("unknown command {}, try help", c))
+    }
+}
+
+// a number of bytes to read, at most MAX_LENGTH
+fn length(s: &str) -> Result<DoubleWord, String> {
+    let x = parse(s)?;
+    if x > MAX_LENGTH { return Err(format!("length {:#X} exceeds {:#X}", x, MAX_LENGTH)); }
+    Ok(x)
+}
+
+// a number or a label of the symbols
+fn resolve(s: &str, symbols: Option<&Symbols>) -> Result<DoubleWord, String> {
+    symbols.and_then(|x| x.lookup(s)).map_or_else(|| parse(s), Ok)
+}
+
+fn describe(address: DoubleWord, symbols: Option<&Symbols>) -> String {
+    symbols.map_or("".to_string(), |s| s.describe(address))
+}
+
+pub fn format_stop(stop: &DebugStop, symbols: Option<&Symbols>) -> String {
+    let reason = match stop.reason {
+        StopReason::Halted => "halted".to_string(),
+        StopReason::Step => "stepped".to_string(),
+        StopReason::Breakpoint(a) => format!("breakpoint {:#010X}", a),
+        StopReason::Exception(u) => format!("exception {:#04X}", u),
+        StopReason::Watchpoint(hit) => hit.format(),
+        StopReason::LastWrite(a) => format!("last write of {:#010X}", a),
+        StopReason::HistoryStart => "start of history".to_string(),
+    };
+    let x = format!("{} at {:#010X} {}", reason, stop.registers.pc, describe(stop.registers.pc, symbols));
+    x.trim_end().to_string() + "\n"
+}
+
+pub fn format_registers(r: &Registers) -> String {
+    let flags: Vec<&str> = FLAGS.iter().filter(|f| r.flags.is_set_bit(f.1)).map(|f| f.0).collect();
+    format!("a  {:#06X}  x  {:#06X}  y  {:#06X}\n\
+    sp {:#010X}  pc {:#010X}\n\
+    flags {:#010b} [{}]\n", r.a, r.x, r.y, r.sp, r.pc, r.flags, flags.join(" "))
+}
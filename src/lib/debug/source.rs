@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::lib::debug::format;
+use crate::lib::mem::{Byte, DoubleWord};
+use crate::lib::ucode::ucode::UCode;
+
+// maps instruction addresses back to the source they were assembled from
+//
+// text format, one instruction per line, # starts a comment:
+//   0x1000'0000 main.s:12
+
+#[derive(Clone)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: u32,
+}
+
+#[derive(Clone)]
+pub struct SourceMap {
+    lines: BTreeMap<DoubleWord, SourceLine>,
+}
+
+impl Default for SourceMap {
+    fn default() -> Self {
+        SourceMap::new()
+    }
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap { lines: BTreeMap::new() }
+    }
+
+    pub fn load(path: &str) -> Result<Self, Byte> {
+        let text = fs::read_to_string(path);
+        if text.is_err() { return Err(UCode::INVALID_SOURCE_MAP); }
+        SourceMap::parse(&text.unwrap())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Byte> {
+        let mut x = SourceMap::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() { continue; }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 { return Err(UCode::INVALID_SOURCE_MAP); }
+            let address = format::parse(fields[0]);
+            let position = fields[1].rsplit_once(':');
+            if address.is_err() || position.is_none() { return Err(UCode::INVALID_SOURCE_MAP); }
+            let (file, number) = position.unwrap();
+            let number = number.parse::<u32>();
+            if number.is_err() { return Err(UCode::INVALID_SOURCE_MAP); }
+            x.insert(address.unwrap(), file, number.unwrap());
+        }
+        Ok(x)
+    }
+}
+
+impl SourceMap {
+    pub fn insert(&mut self, address: DoubleWord, file: &str, line: u32) {
+        self.lines.insert(address, SourceLine { file: file.to_string(), line });
+    }
+
+    pub fn lookup(&self, address: DoubleWord) -> Option<&SourceLine> {
+        self.lines.get(&address)
+    }
+
+    // line of the instruction address belongs to (the closest entry at or below it)
+    pub fn nearest(&self, address: DoubleWord) -> Option<(DoubleWord, &SourceLine)> {
+        self.lines.range(..=address).next_back().map(|(a, l)| (*a, l))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&DoubleWord, &SourceLine)> {
+        self.lines.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
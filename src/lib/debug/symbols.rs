@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::lib::debug::format;
+use crate::lib::debug::source::{SourceLine, SourceMap};
+use crate::lib::mem::{Byte, DoubleWord};
+use crate::lib::ucode::ucode::UCode;
+
+// debug information of an assembled image, written next to it as <image>.sym (see ProgramBuilder)
+//
+// text format, one entry per line, # starts a comment:
+//   label 0x1000'0000 main
+//   line  0x1000'0000 main.s:12
+//   data  0x1000'0040 16 message
+// addresses are absolute, data ranges are `length` bytes that are not instructions
+
+#[derive(Clone)]
+pub struct DataRange {
+    pub start: DoubleWord,
+    pub length: DoubleWord,
+    pub name: String,
+}
+
+#[derive(Clone)]
+pub struct Symbols {
+    labels: BTreeMap<DoubleWord, String>,
+    source: SourceMap,
+    data: Vec<DataRange>,
+}
+
+impl Default for Symbols {
+    fn default() -> Self {
+        Symbols::new()
+    }
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols {
+            labels: BTreeMap::new(),
+            source: SourceMap::new(),
+            data: vec![],
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, Byte> {
+        let text = fs::read_to_string(path);
+        if text.is_err() { return Err(UCode::INVALID_SYMBOL_FILE); }
+        Symbols::parse(&text.unwrap())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Byte> {
+        let mut x = Symbols::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() { continue; }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 3 { return Err(UCode::INVALID_SYMBOL_FILE); }
+            let address = format::parse(fields[1]);
+            if address.is_err() { return Err(UCode::INVALID_SYMBOL_FILE); }
+            let address = address.unwrap();
+
+            match (fields[0], fields.len()) {
+                ("label", 3) => x.add_label(address, fields[2]),
+                ("line", 3) => {
+                    let position = fields[2].rsplit_once(':');
+                    let number = position.map(|p| p.1.parse::<u32>());
+                    if number.as_ref().is_none_or(|n| n.is_err()) { return Err(UCode::INVALID_SYMBOL_FILE); }
+                    x.add_line(address, position.unwrap().0, number.unwrap().unwrap());
+                }
+                ("data", 3) | ("data", 4) => {
+                    let length = format::parse(fields[2]);
+                    if length.is_err() { return Err(UCode::INVALID_SYMBOL_FILE); }
+                    // ranges must not run past the end of the address space
+                    if address.checked_add(length.as_ref().unwrap().saturating_sub(1)).is_none() { return Err(UCode::INVALID_SYMBOL_FILE); }
+                    x.add_data(address, length.unwrap(), fields.get(3).copied().unwrap_or(""));
+                }
+                _ => return Err(UCode::INVALID_SYMBOL_FILE)
+            }
+        }
+        Ok(x)
+    }
+}
+
+impl Symbols {
+    pub fn add_label(&mut self, address: DoubleWord, name: &str) {
+        self.labels.insert(address, name.to_string());
+    }
+
+    pub fn add_line(&mut self, address: DoubleWord, file: &str, line: u32) {
+        self.source.insert(address, file, line);
+    }
+
+    pub fn add_data(&mut self, start: DoubleWord, length: DoubleWord, name: &str) {
+        self.data.push(DataRange { start, length, name: name.to_string() });
+    }
+
+    // address of a label or named data range
+    pub fn lookup(&self, name: &str) -> Option<DoubleWord> {
+        let x = self.labels.iter().find(|l| l.1 == name).map(|l| *l.0);
+        if x.is_some() { return x; }
+        self.data.iter().find(|d| d.name == name).map(|d| d.start)
+    }
+
+    // `name` or `name+0x12` of the data range holding address or else the closest label at or below it
+    pub fn symbolize(&self, address: DoubleWord) -> Option<String> {
+        let data = self.data(address).filter(|d| !d.name.is_empty()).map(|d| (&d.start, &d.name));
+        let (start, name) = data.or_else(|| self.labels.range(..=address).next_back())?;
+        if *start == address { Some(name.clone()) } else { Some(format!("{}+{:#X}", name, address - start)) }
+    }
+
+    pub fn line(&self, address: DoubleWord) -> Option<&SourceLine> {
+        self.source.nearest(address).map(|l| l.1)
+    }
+
+    pub fn data(&self, address: DoubleWord) -> Option<&DataRange> {
+        self.data.iter().find(|d| address >= d.start && address - d.start < d.length)
+    }
+
+    pub fn source(&self) -> &SourceMap {
+        &self.source
+    }
+
+    // `<label+0x12> file.s:7`, empty when nothing is known about address
+    pub fn describe(&self, address: DoubleWord) -> String {
+        let label = self.symbolize(address).map(|l| format!("<{}>", l));
+        let line = self.line(address).map(|l| format!("{}:{}", l.file, l.line));
+        [label, line].into_iter().flatten().collect::<Vec<String>>().join(" ")
+    }
+
+    pub fn format(&self) -> String {
+        let mut x = "".to_string();
+        for (a, name) in self.labels.iter() {
+            x += format!("label {:#010X} {}\n", a, name).as_str();
+        }
+        for (a, l) in self.source.iter() {
+            x += format!("line  {:#010X} {}:{}\n", a, l.file, l.line).as_str();
+        }
+        for d in self.data.iter() {
+            x += format!("data  {:#010X} {} {}\n", d.start, d.length, d.name).as_str();
+        }
+        x
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Byte> {
+        if fs::write(path, self.format()).is_err() { return Err(UCode::INVALID_SYMBOL_FILE); }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::Symbols;
+
+    #[test]
+    fn parse_format_round_trip() {
+        let text = "label 0x10000000 main\n\
+                    label 0x10000011 idle\n\
+                    line  0x10000000 src/main.s:12\n\
+                    line  0x10000009 C:/main.s:13\n\
+                    data  0x05000000 17 gpu_stream\n";
+        let s = Symbols::parse(text).unwrap();
+        assert_eq!(s.format(), text);
+        assert_eq!(s.line(0x1000_000A).map(|l| (l.file.as_str(), l.line)), Some(("C:/main.s", 13)));
+        assert_eq!(Symbols::parse(&s.format()).unwrap().format(), text);
+    }
+
+    #[test]
+    fn parse_accepts_comments_and_separators() {
+        let s = Symbols::parse("# generated\n\n  label 0x1000'0004 loop  # inner\ndata 0x0500'0000 0x10\n").unwrap();
+        assert_eq!(s.lookup("loop"), Some(0x1000_0004));
+        assert_eq!(s.describe(0x1000_0006), "<loop+0x2>");
+        assert_eq!(s.data(0x0500_000F).map(|d| d.length), Some(0x10));
+        assert!(s.data(0x0500_0010).is_none());
+    }
+
+    #[test]
+    fn parse_rejects_malformed_lines() {
+        for text in ["label 0x10000000", "label zz main", "line 0x10000000 main.s", "line 0x10000000 main.s:x",
+                     "data 0x05000000 zz", "data 0xFFFFFFF0 0x11", "frame 0x10000000 main", "label 0x10000000 main extra"] {
+            assert_eq!(Symbols::parse(text).err(), Some(UCode::INVALID_SYMBOL_FILE), "{}", text);
+        }
+    }
+}
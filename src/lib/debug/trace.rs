@@ -0,0 +1,220 @@
+use std::io::{Read, Write};
+
+use crate::lib::cpu::cpu::Registers;
+use crate::lib::debug::disassembler;
+use crate::lib::mem::{B, Byte, DoubleWord, Word};
+use crate::lib::ucode::ucode::UCode;
+
+// log of the instructions the cpu executes, see CPU::set_tracer
+//
+// every record holds the running number of the instruction, the address it was fetched from, its
+// bytes and the registers after it finished, so two runs can be diffed record by record. the text
+// format is meant for reading, the binary format is compact and printed later with `pretty_print`
+//
+// binary: "VMXT" $version, then per record
+//   $index(8) $address(4) $length(1) $bytes(length) $a(2) $x(2) $y(2) $flags(1) $sp(4) $pc(4)
+// all big endian
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+pub struct ExecutionRecord {
+    // instructions retired before this one, filtered or not
+    pub index: u64,
+    pub address: DoubleWord,
+    // opcode followed by its operands
+    pub bytes: Vec<Byte>,
+    // after execution
+    pub registers: Registers,
+}
+
+pub struct ExecutionTracer {
+    out: Box<dyn Write + Send>,
+    format: TraceFormat,
+    // [start, end) address ranges that are traced, all when empty
+    ranges: Vec<(DoubleWord, DoubleWord)>,
+    retired: u64,
+    started: bool,
+}
+
+impl ExecutionTracer {
+    pub fn new(out: Box<dyn Write + Send>, format: TraceFormat) -> Self {
+        ExecutionTracer {
+            out,
+            format,
+            ranges: vec![],
+            retired: 0,
+            started: false,
+        }
+    }
+
+    pub const MAGIC: &'static [u8; 4] = b"VMXT";
+    pub const VERSION: Byte = 0x01;
+}
+
+impl ExecutionTracer {
+    // only instructions fetched from [start, end) are logged once a range was added
+    pub fn add_range(&mut self, start: DoubleWord, end: DoubleWord) {
+        self.ranges.push((start, end));
+    }
+
+    pub fn traces(&self, address: DoubleWord) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|(s, e)| address >= *s && address < *e)
+    }
+
+    // counts an instruction that is not traced
+    pub fn skip(&mut self) {
+        self.retired += 1;
+    }
+
+    pub fn record(&mut self, address: DoubleWord, bytes: &[Byte], registers: Registers) {
+        let r = ExecutionRecord { index: self.retired, address, bytes: bytes.to_vec(), registers };
+        self.retired += 1;
+
+        let mut x = vec![];
+        if self.format == TraceFormat::Binary {
+            if !self.started {
+                x.extend(ExecutionTracer::MAGIC);
+                x.push(ExecutionTracer::VERSION);
+            }
+            ExecutionTracer::encode(&r, &mut x);
+        } else {
+            x.extend(ExecutionTracer::format_record(&r).as_bytes());
+        }
+        self.started = true;
+        // every record is written right away, the cpu thread is never joined
+        let _ = self.out.write_all(&x);
+    }
+
+    pub fn format_record(r: &ExecutionRecord) -> String {
+        let text = disassembler::instruction(&r.bytes).map_or("???".to_string(), |i| i.0);
+        let flags: String = "NV-BDIZC".chars().enumerate()
+            .map(|(i, c)| if r.registers.flags.is_set_bit(7 - i) { c } else { '.' })
+            .collect();
+        format!("{:>10} {:#010X}  {:<32}  {:<32} a={:04X} x={:04X} y={:04X} {} sp={:08X} pc={:08X}\n",
+                r.index, r.address, disassembler::hex(&r.bytes), text,
+                r.registers.a, r.registers.x, r.registers.y, flags, r.registers.sp, r.registers.pc)
+    }
+
+    // reads a whole binary trace
+    pub fn read(input: &mut dyn Read) -> Result<Vec<ExecutionRecord>, Byte> {
+        let mut data = vec![];
+        if input.read_to_end(&mut data).is_err() { return Err(UCode::EXEC_TRACE_FAILURE); }
+        if data.len() < 5 || &data[0..4] != ExecutionTracer::MAGIC || data[4] != ExecutionTracer::VERSION {
+            return Err(UCode::EXEC_TRACE_FAILURE);
+        }
+
+        let mut records = vec![];
+        let mut at = 5;
+        while at < data.len() {
+            let r = ExecutionTracer::decode(&data, &mut at);
+            if r.is_none() { return Err(UCode::EXEC_TRACE_FAILURE); }
+            records.push(r.unwrap());
+        }
+        Ok(records)
+    }
+
+    // converts a binary trace to the text format
+    pub fn pretty_print(input: &mut dyn Read, output: &mut dyn Write) -> Result<(), Byte> {
+        let records = ExecutionTracer::read(input);
+        if records.is_err() { return Err(records.err().unwrap()); }
+        for r in records.unwrap().iter() {
+            if output.write_all(ExecutionTracer::format_record(r).as_bytes()).is_err() { return Err(UCode::EXEC_TRACE_FAILURE); }
+        }
+        Ok(())
+    }
+
+    fn encode(r: &ExecutionRecord, x: &mut Vec<Byte>) {
+        x.extend(r.index.to_be_bytes());
+        x.extend(r.address.to_be_bytes());
+        x.push(r.bytes.len() as Byte);
+        x.extend(&r.bytes);
+        x.extend(r.registers.a.to_be_bytes());
+        x.extend(r.registers.x.to_be_bytes());
+        x.extend(r.registers.y.to_be_bytes());
+        x.push(r.registers.flags);
+        x.extend(r.registers.sp.to_be_bytes());
+        x.extend(r.registers.pc.to_be_bytes());
+    }
+
+    fn decode(data: &[Byte], at: &mut usize) -> Option<ExecutionRecord> {
+        let mut take = |n: usize| -> Option<u64> {
+            if *at + n > data.len() { return None; }
+            let v = data[*at..*at + n].iter().fold(0u64, |v, b| v << 8 | *b as u64);
+            *at += n;
+            Some(v)
+        };
+        let index = take(8)?;
+        let address = take(4)? as DoubleWord;
+        let length = take(1)? as usize;
+        let mut bytes = vec![];
+        for _ in 0..length {
+            bytes.push(take(1)? as Byte);
+        }
+        let registers = Registers {
+            a: take(2)? as Word,
+            x: take(2)? as Word,
+            y: take(2)? as Word,
+            flags: take(1)? as Byte,
+            sp: take(4)? as DoubleWord,
+            pc: take(4)? as DoubleWord,
+        };
+        Some(ExecutionRecord { index, address, bytes, registers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use crate::lib::cpu::cpu::Registers;
+    use crate::lib::ucode::cpu_assembly::CPUAssembly;
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::{ExecutionTracer, TraceFormat};
+
+    // keeps what the tracer wrote readable after it was boxed
+    #[derive(Clone)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    fn registers(pc: u32) -> Registers {
+        Registers { a: 0xF00F, x: 0x1, y: 0x2, flags: 0x82, sp: 0x0FFF_FFFF, pc }
+    }
+
+    #[test]
+    fn binary_trace_round_trip() {
+        let out = Shared(Arc::new(Mutex::new(vec![])));
+        let mut tracer = ExecutionTracer::new(Box::new(out.clone()), TraceFormat::Binary);
+        tracer.add_range(0x1000_0000, 0x1000_0010);
+        tracer.record(0x1000_0000, &[CPUAssembly::LDA, 0xF0, 0x0F], registers(0x1000_0003));
+        assert!(!tracer.traces(0x1000_0010));
+        tracer.skip();
+        tracer.record(0x1000_0003, &[CPUAssembly::HLT], registers(0x1000_0004));
+
+        let data = out.0.lock().unwrap().clone();
+        assert_eq!(&data[0..5], b"VMXT\x01");
+        let records = ExecutionTracer::read(&mut data.as_slice()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].index, 0);
+        assert_eq!(records[0].address, 0x1000_0000);
+        assert_eq!(records[0].bytes, vec![CPUAssembly::LDA, 0xF0, 0x0F]);
+        assert_eq!(records[0].registers, registers(0x1000_0003));
+        assert_eq!(records[1].index, 2);
+        assert_eq!(records[1].bytes, vec![CPUAssembly::HLT]);
+
+        assert!(ExecutionTracer::read(&mut &data[..data.len() - 1]).err() == Some(UCode::EXEC_TRACE_FAILURE));
+        assert!(ExecutionTracer::read(&mut &b"VMXT\x02"[..]).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::lib::bus::bus::Bus;
+use crate::lib::bus::timing::BusMaster;
+use crate::lib::mem::{Byte, DoubleWord, Word};
+use crate::lib::mem::ram::RAM;
+use crate::lib::ucode::ucode::UCode;
+
+// direct memory access controller
+//
+// moves blocks between RAM and a bus device without the cpu copying byte by byte;
+// every cpu cycle each active channel transfers up to BURST bytes under a single bus lock.
+// once a channel finished an interrupt is raised with Bus::DMA as source, the channel
+// status can also be polled (see CPUAssembly::DMS)
+
+struct DMAChannel {
+    direction: Byte,
+    // wide address, devices behind bridges are reached through Bus::next_hop
+    device: DoubleWord,
+    address: DoubleWord,
+    remaining: Word,
+}
+
+pub struct DMA {
+    channels: Vec<Option<DMAChannel>>,
+    transferred: u64,
+}
+
+impl Default for DMA {
+    fn default() -> Self {
+        DMA::new()
+    }
+}
+
+impl DMA {
+    pub fn new() -> Self {
+        DMA {
+            channels: (0..DMA::CHANNELS).map(|_| None).collect(),
+            transferred: 0,
+        }
+    }
+
+    pub const CHANNELS: usize = 4;
+    // bytes moved per channel and cycle
+    pub const BURST: usize = 64;
+
+    // RAM => device
+    pub const OUT: Byte = 0x00;
+    // device => RAM
+    pub const IN: Byte = 0x01;
+}
+
+impl DMA {
+    pub fn start(&mut self, channel: Byte, direction: Byte, device: DoubleWord, address: DoubleWord, length: Word) -> Result<(), Byte> {
+        let c = self.channels.get_mut(channel as usize);
+        if c.is_none() { return Err(UCode::INVALID_DMA_CHANNEL); }
+        let c = c.unwrap();
+        if c.is_some() { return Err(UCode::DMA_CHANNEL_BUSY); }
+        if length == 0 { return Ok(()); }
+        *c = Some(DMAChannel { direction, device, address, remaining: length });
+        Ok(())
+    }
+
+    // bytes the channel still has to move, 0 once it is idle
+    pub fn remaining(&self, channel: Byte) -> Result<Word, Byte> {
+        let c = self.channels.get(channel as usize);
+        if c.is_none() { return Err(UCode::INVALID_DMA_CHANNEL); }
+        Ok(c.unwrap().as_ref().map_or(0, |c| c.remaining))
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.channels.iter().any(|c| c.is_some())
+    }
+
+    pub fn transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    // stops every transfer to or from a device that left the bus (a wide address, see Bus::take_unplugged),
+    // or that was reached through it
+    pub fn abort(&mut self, device: DoubleWord) {
+        let gone = Bus::route(device);
+        for c in self.channels.iter_mut() {
+            if c.as_ref().is_some_and(|c| Bus::route(c.device).starts_with(&gone)) { *c = None; }
+        }
+    }
+
+    // advances every active channel by one burst
+    pub fn step(&mut self, ram: &mut RAM, bus: &mut Bus) -> Result<(), Byte> {
+        let mut finished = false;
+        for slot in self.channels.iter_mut() {
+            if slot.is_none() { continue; }
+            // one burst per granted cycle, the others wait for the bus
+            if !bus.arbitrate(BusMaster::DMA) { continue; }
+            let c = slot.as_mut().unwrap();
+            let count = (c.remaining as usize).min(DMA::BURST);
+
+            while ram.is_locked() {};
+            ram.lock().unwrap();
+            let moved = if c.direction == DMA::OUT {
+                DMA::burst_out(c, count, ram, bus)
+            } else {
+                DMA::burst_in(c, count, ram, bus)
+            };
+            ram.unlock().unwrap();
+            if moved.is_err() { return Err(moved.err().unwrap()); }
+            let moved = moved.unwrap();
+            if moved > 0 { bus.occupy(BusMaster::DMA, Bus::segment_address(c.device), moved); }
+
+            c.address += moved as DoubleWord;
+            c.remaining -= moved as Word;
+            self.transferred += moved as u64;
+            if c.remaining == 0 {
+                *slot = None;
+                finished = true;
+            }
+        }
+        if finished { bus.interrupt(Bus::DMA); }
+        Ok(())
+    }
+
+    fn burst_out(c: &DMAChannel, count: usize, ram: &mut RAM, bus: &mut Bus) -> Result<usize, Byte> {
+        let mut data = Vec::with_capacity(count);
+        for i in 0..count {
+            let x = ram.fetch_byte(c.address as usize + i);
+            if x.is_err() { return Err(x.err().unwrap()); }
+            data.push(x.unwrap());
+        }
+        let res = bus.write_wide(BusMaster::DMA, c.device, &data);
+        if res.is_err() { return Err(res.err().unwrap()); }
+        Ok(count)
+    }
+
+    // takes whatever the device already sent, up to count bytes
+    fn burst_in(c: &DMAChannel, count: usize, ram: &mut RAM, bus: &mut Bus) -> Result<usize, Byte> {
+        let mut moved = 0;
+        while moved < count {
+            let x = bus.read_wide(BusMaster::DMA, c.device);
+            if x.is_err() { return Err(x.err().unwrap()); }
+            let x = x.unwrap();
+            if x.is_none() { break; }
+            let res = ram.write_byte(c.address as usize + moved, x.unwrap());
+            if res.is_err() { return Err(res.err().unwrap()); }
+            moved += 1;
+        }
+        Ok(moved)
+    }
+
+    pub fn stack_trace(&self) -> String {
+        let mut x = format!("dma    :     {} bytes moved\n", self.transferred);
+        for (i, c) in self.channels.iter().enumerate() {
+            if c.is_none() { continue; }
+            let c = c.as_ref().unwrap();
+            x += format!("dma {}  :     {} {:#010X} {:#010X} {} left\n",
+                         i, if c.direction == DMA::OUT { "out" } else { "in " }, c.device, c.address, c.remaining).as_str();
+        }
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::lib::bus::bus::Bus;
+    use crate::lib::bus::bus_device::BusDevice;
+    use crate::lib::mem::ram::RAM;
+    use crate::lib::chip_util::BlockingLock;
+
+    use super::DMA;
+
+    struct Sink;
+
+    impl BusDevice for Sink {
+        fn uuid(&self) -> String { "sink-0000-0000-0000".to_string() }
+        fn name(&self) -> String { "sink".to_string() }
+    }
+
+    #[test]
+    fn moves_blocks_to_devices_behind_a_bridge() {
+        let child = Arc::new(Mutex::new(Bus::new()));
+        let device = child.b_lock().register(Box::new(&Sink)).unwrap();
+        let mut bus = Bus::new();
+        let bridge = bus.bridge("bridge", "brg-0000-0000-0000", &child).unwrap();
+
+        let mut ram = RAM::new(0x100);
+        for i in 0..4 { ram.write_byte(0x10 + i, i as u8 + 1).unwrap(); }
+        let mut dma = DMA::new();
+        dma.start(0, DMA::OUT, 0xFFFF_0000 | (bridge as u32) << 8 | device as u32, 0x10, 4).unwrap();
+        dma.step(&mut ram, &mut bus).unwrap();
+        assert_eq!(dma.remaining(0).unwrap(), 0);
+        assert_eq!(child.b_lock().poll(device), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn unplugging_behind_a_bridge_aborts_the_transfer() {
+        let inner = Arc::new(Mutex::new(Bus::new()));
+        let device = inner.b_lock().register(Box::new(&Sink)).unwrap();
+        let child = Arc::new(Mutex::new(Bus::new()));
+        let other = child.b_lock().register(Box::new(&Sink)).unwrap();
+        let b1 = child.b_lock().bridge("inner", "brg-0000-0000-0001", &inner).unwrap();
+        let mut bus = Bus::new();
+        let b0 = bus.bridge("child", "brg-0000-0000-0000", &child).unwrap();
+
+        let mut dma = DMA::new();
+        let deep = 0xFF00_0000 | (b0 as u32) << 16 | (b1 as u32) << 8 | device as u32;
+        let near = 0xFFFF_0000 | (b0 as u32) << 8 | other as u32;
+        dma.start(0, DMA::IN, deep, 0x10, 4).unwrap();
+        dma.start(1, DMA::IN, near, 0x20, 4).unwrap();
+
+        inner.b_lock().unplug(device).unwrap();
+        let gone = bus.take_unplugged();
+        assert_eq!(gone, vec![deep]);
+        for a in gone { dma.abort(a); }
+        assert_eq!(dma.remaining(0).unwrap(), 0);
+        assert_eq!(dma.remaining(1).unwrap(), 4);
+        assert!(bus.take_unplugged().is_empty());
+
+        // everything behind a removed bridge goes with it
+        bus.unplug(b0).unwrap();
+        for a in bus.take_unplugged() { dma.abort(a); }
+        assert!(!dma.is_busy());
+    }
+}
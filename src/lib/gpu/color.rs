@@ -8,8 +8,19 @@ pub struct Color {
 }
 
 impl Color {
+    // rrrr'gggg_bbbb'aaaa
     pub fn as_word(&self) -> Word {
-        return 0b11001111;
+        ((self.red as Word >> 4) << 12) | ((self.green as Word >> 4) << 8) | ((self.blue as Word >> 4) << 4) | (self.alpha as Word >> 4)
+    }
+
+    pub fn from_word(word: Word) -> Self {
+        let nibble = |shift: u16| ((word >> shift) & 0xF) as u8 * 0x11;
+        Color {
+            red: nibble(12),
+            green: nibble(8),
+            blue: nibble(4),
+            alpha: nibble(0),
+        }
     }
 
     pub fn white() -> Self {
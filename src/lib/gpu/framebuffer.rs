@@ -0,0 +1,83 @@
+use std::sync::{Arc, Mutex};
+
+use crate::lib::bus::bus_device::BusDevice;
+use crate::lib::bus::device_class::DeviceClass;
+use crate::lib::chip_util::{BlockingLock, combine_to_word};
+use crate::lib::gpu::color::Color;
+use crate::lib::gpu::monitor::Monitor;
+use crate::lib::mem::{Byte, DoubleWord, W};
+
+// memory mapped view of a monitor, one 16 bit color word per pixel (rrrr'gggg_bbbb'aaaa),
+// row major, most significant byte first
+//
+// offset = (y * width + x) * 2
+
+pub struct Framebuffer {
+    uuid: String,
+    name: String,
+
+    monitor: Arc<Mutex<Monitor>>,
+}
+
+impl Framebuffer {
+    pub fn new(name: &str, uuid: &str, monitor: Arc<Mutex<Monitor>>) -> Self {
+        Framebuffer {
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            monitor,
+        }
+    }
+}
+
+impl Framebuffer {
+    // size of the mapped region in bytes
+    pub fn size(&self) -> DoubleWord {
+        let m = self.monitor.b_lock();
+        m.width() as DoubleWord * m.height() as DoubleWord * 2
+    }
+
+    // pixel coordinates of a byte offset, None outside of the monitor
+    fn pixel(m: &Monitor, offset: DoubleWord) -> Option<(u16, u16)> {
+        let index = offset / 2;
+        let (w, h) = (m.width() as DoubleWord, m.height() as DoubleWord);
+        if index >= w * h { return None; }
+        Some(((index % w) as u16, (index / w) as u16))
+    }
+}
+
+impl BusDevice for Framebuffer {
+    fn uuid(&self) -> String {
+        self.uuid.to_string()
+    }
+
+    fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn class(&self) -> Byte {
+        DeviceClass::FRAMEBUFFER
+    }
+
+    fn capabilities(&self) -> Byte {
+        DeviceClass::CAP_MMIO
+    }
+
+    fn mmio_read(&mut self, offset: DoubleWord) -> Byte {
+        let m = self.monitor.b_lock();
+        let p = Framebuffer::pixel(&m, offset);
+        if p.is_none() { return 0x0; }
+        let (x, y) = p.unwrap();
+        let word = m.read(x, y).as_word();
+        if offset.is_multiple_of(2) { word.significant_byte() } else { word.insignificant_byte() }
+    }
+
+    fn mmio_write(&mut self, offset: DoubleWord, byte: Byte) {
+        let mut m = self.monitor.b_lock();
+        let p = Framebuffer::pixel(&m, offset);
+        if p.is_none() { return; }
+        let (x, y) = p.unwrap();
+        let word = m.read(x, y).as_word();
+        let word = if offset.is_multiple_of(2) { combine_to_word(byte, word.insignificant_byte()) } else { combine_to_word(word.significant_byte(), byte) };
+        m.write(x, y, Color::from_word(word));
+    }
+}
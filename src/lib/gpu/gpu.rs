@@ -1,20 +1,20 @@
 use std::borrow::Borrow;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs::{read, read_to_string};
 use std::ops::{Deref, DerefMut};
 use std::process::exit;
 use std::sync::{Arc, Mutex};
 
-use queues::{IsQueue, Queue};
-
 use crate::lib::bus::bus::Bus;
 use crate::lib::bus::bus_device::BusDevice;
+use crate::lib::bus::trace::BusTracer;
+use crate::lib::bus::device_class::DeviceClass;
 use crate::lib::chip_util::{BlockingLock, combine_to_double_word, combine_to_word, map};
 use crate::lib::gpu::color::Color;
 use crate::lib::gpu::monitor::Monitor;
 use crate::lib::gpu::vector::Vector;
-use crate::lib::mem::{Byte, DoubleWord, Word};
+use crate::lib::mem::{Byte, DoubleWord, W, Word};
 use crate::lib::ucode::gpu_assembly::GPUAssembly;
 use crate::lib::ucode::ucode::UCode;
 
@@ -43,7 +43,8 @@ use crate::lib::ucode::ucode::UCode;
 
 pub struct GPU {
     address: Byte,
-    instruction_buffer: Queue<Byte>,
+    instruction_buffer: VecDeque<Byte>,
+    response_buffer: Vec<Byte>,
 
     uuid: String,
     name: String,
@@ -53,13 +54,20 @@ pub struct GPU {
 
     vertex_buffer_pointer: Option<Byte>,
     monitor_write_pointer: Option<Byte>,
+
+    // (width, height) of the attached monitors
+    monitors: Vec<(Word, Word)>,
+    // bytes of the running command and the last HISTORY commands, oldest first
+    current: Vec<Byte>,
+    history: VecDeque<Vec<Byte>>,
 }
 
 impl GPU {
     pub fn new(name: &str, uuid: &str) -> Self {
         GPU {
             address: 0x0,
-            instruction_buffer: Queue::new(),
+            instruction_buffer: VecDeque::new(),
+            response_buffer: vec![],
             uuid: uuid.to_string(),
             name: name.to_string(),
 
@@ -69,32 +77,60 @@ impl GPU {
             vertex_buffer_pointer: None,
 
             monitor_write_pointer: None,
+
+            monitors: vec![],
+            current: vec![],
+            history: VecDeque::with_capacity(GPU::HISTORY),
         }
     }
+
+    // executed commands kept for stack traces
+    pub const HISTORY: usize = 16;
 }
 
 impl GPU {
+    // registers the gpu ahead of launching it, so its address is known before its thread runs
+    pub fn register(&mut self, bus: &Arc<Mutex<Bus>>) -> Result<Byte, Byte> {
+        self.address = bus.b_lock().register(Box::new(&*self))?;
+        Ok(self.address)
+    }
+
     pub fn launch(&mut self, bus: &Arc<Mutex<Bus>>, displays: &mut [&mut Arc<Mutex<Monitor>>; 1]) {
-        self.address = bus.b_lock().register(Box::new(self));
+        let registered = bus.b_lock().is_registered(self.address, &self.uuid);
+        if !registered {
+            let address = self.register(bus);
+            if address.is_err() { self.raise_exception(address.err().unwrap()) }
+        }
 
         for d in displays.iter() {
             let w = d.b_lock().width() as usize;
             let h = d.b_lock().height() as usize;
             let v = vec![vec![0x0; w]; h];
             self.display_buffer.push(v);
+            self.monitors.push((w as Word, h as Word));
         }
 
-        let mut instruction: Byte = GPUAssembly::HLT;
-        let mut finished_instruction: bool = true;
-
         loop {
-            let x = bus.b_lock().poll(self.address);
+            // unplugged from the bus, stop the device
+            let x = {
+                let mut b = bus.b_lock();
+                if !b.is_registered(self.address, &self.uuid) { return; }
+                b.poll(self.address)
+            };
             self.queue_to_buffer(x);
 
-            let x = self.fetch_instruction_byte();
-            if x.is_ok() { instruction = x.unwrap(); } else { self.raise_exception(x.err().unwrap()) }
-            let res = self.execute(instruction, displays);
-            if res.is_ok() { finished_instruction = res.unwrap() } else { self.raise_exception(res.err().unwrap()) }
+            while self.instruction_ready() {
+                self.current.clear();
+                let x = self.fetch_instruction_byte();
+                if x.is_err() { self.raise_exception(x.err().unwrap()) }
+                let res = self.execute(x.unwrap(), displays);
+                if res.is_err() { self.raise_exception(res.err().unwrap()) }
+                self.retire();
+            }
+            if !self.response_buffer.is_empty() {
+                bus.b_lock().respond(self.address, &self.response_buffer);
+                self.response_buffer.clear();
+            }
         }
     }
 
@@ -104,17 +140,61 @@ impl GPU {
     }
 
     fn stack_trace(&self) -> String {
-        "[TODO] - todo!".to_string()
+        let pointer = |p: Option<Byte>| p.map_or("none".to_string(), |p| format!("{:#04X}", p));
+        let mut x = format!("-----------------------\n\
+        address:     {:#04X}\n\
+        pending:     {}\n\
+        vertex :     {}\n\
+        monitor:     {}\n",
+                            self.address,
+                            self.instruction_buffer.len(),
+                            pointer(self.vertex_buffer_pointer),
+                            pointer(self.monitor_write_pointer));
+
+        let mut buffers: Vec<(&Byte, &Vec<Vector>)> = self.vertex_buffer.iter().collect();
+        buffers.sort_by_key(|b| b.0);
+        for (id, v) in buffers {
+            x += format!("buffer {:<4}:  {} vertices\n", format!("{:#04X}", id), v.len()).as_str();
+        }
+        for (i, (w, h)) in self.monitors.iter().enumerate() {
+            x += format!("display {:<3}:  {}x{}\n", i, w, h).as_str();
+        }
+
+        x += "history:\n";
+        for c in self.history.iter() {
+            x += BusTracer::decode_gpu(c).as_str();
+        }
+        // the command that raised the exception, possibly missing operands
+        if !self.current.is_empty() {
+            x += "running:\n";
+            x += BusTracer::decode_gpu(&self.current).as_str();
+        }
+        if !self.instruction_buffer.is_empty() {
+            x += "queued :\n";
+            x += BusTracer::decode_gpu(&self.instruction_buffer.iter().copied().collect::<Vec<Byte>>()).as_str();
+        }
+        x
+    }
+
+    // the running command finished
+    fn retire(&mut self) {
+        if self.current.is_empty() { return; }
+        if self.history.len() == GPU::HISTORY { self.history.pop_front(); }
+        self.history.push_back(std::mem::take(&mut self.current));
+    }
+
+    // a command is only executed once all of its operands arrived over the bus
+    fn instruction_ready(&self) -> bool {
+        let x = self.instruction_buffer.front();
+        if x.is_none() { return false; }
+        self.instruction_buffer.len() > GPUAssembly::operand_size(*x.unwrap())
     }
 
     fn fetch_instruction_byte(&mut self) -> Result<Byte, Byte> {
-        if self.instruction_buffer.size() <= 0 { return Ok(GPUAssembly::HLT); }
-        let x = self.instruction_buffer.peek();
-        if x.is_err() { return Err(UCode::INVALID_BUFFER_ACCESS); }
-        let a = x.unwrap().to_owned();
-        let r = self.instruction_buffer.remove();
-        if r.is_err() { return Err(UCode::INVALID_BUFFER_ACCESS); }
-        Ok(a)
+        let x = self.instruction_buffer.pop_front();
+        if x.is_none() { return Err(UCode::INVALID_BUFFER_ACCESS); }
+        self.current.push(x.unwrap());
+        Ok(x.unwrap())
     }
     fn fetch_instruction_word(&mut self) -> Result<Word, Byte> {
         let x1 = self.fetch_instruction_byte();
@@ -141,10 +221,7 @@ impl GPU {
     }
 
     fn queue_to_buffer(&mut self, data: Vec<Byte>) {
-        for i in data {
-            let x = self.instruction_buffer.add(i);
-            if x.is_err() { self.raise_exception(UCode::UNKNOWN_EXCEPTION) }
-        }
+        self.instruction_buffer.extend(data);
     }
 
     fn coincide(&self, data: &Vec<Vector>, x: u8, y: u8) -> Option<Color> {
@@ -160,10 +237,19 @@ impl GPU {
                 println!("{}", self.stack_trace());
                 Ok(true)
             }
+            GPUAssembly::CAP => {
+                self.response_buffer.push(displays.len() as Byte);
+                for d in displays.iter() {
+                    let (w, h) = { let d = d.b_lock(); (d.width(), d.height()) };
+                    self.response_buffer.extend([w.significant_byte(), w.insignificant_byte(), h.significant_byte(), h.insignificant_byte()]);
+                }
+                Ok(true)
+            }
             GPUAssembly::BVB => {
                 let x = self.fetch_instruction_byte();
                 if x.is_err() { return Err(x.err().unwrap()); }
                 self.vertex_buffer_pointer = Some(x.unwrap());
+                self.vertex_buffer.entry(x.unwrap()).or_default();
 
                 let x = self.fetch_instruction_byte();
                 if x.is_err() { return Err(x.err().unwrap()); }
@@ -199,6 +285,8 @@ impl GPU {
                     Some(z.unwrap()),
                 );
 
+                // no buffer bound with BVB
+                if self.vertex_buffer_pointer.is_none() { return Err(UCode::INVALID_BUFFER_ACCESS); }
                 let x = self.vertex_buffer.get_mut(&self.vertex_buffer_pointer.unwrap());
                 if x.is_none() { return Err(UCode::INVALID_BUFFER_ACCESS); }
                 x.unwrap().push(vertex);
@@ -207,12 +295,17 @@ impl GPU {
             GPUAssembly::DRW => {
                 let mut to_write: HashMap<Byte, Vec<(usize, usize, Color)>> = HashMap::new();
                 for i in self.vertex_buffer.iter() {
-                    let monitor = i.1.first().unwrap().monitor;
-                    let mut m = &mut displays[monitor as usize];
-                    for x in 0..m.b_lock().width().to_owned() {
-                        for y in 0..m.b_lock().height().to_owned() {
-                            println!("jej");
-                            let coincide = self.coincide(i.1, map(x, 0..m.b_lock().width(), 0..255), map(y, 0..m.b_lock().height(), 0..255));
+                    // bound but no vertex pushed yet
+                    let first = i.1.first();
+                    if first.is_none() { continue; }
+                    let monitor = first.unwrap().monitor;
+                    let m = displays.get_mut(monitor as usize);
+                    if m.is_none() { return Err(UCode::MONITOR_NOT_FOUND); }
+                    let m = m.unwrap();
+                    let (w, h) = { let d = m.b_lock(); (d.width(), d.height()) };
+                    for x in 0..w {
+                        for y in 0..h {
+                            let coincide = self.coincide(i.1, map(x, 0..w, 0..255), map(y, 0..h, 0..255));
                             if coincide.is_none() { continue; }
 
                             let c = coincide.unwrap();
@@ -243,4 +336,114 @@ impl BusDevice for GPU {
     fn name(&self) -> String {
         self.name.to_string()
     }
-}
\ No newline at end of file
+
+    fn class(&self) -> Byte {
+        DeviceClass::DISPLAY
+    }
+
+    fn capabilities(&self) -> Byte {
+        DeviceClass::CAP_RESPONDS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::lib::chip_util::BlockingLock;
+    use crate::lib::gpu::color::Color;
+    use crate::lib::gpu::monitor::Monitor;
+    use crate::lib::mem::Byte;
+    use crate::lib::ucode::gpu_assembly::GPUAssembly;
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::GPU;
+
+    // a 4x4 display, small enough to draw quickly
+    fn gpu() -> (GPU, Arc<Mutex<Monitor>>) {
+        let mut g = GPU::new("gpu", "gpu-0000-0000-0000");
+        g.display_buffer.push(vec![vec![0x0; 4]; 4]);
+        g.monitors.push((4, 4));
+        (g, Arc::new(Mutex::new(Monitor::new(4, 4))))
+    }
+
+    // runs every complete command, stops at the first error
+    fn run(g: &mut GPU, m: &mut Arc<Mutex<Monitor>>, bytes: &[Byte]) -> Result<(), Byte> {
+        g.queue_to_buffer(bytes.to_vec());
+        while g.instruction_ready() {
+            let x = g.fetch_instruction_byte();
+            if x.is_err() { return Err(x.err().unwrap()); }
+            let res = g.execute(x.unwrap(), &mut [m]);
+            if res.is_err() { return Err(res.err().unwrap()); }
+            g.retire();
+        }
+        Ok(())
+    }
+
+    fn vrx() -> Vec<Byte> {
+        vec![GPUAssembly::VRX, 0x0, 0x1, 0x0, 0x1, 0xFF, 0xFF, 0x0, 0x0, 0x0, 0x0, 0x0]
+    }
+
+    #[test]
+    fn stack_trace_shows_state_and_recent_commands() {
+        let (mut g, mut m) = gpu();
+        let mut x = vec![GPUAssembly::BVB, 0x2, 0x0];
+        x.extend(vrx());
+        x.extend(vrx());
+        assert_eq!(run(&mut g, &mut m, &x), Ok(()));
+        // a command still waiting for operands
+        g.queue_to_buffer(vec![GPUAssembly::VRX, 0x0, 0x1]);
+
+        let t = g.stack_trace();
+        assert!(t.contains("vertex :     0x02\n"), "{}", t);
+        assert!(t.contains("monitor:     0x00\n"), "{}", t);
+        assert!(t.contains("buffer 0x02:  2 vertices\n"), "{}", t);
+        assert!(t.contains("display 0  :  4x4\n"), "{}", t);
+        let history = &t[t.find("history:").unwrap()..t.find("queued :").unwrap()];
+        assert_eq!(history.lines().count(), 4, "{}", t);
+        assert!(history.lines().nth(1).unwrap().trim().starts_with("BVB 02 00"), "{}", t);
+        assert!(t.ends_with(&format!("queued :\n    {:02X} 00 01 (incomplete)\n", GPUAssembly::VRX)), "{}", t);
+    }
+
+    #[test]
+    fn capabilities_list_every_display() {
+        let (mut g, mut m) = gpu();
+        assert_eq!(run(&mut g, &mut m, &[GPUAssembly::CAP]), Ok(()));
+        assert_eq!(g.response_buffer, vec![0x1, 0x0, 0x4, 0x0, 0x4]);
+    }
+
+    #[test]
+    fn vertices_need_a_bound_buffer() {
+        let (mut g, mut m) = gpu();
+        assert_eq!(run(&mut g, &mut m, &vrx()), Err(UCode::INVALID_BUFFER_ACCESS));
+
+        let (mut g, mut m) = gpu();
+        let mut x = vec![GPUAssembly::BVB, 0x0, 0x0];
+        x.extend(vrx());
+        x.extend([GPUAssembly::UVB]);
+        x.extend(vrx());
+        assert_eq!(run(&mut g, &mut m, &x), Err(UCode::INVALID_BUFFER_ACCESS));
+        assert_eq!(g.vertex_buffer[&0x0].len(), 1);
+    }
+
+    #[test]
+    fn draw_skips_empty_buffers() {
+        let (mut g, mut m) = gpu();
+        assert_eq!(run(&mut g, &mut m, &[GPUAssembly::BVB, 0x0, 0x0, GPUAssembly::DRW]), Ok(()));
+        assert_eq!(m.b_lock().read(0, 0).as_word(), Color::black().as_word());
+
+        let mut x = vrx();
+        x.push(GPUAssembly::DRW);
+        assert_eq!(run(&mut g, &mut m, &x), Ok(()));
+        assert_eq!(m.b_lock().read(0, 0).as_word(), Color::white().as_word());
+    }
+
+    #[test]
+    fn draw_rejects_unknown_monitors() {
+        let (mut g, mut m) = gpu();
+        let mut x = vec![GPUAssembly::BVB, 0x0, 0x7];
+        x.extend(vrx());
+        x.push(GPUAssembly::DRW);
+        assert_eq!(run(&mut g, &mut m, &x), Err(UCode::MONITOR_NOT_FOUND));
+    }
+}
@@ -1,5 +1,6 @@
 pub mod gpu;
 pub mod vector;
 pub mod color;
+pub mod framebuffer;
 
 pub mod monitor;
\ No newline at end of file
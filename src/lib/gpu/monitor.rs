@@ -1,7 +1,9 @@
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use sdl2::rect::Point;
 
+use crate::lib::chip_util::BlockingLock;
 use crate::lib::gpu::color::Color;
 
 pub struct Monitor {
@@ -22,14 +24,21 @@ impl Monitor {
     }
 
     pub fn write(&mut self, x: u16, y: u16, color: Color) {
-        println!("{}:{} -> {}", x, y, color.as_word());
-        let x = self.data.get_mut(x as usize).unwrap().insert(y as usize, color);
+        let column = self.data.get_mut(x as usize);
+        if column.is_none() { return; }
+        if let Some(pixel) = column.unwrap().get_mut(y as usize) { *pixel = color; }
+    }
+
+    pub fn read(&self, x: u16, y: u16) -> Color {
+        self.data.get(x as usize).and_then(|c| c.get(y as usize)).cloned().unwrap_or(Color::black())
     }
 
     pub fn width(&self) -> u16 { self.width }
     pub fn height(&self) -> u16 { self.height }
 
-    pub fn launch(&self) {
+    // the monitor is only locked while a frame is copied to the canvas,
+    // so the gpu and memory mapped framebuffer can write in between
+    pub fn launch(monitor: &Arc<Mutex<Monitor>>) {
         let sdl_context = sdl2::init().unwrap();
         let video_subsystem = sdl_context.video().unwrap();
 
@@ -45,11 +54,14 @@ impl Monitor {
         loop {
             canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
             canvas.clear();
-            for x in 0..self.height {
-                for y in 0..self.width {
-                    let c = self.data.get(x as usize).unwrap().get(y as usize).unwrap();
-                    canvas.set_draw_color(sdl2::pixels::Color::RGB(c.r(), c.g(), c.b()));
-                    canvas.draw_point(Point::new(x as i32, y as i32));
+            {
+                let m = monitor.b_lock();
+                for x in 0..m.width {
+                    for y in 0..m.height {
+                        let c = m.data.get(x as usize).unwrap().get(y as usize).unwrap();
+                        canvas.set_draw_color(sdl2::pixels::Color::RGB(c.r(), c.g(), c.b()));
+                        canvas.draw_point(Point::new(x as i32, y as i32));
+                    }
                 }
             }
             canvas.present();
@@ -0,0 +1,480 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::exit;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::lib::bus::bus::Bus;
+use crate::lib::bus::bus_device::BusDevice;
+use crate::lib::bus::device_class::DeviceClass;
+use crate::lib::chip_util::{BlockingLock, combine_to_word};
+use crate::lib::mem::{Byte, D, DoubleWord, W, Word};
+use crate::lib::ucode::hostfs_assembly::HostFSAssembly;
+use crate::lib::ucode::ucode::UCode;
+
+// sandboxed passthrough to one host directory
+//
+// every reply starts with a status byte, HostFS::OK on success or one of the
+// HOSTFS_* uCodes otherwise; no further reply bytes follow a failed status.
+// guest paths are always resolved relative to the root, `..` may not leave it
+// and symlinks pointing outside of it are rejected
+
+pub struct HostFS {
+    address: Byte,
+    instruction_buffer: VecDeque<Byte>,
+    response_buffer: VecDeque<Byte>,
+
+    uuid: String,
+    name: String,
+
+    root: PathBuf,
+    handles: BTreeMap<Byte, File>,
+}
+
+impl HostFS {
+    pub fn new(name: &str, uuid: &str, root: &str) -> Result<Self, Byte> {
+        let root = Path::new(root).canonicalize();
+        if root.is_err() { return Err(UCode::HOSTFS_NOT_FOUND); }
+        Ok(HostFS {
+            address: 0x0,
+            instruction_buffer: VecDeque::new(),
+            response_buffer: VecDeque::new(),
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            root: root.unwrap(),
+            handles: BTreeMap::new(),
+        })
+    }
+
+    pub const OK: Byte = 0x00;
+
+    pub const READ: Byte = 0x01;
+    pub const WRITE: Byte = 0x02;
+    pub const APPEND: Byte = 0x04;
+
+    pub const FILE: Byte = 0x00;
+    pub const DIRECTORY: Byte = 0x01;
+    pub const LINK: Byte = 0x02;
+
+    pub const MAX_HANDLES: usize = 16;
+}
+
+impl HostFS {
+    pub fn launch(&mut self, bus: &Arc<Mutex<Bus>>) {
+        let address = bus.b_lock().register(Box::new(self));
+        if address.is_err() { self.raise_exception(address.err().unwrap()) }
+        self.address = address.unwrap();
+
+        loop {
+            // unplugged from the bus, stop the device
+            let x = {
+                let mut b = bus.b_lock();
+                if !b.is_registered(self.address, &self.uuid) { return; }
+                b.poll(self.address)
+            };
+            self.instruction_buffer.extend(x);
+
+            while self.instruction_ready() {
+                let res = self.execute();
+                if res.is_err() { self.raise_exception(res.err().unwrap()) }
+                if !self.response_buffer.is_empty() {
+                    let r: Vec<Byte> = self.response_buffer.drain(..).collect();
+                    bus.b_lock().respond(self.address, &r);
+                }
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn raise_exception(&self, ucode: Byte) {
+        println!("exception code: {:X} raised;\n{}", ucode, self.stack_trace());
+        exit(ucode as i32)
+    }
+
+    fn stack_trace(&self) -> String {
+        format!("-----------------------\n\
+        address:     {:#04X}\n\
+        root   :     {}\n\
+        pending:     {}\n\
+        replies:     {}\n\
+        handles:     {:?}",
+                self.address,
+                self.root.display(),
+                self.instruction_buffer.len(),
+                self.response_buffer.len(),
+                self.handles.keys().collect::<Vec<_>>())
+    }
+
+    fn peek_word(&self, index: usize) -> Option<Word> {
+        let sig = self.instruction_buffer.get(index);
+        let insig = self.instruction_buffer.get(index + 1);
+        if sig.is_none() || insig.is_none() { return None; }
+        Some(combine_to_word(*sig.unwrap(), *insig.unwrap()))
+    }
+
+    // size of the next instruction including its opcode, None until its length is known
+    fn instruction_size(&self) -> Option<usize> {
+        match *self.instruction_buffer.front()? {
+            HostFSAssembly::OPN | HostFSAssembly::WRT => self.peek_word(2).map(|l| 4 + l as usize),
+            HostFSAssembly::LST | HostFSAssembly::STA => self.peek_word(1).map(|l| 3 + l as usize),
+            HostFSAssembly::RED => Some(4),
+            HostFSAssembly::CLS => Some(2),
+            _ => Some(1),
+        }
+    }
+
+    // an instruction is only executed once all of its operands arrived over the bus
+    fn instruction_ready(&self) -> bool {
+        let size = self.instruction_size();
+        size.is_some() && self.instruction_buffer.len() >= size.unwrap()
+    }
+
+    fn fetch_instruction_byte(&mut self) -> Result<Byte, Byte> {
+        let x = self.instruction_buffer.pop_front();
+        if x.is_none() { return Err(UCode::INVALID_BUFFER_ACCESS); }
+        Ok(x.unwrap())
+    }
+    fn fetch_instruction_word(&mut self) -> Result<Word, Byte> {
+        let x1 = self.fetch_instruction_byte();
+        if x1.is_err() { return Err(x1.err().unwrap()); }
+        let x2 = self.fetch_instruction_byte();
+        if x2.is_err() { return Err(x2.err().unwrap()); }
+        Ok(combine_to_word(x1.unwrap(), x2.unwrap()))
+    }
+    fn fetch_instruction_bytes(&mut self, count: usize) -> Result<Vec<Byte>, Byte> {
+        if self.instruction_buffer.len() < count { return Err(UCode::INVALID_BUFFER_ACCESS); }
+        Ok(self.instruction_buffer.drain(..count).collect())
+    }
+    fn fetch_block(&mut self) -> Result<Vec<Byte>, Byte> {
+        let length = self.fetch_instruction_word();
+        if length.is_err() { return Err(length.err().unwrap()); }
+        self.fetch_instruction_bytes(length.unwrap() as usize)
+    }
+
+    fn respond_word(&mut self, word: Word) {
+        self.response_buffer.push_back(word.significant_byte());
+        self.response_buffer.push_back(word.insignificant_byte());
+    }
+    fn respond_double_word(&mut self, dword: DoubleWord) {
+        self.respond_word(dword.significant_word());
+        self.respond_word(dword.insignificant_word());
+    }
+
+    fn io_error(kind: ErrorKind) -> Byte {
+        match kind {
+            ErrorKind::NotFound => UCode::HOSTFS_NOT_FOUND,
+            ErrorKind::PermissionDenied => UCode::HOSTFS_PERMISSION_DENIED,
+            _ => UCode::HOSTFS_IO_FAILURE
+        }
+    }
+
+    // maps a guest path onto the host, refusing anything that would end up outside of the root
+    pub fn resolve(&self, path: &[Byte]) -> Result<PathBuf, Byte> {
+        let path = std::str::from_utf8(path);
+        if path.is_err() { return Err(UCode::HOSTFS_INVALID_PATH); }
+
+        let mut resolved = self.root.clone();
+        for c in Path::new(path.unwrap()).components() {
+            match c {
+                Component::Normal(x) => resolved.push(x),
+                Component::ParentDir => {
+                    if resolved == self.root { return Err(UCode::HOSTFS_PATH_ESCAPES_ROOT); }
+                    resolved.pop();
+                }
+                _ => ()
+            }
+        }
+
+        // follow symlinks of whatever already exists, the checked path is the one handed out
+        // so a link swapped in afterwards can't redirect it. a dangling link would be followed on create
+        let canonical = if resolved.exists() {
+            resolved.canonicalize().ok()
+        } else if resolved.symlink_metadata().is_ok() {
+            return Err(UCode::HOSTFS_PATH_ESCAPES_ROOT);
+        } else {
+            let parent = resolved.parent().and_then(|p| p.canonicalize().ok());
+            parent.zip(resolved.file_name()).map(|(p, f)| p.join(f))
+        };
+        if canonical.is_none() { return Err(UCode::HOSTFS_NOT_FOUND); }
+        if !canonical.as_ref().unwrap().starts_with(&self.root) { return Err(UCode::HOSTFS_PATH_ESCAPES_ROOT); }
+        Ok(canonical.unwrap())
+    }
+
+    fn open(&mut self, mode: Byte, path: &[Byte]) -> Result<Byte, Byte> {
+        if mode == 0 || mode & !(HostFS::READ | HostFS::WRITE | HostFS::APPEND) != 0 { return Err(UCode::HOSTFS_INVALID_MODE); }
+        let handle = (0..HostFS::MAX_HANDLES as Byte).find(|h| !self.handles.contains_key(h));
+        if handle.is_none() { return Err(UCode::HOSTFS_TOO_MANY_HANDLES); }
+
+        let path = self.resolve(path);
+        if path.is_err() { return Err(path.err().unwrap()); }
+
+        let file = OpenOptions::new()
+            .read(mode & HostFS::READ != 0)
+            .write(mode & HostFS::WRITE != 0)
+            .append(mode & HostFS::APPEND != 0)
+            .create(mode & (HostFS::WRITE | HostFS::APPEND) != 0)
+            .truncate(mode & HostFS::WRITE != 0 && mode & HostFS::APPEND == 0)
+            .open(path.unwrap());
+        if file.is_err() { return Err(HostFS::io_error(file.err().unwrap().kind())); }
+
+        self.handles.insert(handle.unwrap(), file.unwrap());
+        Ok(handle.unwrap())
+    }
+
+    fn list(&self, path: &[Byte]) -> Result<Vec<(Byte, String)>, Byte> {
+        let path = self.resolve(path);
+        if path.is_err() { return Err(path.err().unwrap()); }
+        let entries = path.unwrap().read_dir();
+        if entries.is_err() { return Err(HostFS::io_error(entries.err().unwrap().kind())); }
+
+        let mut x = vec![];
+        for e in entries.unwrap().flatten() {
+            // links are not followed, their targets may lie outside of the root
+            let t = e.path().symlink_metadata().map(|m| m.file_type());
+            let kind = match t {
+                Ok(t) if t.is_symlink() => HostFS::LINK,
+                Ok(t) if t.is_dir() => HostFS::DIRECTORY,
+                _ => HostFS::FILE,
+            };
+            x.push((kind, e.file_name().to_string_lossy().to_string()));
+        }
+        x.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(x)
+    }
+}
+
+impl HostFS {
+    fn execute(&mut self) -> Result<(), Byte> {
+        let opcode = self.fetch_instruction_byte();
+        if opcode.is_err() { return Err(opcode.err().unwrap()); }
+
+        match opcode.unwrap() {
+            HostFSAssembly::HLT => Ok(()),
+            HostFSAssembly::OPN => {
+                let mode = self.fetch_instruction_byte();
+                if mode.is_err() { return Err(mode.err().unwrap()); }
+                let path = self.fetch_block();
+                if path.is_err() { return Err(path.err().unwrap()); }
+
+                match self.open(mode.unwrap(), &path.unwrap()) {
+                    Ok(h) => self.response_buffer.extend([HostFS::OK, h]),
+                    Err(e) => self.response_buffer.push_back(e),
+                }
+                Ok(())
+            }
+            HostFSAssembly::RED => {
+                let handle = self.fetch_instruction_byte();
+                if handle.is_err() { return Err(handle.err().unwrap()); }
+                let length = self.fetch_instruction_word();
+                if length.is_err() { return Err(length.err().unwrap()); }
+
+                let file = self.handles.get_mut(&handle.unwrap());
+                if file.is_none() {
+                    self.response_buffer.push_back(UCode::HOSTFS_INVALID_HANDLE);
+                    return Ok(());
+                }
+                let mut data = vec![0x0; length.unwrap() as usize];
+                match file.unwrap().read(&mut data) {
+                    Ok(n) => {
+                        self.response_buffer.push_back(HostFS::OK);
+                        self.respond_word(n as Word);
+                        self.response_buffer.extend(&data[..n]);
+                    }
+                    Err(e) => self.response_buffer.push_back(HostFS::io_error(e.kind())),
+                }
+                Ok(())
+            }
+            HostFSAssembly::WRT => {
+                let handle = self.fetch_instruction_byte();
+                if handle.is_err() { return Err(handle.err().unwrap()); }
+                let data = self.fetch_block();
+                if data.is_err() { return Err(data.err().unwrap()); }
+
+                let file = self.handles.get_mut(&handle.unwrap());
+                if file.is_none() {
+                    self.response_buffer.push_back(UCode::HOSTFS_INVALID_HANDLE);
+                    return Ok(());
+                }
+                match file.unwrap().write(&data.unwrap()) {
+                    Ok(n) => {
+                        self.response_buffer.push_back(HostFS::OK);
+                        self.respond_word(n as Word);
+                    }
+                    Err(e) => self.response_buffer.push_back(HostFS::io_error(e.kind())),
+                }
+                Ok(())
+            }
+            HostFSAssembly::CLS => {
+                let handle = self.fetch_instruction_byte();
+                if handle.is_err() { return Err(handle.err().unwrap()); }
+                let status = if self.handles.remove(&handle.unwrap()).is_some() { HostFS::OK } else { UCode::HOSTFS_INVALID_HANDLE };
+                self.response_buffer.push_back(status);
+                Ok(())
+            }
+            HostFSAssembly::LST => {
+                let path = self.fetch_block();
+                if path.is_err() { return Err(path.err().unwrap()); }
+
+                match self.list(&path.unwrap()) {
+                    Ok(entries) => {
+                        self.response_buffer.push_back(HostFS::OK);
+                        self.respond_word(entries.len() as Word);
+                        for (kind, name) in entries {
+                            let name = &name.as_bytes()[..name.len().min(u8::MAX as usize)];
+                            self.response_buffer.extend([kind, name.len() as Byte]);
+                            self.response_buffer.extend(name);
+                        }
+                    }
+                    Err(e) => self.response_buffer.push_back(e),
+                }
+                Ok(())
+            }
+            HostFSAssembly::STA => {
+                let path = self.fetch_block();
+                if path.is_err() { return Err(path.err().unwrap()); }
+                let path = self.resolve(&path.unwrap());
+                if path.is_err() {
+                    self.response_buffer.push_back(path.err().unwrap());
+                    return Ok(());
+                }
+
+                match path.unwrap().metadata() {
+                    Ok(m) => {
+                        let modified = m.modified().ok()
+                            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                            .map(|d| d.as_secs()).unwrap_or(0);
+                        self.response_buffer.push_back(HostFS::OK);
+                        self.response_buffer.push_back(if m.is_dir() { HostFS::DIRECTORY } else { HostFS::FILE });
+                        self.respond_double_word(m.len().min(DoubleWord::MAX as u64) as DoubleWord);
+                        self.respond_double_word(modified.min(DoubleWord::MAX as u64) as DoubleWord);
+                    }
+                    Err(e) => self.response_buffer.push_back(HostFS::io_error(e.kind())),
+                }
+                Ok(())
+            }
+            _ => Ok(())
+        }
+    }
+}
+
+impl BusDevice for HostFS {
+    fn uuid(&self) -> String {
+        self.uuid.to_string()
+    }
+
+    fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn class(&self) -> Byte {
+        DeviceClass::STORAGE
+    }
+
+    fn capabilities(&self) -> Byte {
+        DeviceClass::CAP_RESPONDS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    use crate::lib::ucode::hostfs_assembly::HostFSAssembly;
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::HostFS;
+
+    fn send(host: &mut HostFS, bytes: &[u8]) -> Vec<u8> {
+        host.instruction_buffer.extend(bytes);
+        while host.instruction_ready() { host.execute().unwrap(); }
+        host.response_buffer.drain(..).collect()
+    }
+
+    // opcode, optional leading operand and a length prefixed block
+    fn block(opcode: u8, operand: Option<u8>, data: &[u8]) -> Vec<u8> {
+        let mut x = vec![opcode];
+        x.extend(operand);
+        x.extend((data.len() as u16).to_be_bytes());
+        x.extend(data);
+        x
+    }
+
+    #[test]
+    fn resolve_hands_out_the_checked_path() {
+        let dir = std::env::temp_dir().join(format!("hostfs-resolve-{}", std::process::id()));
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(dir.join("secret"), b"x").unwrap();
+        symlink(root.join("sub"), root.join("inside")).unwrap();
+        symlink(dir.join("secret"), root.join("outside")).unwrap();
+        symlink(dir.join("missing"), root.join("dangling")).unwrap();
+
+        let host = HostFS::new("hostfs", "hfs-0000-0000-0000", root.to_str().unwrap()).unwrap();
+        let root = root.canonicalize().unwrap();
+        assert_eq!(host.resolve(b"inside/a").unwrap(), root.join("sub").join("a"));
+        assert_eq!(host.resolve(b"sub/../sub").unwrap(), root.join("sub"));
+        assert_eq!(host.resolve(b"outside"), Err(UCode::HOSTFS_PATH_ESCAPES_ROOT));
+        assert_eq!(host.resolve(b"dangling"), Err(UCode::HOSTFS_PATH_ESCAPES_ROOT));
+        assert_eq!(host.resolve(b"../root"), Err(UCode::HOSTFS_PATH_ESCAPES_ROOT));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn handle_lifecycle() {
+        let dir = std::env::temp_dir().join(format!("hostfs-handles-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let mut host = HostFS::new("hostfs", "hfs-0000-0000-0000", dir.to_str().unwrap()).unwrap();
+
+        assert_eq!(send(&mut host, &block(HostFSAssembly::OPN, Some(HostFS::WRITE), b"sub/a.txt")), vec![HostFS::OK, 0]);
+        assert_eq!(send(&mut host, &block(HostFSAssembly::WRT, Some(0), b"hello")), vec![HostFS::OK, 0, 5]);
+        assert_eq!(send(&mut host, &[HostFSAssembly::CLS, 0]), vec![HostFS::OK]);
+
+        assert_eq!(send(&mut host, &block(HostFSAssembly::OPN, Some(HostFS::READ), b"sub/a.txt")), vec![HostFS::OK, 0]);
+        assert_eq!(send(&mut host, &[HostFSAssembly::RED, 0, 0x0, 0x3]), vec![HostFS::OK, 0, 3, b'h', b'e', b'l']);
+        assert_eq!(send(&mut host, &[HostFSAssembly::RED, 0, 0x0, 0x9]), vec![HostFS::OK, 0, 2, b'l', b'o']);
+        assert_eq!(send(&mut host, &[HostFSAssembly::RED, 0, 0x0, 0x9]), vec![HostFS::OK, 0, 0]);
+        assert_eq!(send(&mut host, &[HostFSAssembly::CLS, 0]), vec![HostFS::OK]);
+
+        let mut listing = vec![HostFS::OK, 0, 1, HostFS::FILE, 5];
+        listing.extend(b"a.txt");
+        assert_eq!(send(&mut host, &block(HostFSAssembly::LST, None, b"sub")), listing);
+        let stat = send(&mut host, &block(HostFSAssembly::STA, None, b"sub/a.txt"));
+        assert_eq!(&stat[..6], &[HostFS::OK, HostFS::FILE, 0, 0, 0, 5]);
+        assert_eq!(send(&mut host, &block(HostFSAssembly::STA, None, b"sub/b.txt")), vec![UCode::HOSTFS_NOT_FOUND]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bad_handles_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("hostfs-bad-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut host = HostFS::new("hostfs", "hfs-0000-0000-0000", dir.to_str().unwrap()).unwrap();
+
+        assert_eq!(send(&mut host, &[HostFSAssembly::RED, 3, 0x0, 0x1]), vec![UCode::HOSTFS_INVALID_HANDLE]);
+        // the data of a rejected write is consumed with it
+        let mut bytes = block(HostFSAssembly::WRT, Some(3), b"xyz");
+        bytes.extend([HostFSAssembly::CLS, 3]);
+        assert_eq!(send(&mut host, &bytes), vec![UCode::HOSTFS_INVALID_HANDLE, UCode::HOSTFS_INVALID_HANDLE]);
+        assert_eq!(send(&mut host, &block(HostFSAssembly::OPN, Some(0x0), b"a")), vec![UCode::HOSTFS_INVALID_MODE]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn listing_does_not_follow_links() {
+        let dir = std::env::temp_dir().join(format!("hostfs-list-{}", std::process::id()));
+        fs::create_dir_all(dir.join("root")).unwrap();
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        symlink(dir.join("outside"), dir.join("root").join("link")).unwrap();
+        let mut host = HostFS::new("hostfs", "hfs-0000-0000-0000", dir.join("root").to_str().unwrap()).unwrap();
+
+        let mut listing = vec![HostFS::OK, 0, 1, HostFS::LINK, 4];
+        listing.extend(b"link");
+        assert_eq!(send(&mut host, &block(HostFSAssembly::LST, None, b"")), listing);
+        assert_eq!(send(&mut host, &block(HostFSAssembly::LST, None, b"link")), vec![UCode::HOSTFS_PATH_ESCAPES_ROOT]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
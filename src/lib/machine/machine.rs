@@ -0,0 +1,73 @@
+use std::sync::{Arc, Mutex};
+
+use crate::lib::bus::bus::Bus;
+use crate::lib::cpu::cpu::CPU;
+use crate::lib::debug::coverage::Coverage;
+use crate::lib::debug::debugger::Debugger;
+use crate::lib::debug::profiler::Profiler;
+use crate::lib::debug::symbols::Symbols;
+use crate::lib::debug::trace::ExecutionTracer;
+use crate::lib::mem::{Byte, DoubleWord};
+use crate::lib::mem::ram::RAM;
+
+// one virtual machine: cpu, memory and the bus its devices live on
+
+pub struct Machine {
+    cpu: CPU,
+    ram: RAM,
+    bus: Arc<Mutex<Bus>>,
+}
+
+impl Machine {
+    pub fn new(ram_size: usize) -> Self {
+        Machine {
+            cpu: CPU::new(),
+            ram: RAM::new(ram_size),
+            bus: Arc::new(Mutex::new(Bus::new())),
+        }
+    }
+
+    // default window for the memory mapped gpu framebuffer (end of the heap)
+    pub const FRAMEBUFFER_BASE: DoubleWord = 0x0F00_0000;
+}
+
+impl Machine {
+    pub fn bus(&self) -> Arc<Mutex<Bus>> {
+        Arc::clone(&self.bus)
+    }
+
+    // copies a program or data image into memory
+    pub fn load(&mut self, address: usize, data: &[Byte]) -> Result<(), Byte> {
+        self.ram.load(address, data)
+    }
+
+    // routes cpu accesses of [start, start + size) to the attached device at the given bus address
+    pub fn map(&mut self, start: DoubleWord, size: DoubleWord, device: Byte) -> Result<(), Byte> {
+        self.cpu.map_region(start, size, device)
+    }
+
+    // attaches a debugger, it keeps working once the machine was moved into its own thread and launched
+    pub fn debugger(&self) -> Debugger {
+        self.cpu.debugger()
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<ExecutionTracer>) -> Option<ExecutionTracer> {
+        self.cpu.set_tracer(tracer)
+    }
+
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) -> Option<Profiler> {
+        self.cpu.set_profiler(profiler)
+    }
+
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) -> Option<Coverage> {
+        self.cpu.set_coverage(coverage)
+    }
+
+    pub fn set_symbols(&mut self, symbols: Option<Symbols>) {
+        self.cpu.set_symbols(symbols)
+    }
+
+    pub fn launch(&mut self) {
+        self.cpu.launch(&mut self.ram, &self.bus)
+    }
+}
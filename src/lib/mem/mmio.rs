@@ -0,0 +1,85 @@
+use crate::lib::mem::{Byte, DoubleWord};
+use crate::lib::ucode::ucode::UCode;
+
+// address ranges of the cpu address space that are routed to a bus device instead of RAM
+
+pub struct MMIORegion {
+    pub start: DoubleWord,
+    pub size: DoubleWord,
+    pub device: Byte,
+}
+
+pub struct MMIOTable {
+    regions: Vec<MMIORegion>,
+}
+
+impl Default for MMIOTable {
+    fn default() -> Self {
+        MMIOTable::new()
+    }
+}
+
+impl MMIOTable {
+    pub fn new() -> Self {
+        MMIOTable {
+            regions: vec![],
+        }
+    }
+}
+
+impl MMIOTable {
+    pub fn map(&mut self, start: DoubleWord, size: DoubleWord, device: Byte) -> Result<(), Byte> {
+        if size == 0 || start.checked_add(size - 1).is_none() { return Err(UCode::INVALID_MMIO_REGION); }
+        let end = start as u64 + size as u64;
+        for r in self.regions.iter() {
+            if (start as u64) < r.start as u64 + r.size as u64 && (r.start as u64) < end { return Err(UCode::MMIO_REGION_OVERLAP); }
+        }
+        self.regions.push(MMIORegion { start, size, device });
+        Ok(())
+    }
+
+    // removes every region routed to the device
+    pub fn unmap(&mut self, device: Byte) {
+        self.regions.retain(|r| r.device != device);
+    }
+
+    // device and offset into its region for a cpu address, None for plain RAM
+    pub fn lookup(&self, address: usize) -> Option<(Byte, DoubleWord)> {
+        self.regions.iter()
+            .find(|r| address >= r.start as usize && address - (r.start as usize) < r.size as usize)
+            .map(|r| (r.device, (address - r.start as usize) as DoubleWord))
+    }
+
+    pub fn regions(&self) -> &Vec<MMIORegion> {
+        &self.regions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::MMIOTable;
+
+    #[test]
+    fn map_rejects_overlapping_and_empty_regions() {
+        let mut t = MMIOTable::new();
+        assert!(t.map(0x1000, 0x100, 1).is_ok());
+        // touching either end is fine
+        assert!(t.map(0x0F00, 0x100, 2).is_ok());
+        assert!(t.map(0x1100, 0x100, 3).is_ok());
+        assert_eq!(t.map(0x10FF, 1, 4), Err(UCode::MMIO_REGION_OVERLAP));
+        assert_eq!(t.map(0x0800, 0x1000, 4), Err(UCode::MMIO_REGION_OVERLAP));
+        assert_eq!(t.map(0x1010, 0x10, 4), Err(UCode::MMIO_REGION_OVERLAP));
+        assert_eq!(t.map(0x3000, 0, 4), Err(UCode::INVALID_MMIO_REGION));
+        assert_eq!(t.map(0xFFFF_FF00, 0x101, 4), Err(UCode::INVALID_MMIO_REGION));
+        assert!(t.map(0xFFFF_FF00, 0x100, 4).is_ok());
+
+        assert_eq!(t.lookup(0x10FF), Some((1, 0xFF)));
+        assert_eq!(t.lookup(0x1100), Some((3, 0x0)));
+        assert_eq!(t.lookup(0x2000), None);
+        t.unmap(1);
+        assert_eq!(t.lookup(0x1000), None);
+        assert!(t.map(0x1080, 0x10, 5).is_ok());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod ram;
 pub mod mem;
+pub mod mmio;
 
 pub type Byte = u8;
 pub type Word = u16;
@@ -43,6 +43,13 @@ impl RAM {
         if address < self.size { Ok(self.memory[address]) } else { Err(UCode::INVALID_MEMORY_READ) }
     }
 
+    // copies a program or data image into memory starting at address
+    pub fn load(&mut self, address: usize, data: &[Byte]) -> Result<(), Byte> {
+        if address + data.len() > self.size { return Err(UCode::INVALID_MEMORY_WRITE); }
+        self.memory[address..address + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
     pub fn write_byte(&mut self, address: usize, byte: Byte) -> Result<(), Byte> {
         if address < self.size {
             self.memory[address] = byte;
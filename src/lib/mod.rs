@@ -1,9 +1,17 @@
 pub mod cpu;
 pub mod mem;
+pub mod machine;
+pub mod debug;
 
 pub mod bus;
+pub mod dma;
 pub mod gpu;
+pub mod timer;
+pub mod rtc;
+pub mod audio;
+pub mod hostfs;
+pub mod net;
 
 pub mod ucode;
 
-pub mod chip_util;
\ No newline at end of file
+pub mod chip_util;
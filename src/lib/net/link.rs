@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+use std::net::UdpSocket;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::lib::mem::Byte;
+use crate::lib::net::nic::NIC;
+use crate::lib::net::pcap::PcapWriter;
+use crate::lib::ucode::ucode::UCode;
+
+// the medium a NIC transmits to and receives from
+pub enum Link {
+    // nothing is ever delivered
+    Disconnected,
+    // transmitted packets are received back by the same nic
+    Loopback(VecDeque<Vec<Byte>>),
+    // another nic in the same process, see Link::pair
+    Peer(Sender<Vec<Byte>>, Receiver<Vec<Byte>>),
+    // one datagram per packet between two local sockets
+    Udp(UdpSocket),
+    // transmitted packets are written to a capture file, nothing is received
+    Pcap(PcapWriter),
+}
+
+impl Link {
+    pub fn loopback() -> Self {
+        Link::Loopback(VecDeque::new())
+    }
+
+    // two connected ends, hand one to each vm
+    pub fn pair() -> (Self, Self) {
+        let (tx_a, rx_a) = channel();
+        let (tx_b, rx_b) = channel();
+        (Link::Peer(tx_a, rx_b), Link::Peer(tx_b, rx_a))
+    }
+
+    // `local` and `remote` as host:port, e.g. 127.0.0.1:4000
+    pub fn udp(local: &str, remote: &str) -> Result<Self, Byte> {
+        let socket = UdpSocket::bind(local)
+            .and_then(|s| s.connect(remote).map(|_| s))
+            .and_then(|s| s.set_nonblocking(true).map(|_| s));
+        if socket.is_err() { return Err(UCode::NIC_LINK_FAILURE); }
+        Ok(Link::Udp(socket.unwrap()))
+    }
+
+    pub fn pcap(path: &str) -> Result<Self, Byte> {
+        let w = PcapWriter::new(path);
+        if w.is_err() { return Err(w.err().unwrap()); }
+        Ok(Link::Pcap(w.unwrap()))
+    }
+}
+
+impl Link {
+    pub fn send(&mut self, packet: &[Byte]) -> Result<(), Byte> {
+        match self {
+            Link::Disconnected => Ok(()),
+            Link::Loopback(q) => {
+                q.push_back(packet.to_vec());
+                Ok(())
+            }
+            // a hung up peer behaves like a pulled cable
+            Link::Peer(tx, _) => {
+                let _ = tx.send(packet.to_vec());
+                Ok(())
+            }
+            Link::Udp(s) => {
+                let res = s.send(packet);
+                if res.is_err() && res.err().unwrap().kind() != std::io::ErrorKind::WouldBlock { return Err(UCode::NIC_LINK_FAILURE); }
+                Ok(())
+            }
+            Link::Pcap(w) => w.write(packet),
+        }
+    }
+
+    pub fn receive(&mut self) -> Option<Vec<Byte>> {
+        match self {
+            Link::Loopback(q) => q.pop_front(),
+            Link::Peer(_, rx) => rx.try_recv().ok(),
+            Link::Udp(s) => {
+                let mut buf = vec![0x0; NIC::MTU];
+                let n = s.recv(&mut buf);
+                if n.is_err() { return None; }
+                buf.truncate(n.unwrap());
+                Some(buf)
+            }
+            _ => None
+        }
+    }
+}
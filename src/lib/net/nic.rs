@@ -0,0 +1,314 @@
+use std::collections::VecDeque;
+use std::process::exit;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::lib::bus::bus::Bus;
+use crate::lib::bus::bus_device::BusDevice;
+use crate::lib::bus::device_class::DeviceClass;
+use crate::lib::chip_util::{BlockingLock, combine_to_word};
+use crate::lib::mem::{Byte, W, Word};
+use crate::lib::net::link::Link;
+use crate::lib::ucode::nic_assembly::NICAssembly;
+use crate::lib::ucode::ucode::UCode;
+
+// network interface
+//
+// packets written with SND are queued on the tx ring and handed to the link,
+// packets arriving on the link are queued on the rx ring until read with RCV;
+// a full ring, an oversized packet or a failing link drops the packet and counts it.
+// SND replies with a status byte, NIC::OK or one of the NIC_* uCodes. with the receive
+// interrupt enabled an interrupt is raised whenever the rx ring becomes non empty
+
+pub struct NIC {
+    address: Byte,
+    instruction_buffer: VecDeque<Byte>,
+    response_buffer: VecDeque<Byte>,
+
+    uuid: String,
+    name: String,
+
+    link: Link,
+    tx_ring: VecDeque<Vec<Byte>>,
+    rx_ring: VecDeque<Vec<Byte>>,
+    dropped: Word,
+    rx_interrupt: bool,
+}
+
+impl NIC {
+    pub fn new(name: &str, uuid: &str, link: Link) -> Self {
+        NIC {
+            address: 0x0,
+            instruction_buffer: VecDeque::new(),
+            response_buffer: VecDeque::new(),
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            link,
+            tx_ring: VecDeque::with_capacity(NIC::RING_SIZE),
+            rx_ring: VecDeque::with_capacity(NIC::RING_SIZE),
+            dropped: 0,
+            rx_interrupt: false,
+        }
+    }
+
+    pub const RING_SIZE: usize = 16;
+    pub const MTU: usize = 1518;
+
+    pub const OK: Byte = 0x00;
+}
+
+impl NIC {
+    pub fn launch(&mut self, bus: &Arc<Mutex<Bus>>) {
+        let address = bus.b_lock().register(Box::new(self));
+        if address.is_err() { self.raise_exception(address.err().unwrap()) }
+        self.address = address.unwrap();
+
+        loop {
+            // unplugged from the bus, stop the device
+            let x = {
+                let mut b = bus.b_lock();
+                if !b.is_registered(self.address, &self.uuid) { return; }
+                b.poll(self.address)
+            };
+            self.instruction_buffer.extend(x);
+
+            while self.instruction_ready() {
+                let res = self.execute();
+                if res.is_err() { self.raise_exception(res.err().unwrap()) }
+                if !self.response_buffer.is_empty() {
+                    let r: Vec<Byte> = self.response_buffer.drain(..).collect();
+                    bus.b_lock().respond(self.address, &r);
+                }
+            }
+
+            if self.transfer() && self.rx_interrupt { bus.b_lock().interrupt(self.address); }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    // flushes the tx ring to the link and fills the rx ring from it,
+    // returns true when the rx ring went from empty to non empty
+    pub fn transfer(&mut self) -> bool {
+        while let Some(p) = self.tx_ring.pop_front() {
+            if self.link.send(&p).is_err() { self.dropped = self.dropped.wrapping_add(1); }
+        }
+
+        let was_empty = self.rx_ring.is_empty();
+        while let Some(p) = self.link.receive() {
+            if self.rx_ring.len() >= NIC::RING_SIZE { self.dropped = self.dropped.wrapping_add(1); } else { self.rx_ring.push_back(p); }
+        }
+        was_empty && !self.rx_ring.is_empty()
+    }
+
+    fn raise_exception(&self, ucode: Byte) {
+        println!("exception code: {:X} raised;\n{}", ucode, self.stack_trace());
+        exit(ucode as i32)
+    }
+
+    fn stack_trace(&self) -> String {
+        format!("-----------------------\n\
+        address:     {:#04X}\n\
+        pending:     {}\n\
+        replies:     {}\n\
+        tx ring:     {}\n\
+        rx ring:     {}\n\
+        dropped:     {}",
+                self.address,
+                self.instruction_buffer.len(),
+                self.response_buffer.len(),
+                self.tx_ring.len(),
+                self.rx_ring.len(),
+                self.dropped)
+    }
+
+    // size of the next instruction including its opcode, None until its length is known
+    fn instruction_size(&self) -> Option<usize> {
+        match *self.instruction_buffer.front()? {
+            NICAssembly::SND => {
+                let sig = self.instruction_buffer.get(1);
+                let insig = self.instruction_buffer.get(2);
+                if sig.is_none() || insig.is_none() { return None; }
+                Some(3 + combine_to_word(*sig.unwrap(), *insig.unwrap()) as usize)
+            }
+            NICAssembly::IEN => Some(2),
+            _ => Some(1),
+        }
+    }
+
+    // an instruction is only executed once all of its operands arrived over the bus
+    fn instruction_ready(&self) -> bool {
+        let size = self.instruction_size();
+        size.is_some() && self.instruction_buffer.len() >= size.unwrap()
+    }
+
+    fn fetch_instruction_byte(&mut self) -> Result<Byte, Byte> {
+        let x = self.instruction_buffer.pop_front();
+        if x.is_none() { return Err(UCode::INVALID_BUFFER_ACCESS); }
+        Ok(x.unwrap())
+    }
+    fn fetch_instruction_word(&mut self) -> Result<Word, Byte> {
+        let x1 = self.fetch_instruction_byte();
+        if x1.is_err() { return Err(x1.err().unwrap()); }
+        let x2 = self.fetch_instruction_byte();
+        if x2.is_err() { return Err(x2.err().unwrap()); }
+        Ok(combine_to_word(x1.unwrap(), x2.unwrap()))
+    }
+
+    fn respond_word(&mut self, word: Word) {
+        self.response_buffer.push_back(word.significant_byte());
+        self.response_buffer.push_back(word.insignificant_byte());
+    }
+}
+
+impl NIC {
+    fn execute(&mut self) -> Result<(), Byte> {
+        let opcode = self.fetch_instruction_byte();
+        if opcode.is_err() { return Err(opcode.err().unwrap()); }
+
+        match opcode.unwrap() {
+            NICAssembly::HLT => Ok(()),
+            NICAssembly::SND => {
+                let length = self.fetch_instruction_word();
+                if length.is_err() { return Err(length.err().unwrap()); }
+                let length = length.unwrap() as usize;
+                if self.instruction_buffer.len() < length { return Err(UCode::INVALID_BUFFER_ACCESS); }
+                let packet: Vec<Byte> = self.instruction_buffer.drain(..length).collect();
+
+                let status = if packet.len() > NIC::MTU {
+                    UCode::NIC_PACKET_TOO_LARGE
+                } else if self.tx_ring.len() >= NIC::RING_SIZE {
+                    UCode::NIC_RING_FULL
+                } else {
+                    self.tx_ring.push_back(packet);
+                    NIC::OK
+                };
+                if status != NIC::OK { self.dropped = self.dropped.wrapping_add(1); }
+                self.response_buffer.push_back(status);
+                Ok(())
+            }
+            NICAssembly::RCV => {
+                let packet = self.rx_ring.pop_front().unwrap_or_default();
+                self.respond_word(packet.len() as Word);
+                self.response_buffer.extend(packet);
+                Ok(())
+            }
+            NICAssembly::STA => {
+                self.response_buffer.push_back(self.rx_ring.len() as Byte);
+                self.response_buffer.push_back(self.tx_ring.len() as Byte);
+                self.respond_word(self.dropped);
+                Ok(())
+            }
+            NICAssembly::IEN => {
+                let x = self.fetch_instruction_byte();
+                if x.is_err() { return Err(x.err().unwrap()); }
+                self.rx_interrupt = x.unwrap() != 0;
+                Ok(())
+            }
+            _ => Ok(())
+        }
+    }
+}
+
+impl BusDevice for NIC {
+    fn uuid(&self) -> String {
+        self.uuid.to_string()
+    }
+
+    fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn class(&self) -> Byte {
+        DeviceClass::NETWORK
+    }
+
+    fn capabilities(&self) -> Byte {
+        DeviceClass::CAP_RESPONDS | DeviceClass::CAP_INTERRUPTS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::lib::mem::Byte;
+    use crate::lib::net::link::Link;
+    use crate::lib::ucode::nic_assembly::NICAssembly;
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::NIC;
+
+    fn send(n: &mut NIC, bytes: &[Byte]) -> Vec<Byte> {
+        n.instruction_buffer.extend(bytes);
+        while n.instruction_ready() { n.execute().unwrap(); }
+        n.response_buffer.drain(..).collect()
+    }
+
+    fn snd(packet: &[Byte]) -> Vec<Byte> {
+        let mut x = vec![NICAssembly::SND, (packet.len() >> 8) as Byte, packet.len() as Byte];
+        x.extend_from_slice(packet);
+        x
+    }
+
+    #[test]
+    fn loopback_delivers_to_the_rx_ring() {
+        let mut n = NIC::new("nic", "nic-0", Link::loopback());
+        assert_eq!(send(&mut n, &snd(&[0x1, 0x2, 0x3])), vec![NIC::OK]);
+        assert_eq!(send(&mut n, &[NICAssembly::STA]), vec![0, 1, 0, 0]);
+
+        assert!(n.transfer());
+        assert_eq!(send(&mut n, &[NICAssembly::RCV]), vec![0x0, 0x3, 0x1, 0x2, 0x3]);
+        assert_eq!(send(&mut n, &[NICAssembly::RCV]), vec![0x0, 0x0]);
+    }
+
+    #[test]
+    fn full_rings_and_oversized_packets_are_dropped() {
+        let mut n = NIC::new("nic", "nic-0", Link::loopback());
+        for _ in 0..NIC::RING_SIZE { assert_eq!(send(&mut n, &snd(&[0xAA])), vec![NIC::OK]); }
+        assert_eq!(send(&mut n, &snd(&[0xAA])), vec![UCode::NIC_RING_FULL]);
+        assert_eq!(send(&mut n, &snd(&vec![0x0; NIC::MTU + 1])), vec![UCode::NIC_PACKET_TOO_LARGE]);
+
+        // the rx ring fills up, the packet beyond it is dropped on arrival
+        assert!(n.transfer());
+        send(&mut n, &snd(&[0xBB]));
+        assert!(!n.transfer());
+        assert_eq!(send(&mut n, &[NICAssembly::STA]), vec![NIC::RING_SIZE as Byte, 0, 0, 3]);
+    }
+
+    #[test]
+    fn paired_nics_reach_each_other() {
+        let (a, b) = Link::pair();
+        let mut a = NIC::new("a", "nic-a", a);
+        let mut b = NIC::new("b", "nic-b", b);
+
+        send(&mut a, &snd(&[0xDE, 0xAD]));
+        assert!(!a.transfer());
+        assert!(b.transfer());
+        assert_eq!(send(&mut b, &[NICAssembly::RCV]), vec![0x0, 0x2, 0xDE, 0xAD]);
+
+        send(&mut b, &snd(&[0xBE, 0xEF]));
+        b.transfer();
+        assert!(a.transfer());
+        assert_eq!(send(&mut a, &[NICAssembly::RCV]), vec![0x0, 0x2, 0xBE, 0xEF]);
+    }
+
+    #[test]
+    fn pcap_link_records_every_packet() {
+        let path = std::env::temp_dir().join(format!("vm-nic-{}.pcap", std::process::id()));
+        let mut n = NIC::new("nic", "nic-0", Link::pcap(path.to_str().unwrap()).unwrap());
+        send(&mut n, &snd(&[0x1, 0x2, 0x3]));
+        send(&mut n, &snd(&[0x4]));
+        assert!(!n.transfer());
+
+        let data = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(&data[0..4], &0xa1b2_c3d4u32.to_le_bytes());
+        assert_eq!(&data[20..24], &147u32.to_le_bytes());
+        // 24 byte header, 16 byte record headers
+        assert_eq!(data.len(), 24 + 16 + 3 + 16 + 1);
+        assert_eq!(&data[32..40], &[3, 0, 0, 0, 3, 0, 0, 0]);
+        assert_eq!(&data[40..43], &[0x1, 0x2, 0x3]);
+        assert_eq!(&data[59..60], &[0x4]);
+    }
+}
@@ -0,0 +1,49 @@
+use std::fs::File;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::lib::mem::Byte;
+use crate::lib::ucode::ucode::UCode;
+
+// libpcap capture file writer, packets are raw guest frames (LINKTYPE_USER0)
+
+pub struct PcapWriter {
+    file: File,
+}
+
+impl PcapWriter {
+    pub fn new(path: &str) -> Result<Self, Byte> {
+        let file = File::create(path);
+        if file.is_err() { return Err(UCode::NIC_LINK_FAILURE); }
+        let mut w = PcapWriter { file: file.unwrap() };
+
+        let mut h: Vec<u8> = Vec::with_capacity(24);
+        h.extend_from_slice(&PcapWriter::MAGIC.to_le_bytes());
+        h.extend_from_slice(&2u16.to_le_bytes());
+        h.extend_from_slice(&4u16.to_le_bytes());
+        h.extend_from_slice(&0i32.to_le_bytes());
+        h.extend_from_slice(&0u32.to_le_bytes());
+        h.extend_from_slice(&PcapWriter::SNAP_LENGTH.to_le_bytes());
+        h.extend_from_slice(&PcapWriter::LINKTYPE_USER0.to_le_bytes());
+        if w.file.write_all(&h).is_err() { return Err(UCode::NIC_LINK_FAILURE); }
+        Ok(w)
+    }
+
+    const MAGIC: u32 = 0xa1b2_c3d4;
+    const SNAP_LENGTH: u32 = 65_535;
+    const LINKTYPE_USER0: u32 = 147;
+}
+
+impl PcapWriter {
+    pub fn write(&mut self, packet: &[Byte]) -> Result<(), Byte> {
+        let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut r: Vec<u8> = Vec::with_capacity(16 + packet.len());
+        r.extend_from_slice(&(t.as_secs() as u32).to_le_bytes());
+        r.extend_from_slice(&t.subsec_micros().to_le_bytes());
+        r.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        r.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        r.extend_from_slice(packet);
+        if self.file.write_all(&r).and_then(|_| self.file.flush()).is_err() { return Err(UCode::NIC_LINK_FAILURE); }
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::lib::bus::bus_device::BusDevice;
+use crate::lib::bus::device_class::DeviceClass;
+use crate::lib::chip_util::combine_to_word;
+use crate::lib::mem::{Byte, W, Word};
+use crate::lib::ucode::rtc_assembly::RTCAssembly;
+use crate::lib::ucode::ucode::UCode;
+
+// YYYY'YYYY_YYYY'YYYY  -   WORD    year
+// MMMM'MMMM            -   BYTE    month       1 - 12
+// DDDD'DDDD            -   BYTE    day         1 - 31
+// hhhh'hhhh            -   BYTE    hour        0 - 23
+// mmmm'mmmm            -   BYTE    minute      0 - 59
+// ssss'ssss            -   BYTE    second      0 - 59
+// WWWW'WWWW            -   BYTE    weekday     0 - 6   (0 = sunday)
+
+//                          8 Bytes per date, UTC
+
+// the clock is driven by the bus hooks (see Bus::attach), alarms are checked every ALARM_INTERVAL cycles
+//
+// SAL and CAL reply with a status byte, RTC::OK or the uCode the command was rejected with
+
+pub enum RTCClock {
+    // host wall clock
+    Host,
+    // always reports the given unix time (seconds)
+    Fixed(i64),
+    // host wall clock shifted by the given amount of seconds
+    Offset(i64),
+}
+
+pub struct RTC {
+    instruction_buffer: VecDeque<Byte>,
+    response_buffer: VecDeque<Byte>,
+
+    uuid: String,
+    name: String,
+
+    clock: RTCClock,
+    alarms: Vec<Option<i64>>,
+}
+
+impl RTC {
+    pub fn new(name: &str, uuid: &str, clock: RTCClock) -> Self {
+        RTC {
+            instruction_buffer: VecDeque::new(),
+            response_buffer: VecDeque::new(),
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            clock,
+            alarms: vec![None; RTC::ALARM_SLOTS],
+        }
+    }
+
+    pub const ALARM_SLOTS: usize = 4;
+    pub const DATE_SIZE: usize = 8;
+    pub const ALARM_INTERVAL: u64 = 1024;
+
+    pub const OK: Byte = 0x00;
+}
+
+impl RTC {
+    // current unix time (seconds) as seen by the guest
+    pub fn now(&self) -> i64 {
+        let host = || SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        match self.clock {
+            RTCClock::Host => host(),
+            RTCClock::Fixed(t) => t,
+            RTCClock::Offset(o) => host() + o,
+        }
+    }
+
+    // current time in the rtc date layout
+    pub fn date(&self) -> [Byte; RTC::DATE_SIZE] {
+        RTC::encode(self.now())
+    }
+
+    pub fn alarm(&self, slot: usize) -> Option<i64> {
+        self.alarms.get(slot).copied().flatten()
+    }
+
+    // clears every alarm that is due, returns true when any of them fired
+    pub fn update(&mut self) -> bool {
+        let now = self.now();
+        let mut fired = false;
+        for a in self.alarms.iter_mut() {
+            if a.is_some() && a.unwrap() <= now {
+                *a = None;
+                fired = true;
+            }
+        }
+        fired
+    }
+
+    pub fn encode(time: i64) -> [Byte; RTC::DATE_SIZE] {
+        let days = time.div_euclid(86_400);
+        let secs = time.rem_euclid(86_400);
+        let (year, month, day) = RTC::civil_from_days(days);
+        let weekday = (days + 4).rem_euclid(7);
+        let year = year as Word;
+
+        [
+            year.significant_byte(),
+            year.insignificant_byte(),
+            month as Byte,
+            day as Byte,
+            (secs / 3600) as Byte,
+            (secs % 3600 / 60) as Byte,
+            (secs % 60) as Byte,
+            weekday as Byte,
+        ]
+    }
+
+    pub fn decode(date: &[Byte; RTC::DATE_SIZE]) -> Result<i64, Byte> {
+        let year = combine_to_word(date[0], date[1]) as i64;
+        let (month, day) = (date[2] as i64, date[3] as i64);
+        let (hour, minute, second) = (date[4] as i64, date[5] as i64, date[6] as i64);
+        if !(1..=12).contains(&month) || day < 1 || day > RTC::days_in_month(year, month) || hour > 23 || minute > 59 || second > 59 {
+            return Err(UCode::INVALID_DATE);
+        }
+        Ok(RTC::days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second)
+    }
+
+    fn days_in_month(year: i64, month: i64) -> i64 {
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        match month {
+            2 => if leap { 29 } else { 28 },
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    // days since 1970-01-01 => (year, month, day)
+    fn civil_from_days(days: i64) -> (i64, i64, i64) {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year, month, day)
+    }
+
+    // (year, month, day) => days since 1970-01-01
+    fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    pub fn stack_trace(&self) -> String {
+        let mut x = format!("-----------------------\n\
+        pending:     {}\n\
+        time   :     {}\n", self.instruction_buffer.len(), self.now());
+        for (i, a) in self.alarms.iter().enumerate() {
+            x += format!("alarm {}:     {:?}\n", i, a).as_str();
+        }
+        x
+    }
+
+    fn queue_to_buffer(&mut self, data: Vec<Byte>) {
+        self.instruction_buffer.extend(data);
+    }
+
+    // an instruction is only executed once all of its operands arrived over the bus
+    fn instruction_ready(&self) -> bool {
+        let x = self.instruction_buffer.front();
+        if x.is_none() { return false; }
+        self.instruction_buffer.len() > RTCAssembly::operand_size(*x.unwrap())
+    }
+
+    fn fetch_instruction_byte(&mut self) -> Result<Byte, Byte> {
+        let x = self.instruction_buffer.pop_front();
+        if x.is_none() { return Err(UCode::INVALID_BUFFER_ACCESS); }
+        Ok(x.unwrap())
+    }
+
+    fn fetch_slot(&mut self) -> Result<usize, Byte> {
+        let x = self.fetch_instruction_byte();
+        if x.is_err() { return Err(x.err().unwrap()); }
+        let slot = x.unwrap() as usize;
+        if slot >= self.alarms.len() { return Err(UCode::INVALID_ALARM_SLOT); }
+        Ok(slot)
+    }
+}
+
+impl RTC {
+    fn execute(&mut self) -> Result<(), Byte> {
+        let opcode = self.fetch_instruction_byte();
+        if opcode.is_err() { return Err(opcode.err().unwrap()); }
+
+        match opcode.unwrap() {
+            RTCAssembly::HLT => Ok(()),
+            RTCAssembly::SAL => {
+                let slot = self.fetch_slot();
+                if slot.is_err() { return Err(slot.err().unwrap()); }
+                let mut date = [0x0; RTC::DATE_SIZE];
+                for i in date.iter_mut() {
+                    let x = self.fetch_instruction_byte();
+                    if x.is_err() { return Err(x.err().unwrap()); }
+                    *i = x.unwrap();
+                }
+                let time = RTC::decode(&date);
+                if time.is_err() { return Err(time.err().unwrap()); }
+                self.alarms[slot.unwrap()] = Some(time.unwrap());
+                self.response_buffer.push_back(RTC::OK);
+                Ok(())
+            }
+            RTCAssembly::RDT => {
+                let date = self.date();
+                self.response_buffer.extend(date);
+                Ok(())
+            }
+            RTCAssembly::CAL => {
+                let slot = self.fetch_slot();
+                if slot.is_err() { return Err(slot.err().unwrap()); }
+                self.alarms[slot.unwrap()] = None;
+                self.response_buffer.push_back(RTC::OK);
+                Ok(())
+            }
+            _ => Ok(())
+        }
+    }
+}
+
+impl BusDevice for RTC {
+    fn uuid(&self) -> String {
+        self.uuid.to_string()
+    }
+
+    fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn class(&self) -> Byte {
+        DeviceClass::CLOCK
+    }
+
+    fn capabilities(&self) -> Byte {
+        DeviceClass::CAP_RESPONDS | DeviceClass::CAP_INTERRUPTS
+    }
+
+    fn on_write(&mut self, byte: Byte) {
+        self.queue_to_buffer(vec![byte]);
+        while self.instruction_ready() {
+            let rest = self.instruction_buffer.len() - 1 - RTCAssembly::operand_size(*self.instruction_buffer.front().unwrap());
+            let res = self.execute();
+            // hooks run with the bus locked, a rejected command is dropped whole and answered with its uCode
+            if res.is_err() {
+                while self.instruction_buffer.len() > rest { self.instruction_buffer.pop_front(); }
+                self.response_buffer.push_back(res.err().unwrap());
+            }
+        }
+    }
+
+    fn on_read(&mut self) -> Option<Byte> {
+        self.response_buffer.pop_front()
+    }
+
+    fn tick(&mut self, cycles: u64) -> bool {
+        cycles.is_multiple_of(RTC::ALARM_INTERVAL) && self.update()
+    }
+
+    fn reset(&mut self) {
+        self.instruction_buffer.clear();
+        self.response_buffer.clear();
+        self.alarms = vec![None; RTC::ALARM_SLOTS];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::RTC;
+
+    #[test]
+    fn encode_decode_round_trip() {
+        // 2000-02-29 12:34:56, a tuesday
+        let t = 951_827_696;
+        assert_eq!(RTC::encode(t), [0x07, 0xD0, 2, 29, 12, 34, 56, 2]);
+        for t in [0, 59, 86_399, 86_400, t, 1_709_164_800, 4_102_444_800, -1] {
+            assert_eq!(RTC::decode(&RTC::encode(t)), Ok(t));
+        }
+    }
+
+    #[test]
+    fn decode_knows_leap_years() {
+        assert!(RTC::decode(&[0x07, 0xE8, 2, 29, 0, 0, 0, 0]).is_ok());
+        assert!(RTC::decode(&[0x07, 0xD0, 2, 29, 0, 0, 0, 0]).is_ok());
+        assert_eq!(RTC::decode(&[0x07, 0xE9, 2, 29, 0, 0, 0, 0]), Err(UCode::INVALID_DATE));
+        assert_eq!(RTC::decode(&[0x08, 0x34, 2, 29, 0, 0, 0, 0]), Err(UCode::INVALID_DATE));
+        assert_eq!(RTC::decode(&[0x07, 0xE8, 4, 31, 0, 0, 0, 0]), Err(UCode::INVALID_DATE));
+        assert_eq!(RTC::decode(&[0x07, 0xE8, 13, 1, 0, 0, 0, 0]), Err(UCode::INVALID_DATE));
+        assert_eq!(RTC::decode(&[0x07, 0xE8, 1, 1, 24, 0, 0, 0]), Err(UCode::INVALID_DATE));
+    }
+}
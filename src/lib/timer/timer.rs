@@ -0,0 +1,374 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::lib::bus::bus_device::BusDevice;
+use crate::lib::bus::device_class::DeviceClass;
+use crate::lib::chip_util::{combine_to_double_word, combine_to_word};
+use crate::lib::mem::{Byte, D, DoubleWord, W};
+use crate::lib::ucode::timer_assembly::TimerAssembly;
+use crate::lib::ucode::ucode::UCode;
+
+// programmable interval timer, driven by the bus hooks (see Bus::attach)
+//
+// every channel counts down from its period to zero, on zero the channel is marked
+// as expired and an interrupt is raised with the timer's bus address as source
+//
+// mode:    0x00 = one shot     (channel stops after expiry)
+//          0x01 = periodic     (counter is reloaded from the period)
+// unit:    0x00 = cpu cycles   (bus clock)
+//          0x01 = microseconds (host wall clock)
+//
+// every command except HLT replies with a status byte, Timer::OK or the uCode it was rejected with;
+// no further reply bytes follow a failed status
+
+struct TimerChannel {
+    mode: Byte,
+    unit: Byte,
+    period: DoubleWord,
+    counter: DoubleWord,
+    running: bool,
+    expired: bool,
+
+    last_cycle: u64,
+    last_instant: Instant,
+}
+
+impl TimerChannel {
+    fn new() -> Self {
+        TimerChannel {
+            mode: Timer::ONE_SHOT,
+            unit: Timer::CYCLES,
+            period: 0x0,
+            counter: 0x0,
+            running: false,
+            expired: false,
+            last_cycle: 0,
+            last_instant: Instant::now(),
+        }
+    }
+
+    fn elapsed(&mut self, cycles: u64) -> u64 {
+        if self.unit == Timer::CYCLES {
+            let e = cycles.saturating_sub(self.last_cycle);
+            self.last_cycle = cycles;
+            e
+        } else {
+            // only whole microseconds are counted, the remainder carries over to the next update
+            let e = self.last_instant.elapsed().as_micros() as u64;
+            self.last_instant += Duration::from_micros(e);
+            e
+        }
+    }
+
+    // returns true when the channel expired during this update
+    fn update(&mut self, cycles: u64) -> bool {
+        if !self.running { return false; }
+        let elapsed = self.elapsed(cycles);
+        if elapsed < self.counter as u64 {
+            self.counter -= elapsed as DoubleWord;
+            return false;
+        }
+
+        self.expired = true;
+        if self.mode == Timer::PERIODIC && self.period > 0 {
+            let overshoot = (elapsed - self.counter as u64) % self.period as u64;
+            self.counter = self.period - overshoot as DoubleWord;
+        } else {
+            self.counter = 0;
+            self.running = false;
+        }
+        true
+    }
+}
+
+pub struct Timer {
+    instruction_buffer: VecDeque<Byte>,
+    response_buffer: VecDeque<Byte>,
+
+    uuid: String,
+    name: String,
+
+    channels: Vec<TimerChannel>,
+    cycles: u64,
+}
+
+impl Timer {
+    pub fn new(name: &str, uuid: &str, channels: usize) -> Self {
+        Timer {
+            instruction_buffer: VecDeque::new(),
+            response_buffer: VecDeque::new(),
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            channels: (0..channels).map(|_| TimerChannel::new()).collect(),
+            cycles: 0,
+        }
+    }
+
+    pub const ONE_SHOT: Byte = 0x00;
+    pub const PERIODIC: Byte = 0x01;
+
+    pub const CYCLES: Byte = 0x00;
+    pub const MICROS: Byte = 0x01;
+
+    pub const OK: Byte = 0x00;
+}
+
+impl Timer {
+    // current counter value of a channel
+    pub fn counter(&self, channel: usize) -> Option<DoubleWord> {
+        self.channels.get(channel).map(|c| c.counter)
+    }
+
+    pub fn is_expired(&self, channel: usize) -> Option<bool> {
+        self.channels.get(channel).map(|c| c.expired)
+    }
+
+    // updates all running channels, returns true when any of them expired
+    pub fn update(&mut self, cycles: u64) -> bool {
+        let mut fired = false;
+        for c in self.channels.iter_mut() {
+            fired |= c.update(cycles);
+        }
+        fired
+    }
+
+    pub fn stack_trace(&self) -> String {
+        let mut x = format!("-----------------------\n\
+        pending:     {}\n", self.instruction_buffer.len());
+        for (i, c) in self.channels.iter().enumerate() {
+            x += format!("channel {}:   mode {:#04X} unit {:#04X} period {:0>8X} counter {:0>8X} running {} expired {}\n",
+                         i, c.mode, c.unit, c.period, c.counter, c.running, c.expired).as_str();
+        }
+        x
+    }
+
+    fn queue_to_buffer(&mut self, data: Vec<Byte>) {
+        self.instruction_buffer.extend(data);
+    }
+
+    // an instruction is only executed once all of its operands arrived over the bus
+    fn instruction_ready(&self) -> bool {
+        let x = self.instruction_buffer.front();
+        if x.is_none() { return false; }
+        self.instruction_buffer.len() > TimerAssembly::operand_size(*x.unwrap())
+    }
+
+    fn fetch_instruction_byte(&mut self) -> Result<Byte, Byte> {
+        let x = self.instruction_buffer.pop_front();
+        if x.is_none() { return Err(UCode::INVALID_BUFFER_ACCESS); }
+        Ok(x.unwrap())
+    }
+    fn fetch_instruction_double_word(&mut self) -> Result<DoubleWord, Byte> {
+        let mut b = [0x0; 4];
+        for i in b.iter_mut() {
+            let x = self.fetch_instruction_byte();
+            if x.is_err() { return Err(x.err().unwrap()); }
+            *i = x.unwrap();
+        }
+        Ok(combine_to_double_word(combine_to_word(b[0], b[1]), combine_to_word(b[2], b[3])))
+    }
+
+    fn fetch_channel(&mut self) -> Result<usize, Byte> {
+        let x = self.fetch_instruction_byte();
+        if x.is_err() { return Err(x.err().unwrap()); }
+        let channel = x.unwrap() as usize;
+        if channel >= self.channels.len() { return Err(UCode::INVALID_TIMER_CHANNEL); }
+        Ok(channel)
+    }
+}
+
+impl Timer {
+    fn execute(&mut self) -> Result<(), Byte> {
+        let opcode = self.fetch_instruction_byte();
+        if opcode.is_err() { return Err(opcode.err().unwrap()); }
+
+        match opcode.unwrap() {
+            TimerAssembly::HLT => Ok(()),
+            TimerAssembly::CFG => {
+                let channel = self.fetch_channel();
+                if channel.is_err() { return Err(channel.err().unwrap()); }
+                let mode = self.fetch_instruction_byte();
+                if mode.is_err() { return Err(mode.err().unwrap()); }
+                let unit = self.fetch_instruction_byte();
+                if unit.is_err() { return Err(unit.err().unwrap()); }
+                let period = self.fetch_instruction_double_word();
+                if period.is_err() { return Err(period.err().unwrap()); }
+
+                let (mode, unit) = (mode.unwrap(), unit.unwrap());
+                if mode > Timer::PERIODIC || unit > Timer::MICROS { return Err(UCode::INVALID_TIMER_MODE); }
+
+                let c = &mut self.channels[channel.unwrap()];
+                c.mode = mode;
+                c.unit = unit;
+                c.period = period.unwrap();
+                c.counter = c.period;
+                c.running = false;
+                c.expired = false;
+                self.response_buffer.push_back(Timer::OK);
+                Ok(())
+            }
+            TimerAssembly::STR => {
+                let channel = self.fetch_channel();
+                if channel.is_err() { return Err(channel.err().unwrap()); }
+                let c = &mut self.channels[channel.unwrap()];
+                c.counter = c.period;
+                c.running = true;
+                c.expired = false;
+                c.last_cycle = self.cycles;
+                c.last_instant = Instant::now();
+                self.response_buffer.push_back(Timer::OK);
+                Ok(())
+            }
+            TimerAssembly::STP => {
+                let channel = self.fetch_channel();
+                if channel.is_err() { return Err(channel.err().unwrap()); }
+                self.channels[channel.unwrap()].running = false;
+                self.response_buffer.push_back(Timer::OK);
+                Ok(())
+            }
+            TimerAssembly::ACK => {
+                let channel = self.fetch_channel();
+                if channel.is_err() { return Err(channel.err().unwrap()); }
+                self.channels[channel.unwrap()].expired = false;
+                self.response_buffer.push_back(Timer::OK);
+                Ok(())
+            }
+            TimerAssembly::RDC => {
+                let channel = self.fetch_channel();
+                if channel.is_err() { return Err(channel.err().unwrap()); }
+                let c = &self.channels[channel.unwrap()];
+                let (counter, expired) = (c.counter, c.expired);
+                self.response_buffer.extend([
+                    Timer::OK,
+                    counter.significant_word().significant_byte(),
+                    counter.significant_word().insignificant_byte(),
+                    counter.insignificant_word().significant_byte(),
+                    counter.insignificant_word().insignificant_byte(),
+                    expired as Byte,
+                ]);
+                Ok(())
+            }
+            _ => Ok(())
+        }
+    }
+}
+
+impl BusDevice for Timer {
+    fn uuid(&self) -> String {
+        self.uuid.to_string()
+    }
+
+    fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn class(&self) -> Byte {
+        DeviceClass::TIMER
+    }
+
+    fn capabilities(&self) -> Byte {
+        DeviceClass::CAP_RESPONDS | DeviceClass::CAP_INTERRUPTS
+    }
+
+    fn on_write(&mut self, byte: Byte) {
+        self.queue_to_buffer(vec![byte]);
+        while self.instruction_ready() {
+            let rest = self.instruction_buffer.len() - 1 - TimerAssembly::operand_size(*self.instruction_buffer.front().unwrap());
+            let res = self.execute();
+            // hooks run with the bus locked, a rejected command is dropped whole and answered with its uCode
+            if res.is_err() {
+                while self.instruction_buffer.len() > rest { self.instruction_buffer.pop_front(); }
+                self.response_buffer.push_back(res.err().unwrap());
+            }
+        }
+    }
+
+    fn on_read(&mut self) -> Option<Byte> {
+        self.response_buffer.pop_front()
+    }
+
+    fn tick(&mut self, cycles: u64) -> bool {
+        self.cycles = cycles;
+        self.update(cycles)
+    }
+
+    fn reset(&mut self) {
+        self.instruction_buffer.clear();
+        self.response_buffer.clear();
+        self.channels = (0..self.channels.len()).map(|_| TimerChannel::new()).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::lib::bus::bus_device::BusDevice;
+    use crate::lib::ucode::timer_assembly::TimerAssembly;
+    use crate::lib::ucode::ucode::UCode;
+
+    use super::Timer;
+
+    fn send(t: &mut Timer, bytes: &[u8]) -> Vec<u8> {
+        for b in bytes { t.on_write(*b); }
+        std::iter::from_fn(|| t.on_read()).collect()
+    }
+
+    fn configure(t: &mut Timer, channel: u8, mode: u8, unit: u8, period: u32) {
+        let p = period.to_be_bytes();
+        assert_eq!(send(t, &[TimerAssembly::CFG, channel, mode, unit, p[0], p[1], p[2], p[3]]), vec![Timer::OK]);
+        assert_eq!(send(t, &[TimerAssembly::STR, channel]), vec![Timer::OK]);
+    }
+
+    #[test]
+    fn one_shot_fires_once() {
+        let mut t = Timer::new("timer", "vpit-0000-0000-0000", 2);
+        configure(&mut t, 0, Timer::ONE_SHOT, Timer::CYCLES, 10);
+        assert!(!t.tick(9));
+        assert_eq!(t.counter(0), Some(1));
+        assert!(t.tick(10));
+        assert!(!t.tick(30));
+        assert_eq!(send(&mut t, &[TimerAssembly::RDC, 0]), vec![Timer::OK, 0, 0, 0, 0, 1]);
+        assert_eq!(send(&mut t, &[TimerAssembly::ACK, 0]), vec![Timer::OK]);
+        assert_eq!(t.is_expired(0), Some(false));
+    }
+
+    #[test]
+    fn periodic_reloads_and_keeps_the_phase() {
+        let mut t = Timer::new("timer", "vpit-0000-0000-0000", 1);
+        configure(&mut t, 0, Timer::PERIODIC, Timer::CYCLES, 4);
+        assert!(t.tick(4));
+        assert!(!t.tick(6));
+        assert!(t.tick(8));
+        // a late update fires once and keeps the overshoot
+        assert!(t.tick(13));
+        assert_eq!(t.counter(0), Some(3));
+        assert!(!t.tick(15));
+        assert!(t.tick(16));
+        assert_eq!(send(&mut t, &[TimerAssembly::STP, 0]), vec![Timer::OK]);
+        assert!(!t.tick(100));
+    }
+
+    #[test]
+    fn microseconds_follow_the_host_clock() {
+        let mut t = Timer::new("timer", "vpit-0000-0000-0000", 1);
+        configure(&mut t, 0, Timer::ONE_SHOT, Timer::MICROS, 2_000);
+        // cycles do not count towards a microsecond channel
+        assert!(!t.tick(1_000_000));
+        thread::sleep(Duration::from_millis(3));
+        assert!(t.tick(1_000_001));
+    }
+
+    #[test]
+    fn rejected_commands_reply_with_their_ucode() {
+        let mut t = Timer::new("timer", "vpit-0000-0000-0000", 1);
+        assert_eq!(send(&mut t, &[TimerAssembly::STR, 1]), vec![UCode::INVALID_TIMER_CHANNEL]);
+        assert_eq!(send(&mut t, &[TimerAssembly::RDC, 7]), vec![UCode::INVALID_TIMER_CHANNEL]);
+        assert_eq!(send(&mut t, &[TimerAssembly::CFG, 0, 0x2, Timer::CYCLES, 0, 0, 0, 1]), vec![UCode::INVALID_TIMER_MODE]);
+        // the rejected command was dropped whole, the next one is read from its opcode
+        assert_eq!(send(&mut t, &[TimerAssembly::HLT, TimerAssembly::ACK, 0]), vec![Timer::OK]);
+        t.reset();
+        assert_eq!(t.counter(0), Some(0));
+    }
+}
@@ -0,0 +1,42 @@
+pub struct APUAssembly {}
+
+impl APUAssembly {
+    // do nothing instruction
+    pub const HLT: u8 = 0x00;
+
+    // set channel waveform (0x00 = square, 0x01 = noise)
+    // WAV $channel $waveform
+    pub const WAV: u8 = 0xa0;
+    // set channel frequency in Hz
+    // FRQ $channel $0xFFFF'FFFF_FFFF'FFFF
+    pub const FRQ: u8 = 0xa1;
+    // set channel volume
+    // VOL $channel $VV
+    pub const VOL: u8 = 0xa2;
+    // set channel envelope, attack / decay / release in units of 10 ms, sustain as level
+    // ENV $channel $AA $DD $SS $RR
+    pub const ENV: u8 = 0xa3;
+    // gate channel on (start attack)
+    // KON $channel
+    pub const KON: u8 = 0xa4;
+    // gate channel off (start release)
+    // KOF $channel
+    pub const KOF: u8 = 0xa5;
+
+    // push an unsigned 8 bit sample to the pcm fifo
+    // SMP $SS
+    pub const SMP: u8 = 0xb0;
+    // drop every queued pcm sample
+    pub const CLR: u8 = 0xb1;
+
+    // number of operand bytes following the opcode
+    pub fn operand_size(opcode: u8) -> usize {
+        match opcode {
+            APUAssembly::ENV => 5,
+            APUAssembly::FRQ => 3,
+            APUAssembly::WAV | APUAssembly::VOL => 2,
+            APUAssembly::KON | APUAssembly::KOF | APUAssembly::SMP => 1,
+            _ => 0
+        }
+    }
+}
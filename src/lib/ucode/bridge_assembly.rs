@@ -0,0 +1,20 @@
+pub struct BridgeAssembly {}
+
+// commands understood by a bus bridge at its address on the parent segment
+//
+// devices behind the bridge are reached with wide addresses (see Bus::write_wide),
+// their interrupts are raised on the parent segment with the bridge as source
+
+impl BridgeAssembly {
+    // do nothing instruction
+    pub const HLT: u8 = 0x00;
+
+    // drain the addresses of the devices behind the bridge that raised an interrupt
+    // SRC
+    // => $count ($address)*
+    pub const SRC: u8 = 0xa0;
+    // number of devices on the segment behind the bridge
+    // CNT
+    // => $count
+    pub const CNT: u8 = 0xa1;
+}
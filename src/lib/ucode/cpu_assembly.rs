@@ -5,6 +5,11 @@ impl CPUAssembly {
     pub const HLT: u8 = 0x00;
     pub const STK: u8 = 0x01;
 
+    // set interrupt disable
+    pub const SEI: u8 = 0x08;
+    // clear interrupt disable
+    pub const CLI: u8 = 0x09;
+
     // load a
     pub const LDA: u8 = 0x40;
     // load x
@@ -52,6 +57,49 @@ impl CPUAssembly {
     // pull program counter from stack
     pub const PLP: u8 = 0x79;
 
+    // return from interrupt (pull program counter, clear interrupt disable)
+    pub const RTI: u8 = 0x7a;
+
+    // io on this bus segment, raises UCode::DEVICE_NOT_FOUND when nothing is plugged in at the device address
+    // write low byte of a to device
+    pub const OUT: u8 = 0x80;
+    // write a to device
+    pub const OTW: u8 = 0x81;
+    // read byte from device to a (waits for the device)
+    pub const INP: u8 = 0x82;
+    // read word from device to a (waits for the device)
+    pub const INW: u8 = 0x83;
+    // write memory block to device
+    pub const OTB: u8 = 0x84;
+    // read memory block from device (waits for the device)
+    pub const INB: u8 = 0x85;
+    // start dma transfer from memory to a device on another bus segment
+    // DOL $channel $0xAAAA'AAAA_AAAA'AAAA_AAAA'AAAA_AAAA'AAAA (device) $0xAAAA'AAAA_AAAA'AAAA_AAAA'AAAA_AAAA'AAAA (source) $0xLLLL'LLLL_LLLL'LLLL (length)
+    pub const DOL: u8 = 0x86;
+    // start dma transfer from a device on another bus segment to memory
+    pub const DIL: u8 = 0x87;
+
+    // start dma transfer from memory to device
+    // DMO $channel $device $0xAAAA'AAAA_AAAA'AAAA_AAAA'AAAA_AAAA'AAAA (source) $0xLLLL'LLLL_LLLL'LLLL (length)
+    pub const DMO: u8 = 0x88;
+    // start dma transfer from device to memory
+    // DMI $channel $device $0xAAAA'AAAA_AAAA'AAAA_AAAA'AAAA_AAAA'AAAA (destination) $0xLLLL'LLLL_LLLL'LLLL (length)
+    pub const DMI: u8 = 0x89;
+    // load bytes left on dma channel to a, zero flag set once it finished
+    // DMS $channel
+    pub const DMS: u8 = 0x8a;
+
+    // io on other bus segments, the device is a wide address (see Bus::LOCAL)
+    // write low byte of a to device
+    // OTL $0xAAAA'AAAA_AAAA'AAAA_AAAA'AAAA_AAAA'AAAA
+    pub const OTL: u8 = 0x8c;
+    // read byte from device to a (waits for the device)
+    pub const INL: u8 = 0x8d;
+    // write memory block to device
+    pub const OBL: u8 = 0x8e;
+    // read memory block from device (waits for the device)
+    pub const IBL: u8 = 0x8f;
+
     // compare to a
     pub const CMP: u8 = 0xa0;
     // compare to x
@@ -85,4 +133,88 @@ impl CPUAssembly {
     pub const INX: u8 = 0xbb;
     // inc y
     pub const INY: u8 = 0xbc;
-}
\ No newline at end of file
+
+    // byte sizes of the operands following the opcode, in order
+    pub fn operands(opcode: u8) -> &'static [usize] {
+        match opcode {
+            CPUAssembly::LDA | CPUAssembly::LDX | CPUAssembly::LDY => &[2],
+            CPUAssembly::CMP | CPUAssembly::CMX | CPUAssembly::CMY => &[2],
+            CPUAssembly::STA | CPUAssembly::STX | CPUAssembly::STY => &[4],
+            CPUAssembly::BEQ | CPUAssembly::BNE | CPUAssembly::JMP => &[4],
+            CPUAssembly::OUT | CPUAssembly::OTW | CPUAssembly::INP | CPUAssembly::INW => &[1],
+            CPUAssembly::OTL | CPUAssembly::INL => &[4],
+            CPUAssembly::OTB | CPUAssembly::INB => &[1, 4, 2],
+            CPUAssembly::OBL | CPUAssembly::IBL => &[4, 4, 2],
+            CPUAssembly::DMO | CPUAssembly::DMI => &[1, 1, 4, 2],
+            CPUAssembly::DOL | CPUAssembly::DIL => &[1, 4, 4, 2],
+            CPUAssembly::DMS => &[1],
+            _ => &[]
+        }
+    }
+
+    // number of operand bytes following the opcode
+    pub fn operand_size(opcode: u8) -> usize {
+        CPUAssembly::operands(opcode).iter().sum()
+    }
+
+    pub fn mnemonic(opcode: u8) -> &'static str {
+        match opcode {
+            CPUAssembly::HLT => "HLT",
+            CPUAssembly::STK => "STK",
+            CPUAssembly::SEI => "SEI",
+            CPUAssembly::CLI => "CLI",
+            CPUAssembly::LDA => "LDA",
+            CPUAssembly::LDX => "LDX",
+            CPUAssembly::LDY => "LDY",
+            CPUAssembly::TAX => "TAX",
+            CPUAssembly::TAY => "TAY",
+            CPUAssembly::TXA => "TXA",
+            CPUAssembly::TXY => "TXY",
+            CPUAssembly::TYA => "TYA",
+            CPUAssembly::TYX => "TYX",
+            CPUAssembly::STA => "STA",
+            CPUAssembly::STX => "STX",
+            CPUAssembly::STY => "STY",
+            CPUAssembly::PSA => "PSA",
+            CPUAssembly::PSX => "PSX",
+            CPUAssembly::PSY => "PSY",
+            CPUAssembly::PSP => "PSP",
+            CPUAssembly::PLA => "PLA",
+            CPUAssembly::PLX => "PLX",
+            CPUAssembly::PLY => "PLY",
+            CPUAssembly::PLP => "PLP",
+            CPUAssembly::RTI => "RTI",
+            CPUAssembly::OUT => "OUT",
+            CPUAssembly::OTW => "OTW",
+            CPUAssembly::INP => "INP",
+            CPUAssembly::INW => "INW",
+            CPUAssembly::OTB => "OTB",
+            CPUAssembly::INB => "INB",
+            CPUAssembly::DMO => "DMO",
+            CPUAssembly::DMI => "DMI",
+            CPUAssembly::DOL => "DOL",
+            CPUAssembly::DIL => "DIL",
+            CPUAssembly::DMS => "DMS",
+            CPUAssembly::OTL => "OTL",
+            CPUAssembly::INL => "INL",
+            CPUAssembly::OBL => "OBL",
+            CPUAssembly::IBL => "IBL",
+            CPUAssembly::CMP => "CMP",
+            CPUAssembly::CMX => "CMX",
+            CPUAssembly::CMY => "CMY",
+            CPUAssembly::CAX => "CAX",
+            CPUAssembly::CAY => "CAY",
+            CPUAssembly::CXY => "CXY",
+            CPUAssembly::BEQ => "BEQ",
+            CPUAssembly::BNE => "BNE",
+            CPUAssembly::JMP => "JMP",
+            CPUAssembly::DEC => "DEC",
+            CPUAssembly::DEX => "DEX",
+            CPUAssembly::DEY => "DEY",
+            CPUAssembly::INC => "INC",
+            CPUAssembly::INX => "INX",
+            CPUAssembly::INY => "INY",
+            _ => "???"
+        }
+    }
+}
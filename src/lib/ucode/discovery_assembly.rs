@@ -0,0 +1,22 @@
+pub struct DiscoveryAssembly {}
+
+// commands understood by the bus itself at Bus::DISCOVERY
+//
+// device entry:
+// $address $class $capabilities $uuid_length $uuid... $name_length $name...
+
+impl DiscoveryAssembly {
+    // list every device
+    // LST
+    // => $count (entry)*
+    pub const LST: u8 = 0xa0;
+    // list every device of a class
+    // FND $class
+    // => $count (entry)*
+    pub const FND: u8 = 0xa1;
+    // drain pending plug / unplug events (Bus::PLUGGED, Bus::UNPLUGGED), an interrupt
+    // with Bus::DISCOVERY as source is raised whenever one is queued
+    // EVT
+    // => $count ($event $address)*
+    pub const EVT: u8 = 0xa2;
+}
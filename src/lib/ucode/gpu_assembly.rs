@@ -5,6 +5,9 @@ impl GPUAssembly {
     pub const HLT: u8 = 0x00;
     // print stack trace
     pub const STK: u8 = 0x01;
+    // report capabilities
+    // => $monitors ($0xWWWW'WWWW_WWWW'WWWW $0xHHHH'HHHH_HHHH'HHHH)*
+    pub const CAP: u8 = 0x02;
 
     // bind vertex buffer
     pub const BVB: u8 = 0xa0;
@@ -16,4 +19,26 @@ impl GPUAssembly {
 
     // issue draw
     pub const DRW: u8 = 0xaf;
-}
\ No newline at end of file
+
+    // number of operand bytes following the opcode
+    pub fn operand_size(opcode: u8) -> usize {
+        match opcode {
+            GPUAssembly::BVB => 2,
+            GPUAssembly::VRX => 11,
+            _ => 0
+        }
+    }
+
+    pub fn mnemonic(opcode: u8) -> &'static str {
+        match opcode {
+            GPUAssembly::HLT => "HLT",
+            GPUAssembly::STK => "STK",
+            GPUAssembly::CAP => "CAP",
+            GPUAssembly::BVB => "BVB",
+            GPUAssembly::UVB => "UVB",
+            GPUAssembly::VRX => "VRX",
+            GPUAssembly::DRW => "DRW",
+            _ => "???"
+        }
+    }
+}
@@ -0,0 +1,31 @@
+pub struct HostFSAssembly {}
+
+impl HostFSAssembly {
+    // do nothing instruction
+    pub const HLT: u8 = 0x00;
+
+    // open a file relative to the hostfs root
+    // OPN $mode $0xLLLL'LLLL_LLLL'LLLL $path...
+    // => $status $handle
+    pub const OPN: u8 = 0xa0;
+    // read up to length bytes
+    // RED $handle $0xLLLL'LLLL_LLLL'LLLL
+    // => $status $0xLLLL'LLLL_LLLL'LLLL $data...
+    pub const RED: u8 = 0xa1;
+    // write length bytes
+    // WRT $handle $0xLLLL'LLLL_LLLL'LLLL $data...
+    // => $status $0xLLLL'LLLL_LLLL'LLLL
+    pub const WRT: u8 = 0xa2;
+    // close a handle
+    // CLS $handle
+    // => $status
+    pub const CLS: u8 = 0xa3;
+    // list a directory, kind is HostFS::FILE, DIRECTORY or LINK (links are not followed)
+    // LST $0xLLLL'LLLL_LLLL'LLLL $path...
+    // => $status $0xCCCC'CCCC_CCCC'CCCC ($kind $length $name...)*
+    pub const LST: u8 = 0xa4;
+    // stat a path
+    // STA $0xLLLL'LLLL_LLLL'LLLL $path...
+    // => $status $kind $0xSSSS'SSSS_SSSS'SSSS_SSSS'SSSS_SSSS'SSSS (size) $0xMMMM'MMMM_MMMM'MMMM_MMMM'MMMM_MMMM'MMMM (modified, unix time)
+    pub const STA: u8 = 0xa5;
+}
@@ -1,3 +1,10 @@
+pub mod apu_assembly;
+pub mod bridge_assembly;
 pub mod cpu_assembly;
+pub mod discovery_assembly;
 pub mod gpu_assembly;
-pub mod ucode;
\ No newline at end of file
+pub mod hostfs_assembly;
+pub mod nic_assembly;
+pub mod rtc_assembly;
+pub mod timer_assembly;
+pub mod ucode;
@@ -0,0 +1,22 @@
+pub struct NICAssembly {}
+
+impl NICAssembly {
+    // do nothing instruction
+    pub const HLT: u8 = 0x00;
+
+    // queue a packet on the tx ring, packets over NIC::MTU or beyond a full ring are dropped
+    // SND $0xLLLL'LLLL_LLLL'LLLL $data...
+    // => $status
+    pub const SND: u8 = 0xa0;
+    // pop a packet from the rx ring, length 0 when the ring is empty
+    // RCV
+    // => $0xLLLL'LLLL_LLLL'LLLL $data...
+    pub const RCV: u8 = 0xa1;
+    // ring status
+    // STA
+    // => $rx_queued $tx_queued $0xDDDD'DDDD_DDDD'DDDD (dropped packets)
+    pub const STA: u8 = 0xa2;
+    // enable / disable the receive interrupt
+    // IEN $0x00 | $0x01
+    pub const IEN: u8 = 0xa3;
+}
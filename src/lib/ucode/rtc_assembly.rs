@@ -0,0 +1,28 @@
+pub struct RTCAssembly {}
+
+impl RTCAssembly {
+    // do nothing instruction
+    pub const HLT: u8 = 0x00;
+
+    // set alarm, time in the rtc date layout (weekday is ignored)
+    // SAL $slot $0xYYYY'YYYY_YYYY'YYYY $MM $DD $hh $mm $ss $WW
+    // => $status
+    pub const SAL: u8 = 0xa0;
+    // clear alarm
+    // CAL $slot
+    // => $status
+    pub const CAL: u8 = 0xa1;
+    // read the current date
+    // RDT
+    // => $0xYYYY'YYYY_YYYY'YYYY $MM $DD $hh $mm $ss $WW
+    pub const RDT: u8 = 0xa2;
+
+    // number of operand bytes following the opcode
+    pub fn operand_size(opcode: u8) -> usize {
+        match opcode {
+            RTCAssembly::SAL => 9,
+            RTCAssembly::CAL => 1,
+            _ => 0
+        }
+    }
+}
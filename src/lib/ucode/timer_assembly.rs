@@ -0,0 +1,36 @@
+pub struct TimerAssembly {}
+
+impl TimerAssembly {
+    // do nothing instruction
+    pub const HLT: u8 = 0x00;
+
+    // configure channel
+    // CFG $channel $mode $unit $0xPPPP'PPPP_PPPP'PPPP_PPPP'PPPP_PPPP'PPPP (period)
+    // => $status
+    pub const CFG: u8 = 0xa0;
+    // start channel, counter is reloaded from the period
+    // STR $channel
+    // => $status
+    pub const STR: u8 = 0xa1;
+    // stop channel, counter keeps its value
+    // STP $channel
+    // => $status
+    pub const STP: u8 = 0xa2;
+    // acknowledge channel expiry
+    // ACK $channel
+    // => $status
+    pub const ACK: u8 = 0xa3;
+    // read channel counter and expiry flag
+    // RDC $channel
+    // => $status $0xCCCC'CCCC_CCCC'CCCC_CCCC'CCCC_CCCC'CCCC $expired
+    pub const RDC: u8 = 0xa4;
+
+    // number of operand bytes following the opcode
+    pub fn operand_size(opcode: u8) -> usize {
+        match opcode {
+            TimerAssembly::CFG => 7,
+            TimerAssembly::STR | TimerAssembly::STP | TimerAssembly::ACK | TimerAssembly::RDC => 1,
+            _ => 0
+        }
+    }
+}
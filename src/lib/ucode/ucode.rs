@@ -15,16 +15,69 @@ impl UCode {
     pub const MONITOR_NOT_FOUND: Byte = 0xb0;
     pub const PIXEL_OUT_OF_BOUNDS: Byte = 0xb1;
 
+    // timer uCode
+    pub const INVALID_TIMER_CHANNEL: Byte = 0xb4;
+    pub const INVALID_TIMER_MODE: Byte = 0xb5;
+
+    // rtc uCode
+    pub const INVALID_ALARM_SLOT: Byte = 0xb6;
+    pub const INVALID_DATE: Byte = 0xb7;
+
+    // apu uCode
+    pub const INVALID_AUDIO_CHANNEL: Byte = 0xb8;
+    pub const INVALID_WAVEFORM: Byte = 0xb9;
+    pub const AUDIO_OUTPUT_FAILURE: Byte = 0xba;
+
+    // dma uCode
+    pub const INVALID_DMA_CHANNEL: Byte = 0xbc;
+    pub const DMA_CHANNEL_BUSY: Byte = 0xbd;
+
+    // hostfs uCode
+    pub const HOSTFS_INVALID_PATH: Byte = 0xc0;
+    pub const HOSTFS_PATH_ESCAPES_ROOT: Byte = 0xc1;
+    pub const HOSTFS_NOT_FOUND: Byte = 0xc2;
+    pub const HOSTFS_PERMISSION_DENIED: Byte = 0xc3;
+    pub const HOSTFS_INVALID_HANDLE: Byte = 0xc4;
+    pub const HOSTFS_TOO_MANY_HANDLES: Byte = 0xc5;
+    pub const HOSTFS_INVALID_MODE: Byte = 0xc6;
+    pub const HOSTFS_IO_FAILURE: Byte = 0xc7;
+
+    // nic uCode
+    pub const NIC_LINK_FAILURE: Byte = 0xc8;
+    pub const NIC_PACKET_TOO_LARGE: Byte = 0xc9;
+    pub const NIC_RING_FULL: Byte = 0xca;
+
     // memory uCode
     pub const GENERIC_MEMORY_FAILURE: Byte = 0xd0;
     pub const INVALID_MEMORY_READ: Byte = 0xd1;
     pub const INVALID_MEMORY_WRITE: Byte = 0xd2;
     pub const MEMORY_ALREADY_LOCKED: Byte = 0xd3;
     pub const MEMORY_ALREADY_UNLOCKED: Byte = 0xd4;
+    pub const INVALID_MMIO_REGION: Byte = 0xd5;
+    pub const MMIO_REGION_OVERLAP: Byte = 0xd6;
 
     // Buffer uCode
     pub const INVALID_BUFFER_ACCESS: Byte = 0xe0;
 
+    // bus uCode
+    pub const BUS_FULL: Byte = 0xe8;
+    pub const DEVICE_NOT_FOUND: Byte = 0xe9;
+    pub const BUS_TRACE_FAILURE: Byte = 0xea;
+
+    // debugger uCode
+    pub const DEBUG_TARGET_LOST: Byte = 0xf0;
+    pub const BREAKPOINT_NOT_FOUND: Byte = 0xf1;
+    pub const GDB_CONNECTION_FAILURE: Byte = 0xf2;
+    pub const WATCHPOINT_NOT_FOUND: Byte = 0xf3;
+    pub const EXEC_TRACE_FAILURE: Byte = 0xf4;
+    pub const HISTORY_EMPTY: Byte = 0xf5;
+    pub const PROFILER_NOT_RUNNING: Byte = 0xf6;
+    pub const COVERAGE_NOT_RUNNING: Byte = 0xf7;
+    pub const INVALID_SOURCE_MAP: Byte = 0xf8;
+    pub const INVALID_SYMBOL_FILE: Byte = 0xf9;
+    pub const SYMBOL_NOT_FOUND: Byte = 0xfa;
+    pub const GDB_PACKET_TOO_LARGE: Byte = 0xfb;
+
     //
     pub const UNKNOWN_EXCEPTION: Byte = 0xfe;
     // generic uCode
@@ -1,46 +1,229 @@
+use std::fs::File;
 use std::path::Display;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use sdl2::keyboard::Keycode::Mute;
 
-use crate::lib::bus::bus::Bus;
-use crate::lib::cpu::cpu::CPU;
-use crate::lib::gpu::gpu::GPU;
-use crate::lib::gpu::monitor::Monitor;
-use crate::lib::mem::ram::RAM;
-
-pub mod lib;
+use virtual_machine::lib::audio::apu::APU;
+use virtual_machine::lib::audio::sink::AudioSink;
+use virtual_machine::lib::audio::wav::WavWriter;
+use virtual_machine::lib::bus::bus::Bus;
+use virtual_machine::lib::bus::timing::BusTiming;
+use virtual_machine::lib::bus::trace::BusTracer;
+use virtual_machine::lib::chip_util::BlockingLock;
+use virtual_machine::lib::cpu::program::ProgramBuilder;
+use virtual_machine::lib::debug::format::parse;
+use virtual_machine::lib::debug::gdb::GdbStub;
+use virtual_machine::lib::debug::history::History;
+use virtual_machine::lib::debug::repl;
+use virtual_machine::lib::debug::repl::format_stop;
+use virtual_machine::lib::debug::trace::{ExecutionTracer, TraceFormat};
+use virtual_machine::lib::gpu::framebuffer::Framebuffer;
+use virtual_machine::lib::gpu::gpu::GPU;
+use virtual_machine::lib::gpu::monitor::Monitor;
+use virtual_machine::lib::hostfs::hostfs::HostFS;
+use virtual_machine::lib::machine::machine::Machine;
+use virtual_machine::lib::net::link::Link;
+use virtual_machine::lib::net::nic::NIC;
+use virtual_machine::lib::rtc::rtc::{RTC, RTCClock};
+use virtual_machine::lib::timer::timer::Timer;
+use virtual_machine::lib::ucode::cpu_assembly::CPUAssembly;
+use virtual_machine::lib::ucode::gpu_assembly::GPUAssembly;
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let headless = args.iter().any(|a| a == "--headless");
+    let hostfs_root = args.iter().position(|a| a == "--hostfs").and_then(|i| args.get(i + 1));
+    let pcap_path = args.iter().position(|a| a == "--pcap").and_then(|i| args.get(i + 1));
+    // --udp local,remote e.g. 127.0.0.1:4000,127.0.0.1:4001
+    let udp_peers = args.iter().position(|a| a == "--udp").and_then(|i| args.get(i + 1));
+    let trace_path = args.iter().position(|a| a == "--trace").and_then(|i| args.get(i + 1));
+    let timing = args.iter().any(|a| a == "--timing");
+    let debug = args.iter().any(|a| a == "--debug");
+    let gdb_address = args.iter().position(|a| a == "--gdb").and_then(|i| args.get(i + 1));
+    let exec_trace_path = args.iter().position(|a| a == "--exec-trace").and_then(|i| args.get(i + 1));
+    let emit_path = args.iter().position(|a| a == "--emit").and_then(|i| args.get(i + 1));
+    let exec_trace_ranges: Vec<&String> = args.windows(2).filter(|w| w[0] == "--exec-trace-range").map(|w| &w[1]).collect();
+
     // 536870912 * 8 => 4 GB => 4096 MB
     // address range => 0x0000'0000 <-> 0x1FFF'FFFF
-    let mut ram = RAM::new(536_870_912);
-    let mut bus = Arc::new(Mutex::new(Bus::new()));
-    let mut bref1 = Arc::clone(&bus);
+    let mut machine = Machine::new(536_870_912);
+    let mut bus = machine.bus();
+
+    // registered before any other device, the program below addresses it directly
+    let mut gpu = GPU::new("vGPU - GACUM (Graphical Accelerated Compute Unit Magic)", "vgpu-acum-0000-0000");
+    let gpu_address = gpu.register(&bus).unwrap();
+
+    // draw one vertex: the gpu command stream lives on the heap and is handed to the dma controller
+    let gpu_stream = [
+        GPUAssembly::BVB, 0x0, 0x0,
+        // x, y, c, ax, ay, z index
+        GPUAssembly::VRX, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+        GPUAssembly::UVB,
+        GPUAssembly::DRW,
+    ];
+    let mut builder = ProgramBuilder::new(0x1000_0000);
+    // DMO $channel $gpu $0x0500'0000 $length
+    builder.source(file!(), line!()).label("main")
+        .op(CPUAssembly::DMO).byte(0x0).byte(gpu_address).double_word(0x0500_0000).word(gpu_stream.len() as u16);
+    // LDA $0xF00F, STA $0x0F00'0000 (top left pixel red through the memory mapped framebuffer)
+    builder.source(file!(), line!()).op(CPUAssembly::LDA).word(0xF00F);
+    builder.source(file!(), line!()).op(CPUAssembly::STA).double_word(Machine::FRAMEBUFFER_BASE);
+    // JMP $0x1000'0011 (idle)
+    builder.source(file!(), line!()).label("idle").op(CPUAssembly::JMP).address("idle");
+    let (program, mut symbols) = builder.build().unwrap();
+    symbols.add_data(0x0500_0000, gpu_stream.len() as u32, "gpu_stream");
+    // --emit <image> writes the program and <image>.sym for vmdbg instead of running it
+    if let Some(path) = emit_path {
+        if std::fs::write(path, &program).is_err() || symbols.save(&format!("{}.sym", path)).is_err() {
+            eprintln!("can't write {}", path);
+        }
+        return;
+    }
+    machine.load(0x0500_0000, &gpu_stream).unwrap();
+    machine.load(0x1000_0000, &program).unwrap();
+    machine.set_symbols(Some(symbols.clone()));
+    // --trace - prints the bus traffic, --trace <file> captures it
+    if let Some(path) = trace_path {
+        let out: Box<dyn std::io::Write + Send> = if path == "-" { Box::new(std::io::stdout()) } else {
+            let file = File::create(path);
+            if file.is_err() {
+                eprintln!("can't write {}: {}", path, file.err().unwrap());
+                return;
+            }
+            Box::new(file.unwrap())
+        };
+        let mut tracer = BusTracer::new();
+        tracer.set_live(Some(out));
+        bus.b_lock().set_tracer(Some(tracer));
+    }
+    // --exec-trace - prints every executed instruction, --exec-trace <file> logs them in the binary format
+    // (see vmdbg --print-trace), --exec-trace-range <start>:<end> limits it to the given addresses
+    if let Some(path) = exec_trace_path {
+        let mut tracer = if path == "-" {
+            ExecutionTracer::new(Box::new(std::io::stdout()), TraceFormat::Text)
+        } else {
+            let file = File::create(path);
+            if file.is_err() {
+                eprintln!("can't write {}: {}", path, file.err().unwrap());
+                return;
+            }
+            ExecutionTracer::new(Box::new(file.unwrap()), TraceFormat::Binary)
+        };
+        for r in exec_trace_ranges {
+            let bounds: Vec<Result<u32, String>> = r.split(':').map(parse).collect();
+            if bounds.len() != 2 || bounds.iter().any(|b| b.is_err()) || bounds[0] >= bounds[1] {
+                eprintln!("usage: --exec-trace-range <start>:<end>, start below end (e.g. 0x1000'0000:0x1000'0100), got {}", r);
+                return;
+            }
+            tracer.add_range(*bounds[0].as_ref().unwrap(), *bounds[1].as_ref().unwrap());
+        }
+        machine.set_tracer(Some(tracer));
+    }
+    // every transfer waits 2 cycles before moving 4 bytes per cycle
+    if timing { bus.b_lock().set_timing(Some(BusTiming::new(2, 4))); }
     let mut bref2 = Arc::clone(&bus);
+    let bref3 = Arc::clone(&bus);
+    let bref4 = Arc::clone(&bus);
 
     let mut m0 = Arc::new(Mutex::new(Monitor::new(20, 20)));
     let mut m1 = Arc::clone(&m0);
     let mut m2 = Arc::clone(&m0);
-    let mut cpu = CPU::new();
-
-    let mut gpu = GPU::new("vGPU - GACUM (Graphical Accelerated Compute Unit Magic)", "vgpu-acum-0000-0000");
 
     thread::spawn(move || {
         gpu.launch(&bref2, &mut [&mut m1])
     });
 
+    if !headless {
+        thread::spawn(move || {
+            Monitor::launch(&m2);
+        });
+    }
+
+    thread::sleep(Duration::new(0, 500_000));
+
+    let timer = Timer::new("vPIT - Programmable Interval Timer", "vpit-0000-0000-0000", 4);
+    bus.b_lock().attach(Box::new(timer)).unwrap();
+
+    let framebuffer = Framebuffer::new("vFB - GACUM Framebuffer", "vgpu-acum-0000-0001", Arc::clone(&m0));
+    let size = framebuffer.size();
+    let address = bus.b_lock().attach(Box::new(framebuffer)).unwrap();
+    machine.map(Machine::FRAMEBUFFER_BASE, size, address).unwrap();
+
+    let rtc = RTC::new("vRTC - Real Time Clock", "vrtc-0000-0000-0000", RTCClock::Host);
+    bus.b_lock().attach(Box::new(rtc)).unwrap();
+
+    let mut apu = APU::new("vAPU - Audio Processing Unit", "vapu-0000-0000-0000");
+    if headless {
+        // rendered against the bus clock, audio.wav only depends on the executed program
+        apu.set_output(WavWriter::new("audio.wav", APU::SAMPLE_RATE).ok());
+        bus.b_lock().attach(Box::new(apu)).unwrap();
+    } else {
+        thread::spawn(move || {
+            let sink = AudioSink::sdl(APU::SAMPLE_RATE);
+            apu.launch(&bref3, &mut sink.unwrap_or(AudioSink::Null))
+        });
+    }
+
+    if let Some(root) = hostfs_root {
+        let hostfs = HostFS::new("vHFS - Host Filesystem", "vhfs-0000-0000-0000", root);
+        if hostfs.is_err() { panic!("hostfs root {} not found", root); }
+        let mut hostfs = hostfs.unwrap();
+        thread::spawn(move || {
+            hostfs.launch(&bref4)
+        });
+    }
+
+    // expansion segment behind a bridge, its devices are reached with wide addresses (0xFFFF'BBDD)
+    let expansion = Arc::new(Mutex::new(Bus::new()));
+    bus.b_lock().bridge("vBRG - Expansion Bridge", "vbrg-0000-0000-0000", &expansion).unwrap();
+
+    let link = if let Some(udp_peers) = udp_peers {
+        let peers: Vec<&str> = udp_peers.split(',').collect();
+        if peers.len() != 2 {
+            eprintln!("usage: --udp local,remote");
+            return;
+        }
+        let l = Link::udp(peers[0], peers[1]);
+        if l.is_err() {
+            eprintln!("can't open udp link {} -> {}", peers[0], peers[1]);
+            return;
+        }
+        l.unwrap()
+    } else if let Some(pcap_path) = pcap_path {
+        Link::pcap(pcap_path).unwrap_or(Link::Disconnected)
+    } else {
+        Link::loopback()
+    };
+    let mut nic = NIC::new("vNIC - Network Interface", "vnic-0000-0000-0000", link);
     thread::spawn(move || {
-        m2.lock().unwrap().launch();
+        nic.launch(&expansion)
     });
 
-    thread::sleep(Duration::new(0, 500_000));
+    // --debug starts the machine halted with a debugger prompt on stdin
+    let debugger = if debug { Some(machine.debugger()) } else { None };
+    if let Some(d) = debugger.as_ref() { d.interrupt().unwrap(); }
+
+    // --gdb <host:port> serves the gdb remote protocol, the cpu halts whenever a client connects
+    if let Some(address) = gdb_address {
+        let mut stub = GdbStub::new(machine.debugger());
+        let address = address.to_string();
+        thread::spawn(move || {
+            let res = stub.listen(&address);
+            if res.is_err() { eprintln!("gdb stub on {} failed: {:X}", address, res.err().unwrap()); }
+        });
+    }
 
     let cpu_thread = thread::spawn(move || {
-        cpu.launch(&mut ram, &bref1)
+        machine.launch()
     });
 
+    if let Some(debugger) = debugger {
+        print!("{}", format_stop(&debugger.wait().unwrap(), Some(&symbols)));
+        debugger.record(History::CAPACITY).unwrap();
+        repl::run(&debugger, Some(symbols), &mut std::io::stdin().lock(), &mut std::io::stdout());
+    }
 
     while !cpu_thread.is_finished() {}
 }
\ No newline at end of file